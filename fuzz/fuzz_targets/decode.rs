@@ -0,0 +1,16 @@
+#![no_main]
+use std::fmt::Write;
+use libfuzzer_sys::fuzz_target;
+use riscv_vm::{Disasm32, Inst32};
+
+// Decodes and disassembles whatever starts at every halfword of the input
+fuzz_target!(|data: &[u8]| {
+    let mut text = String::new();
+    for at in (0..data.len().saturating_sub(1)).step_by(2) {
+        let Some((inst, _)) = Inst32::decode(&data[at..]) else { continue; };
+        let _ = (inst.opcode(), inst.rd(), inst.r1(), inst.r2(), inst.funct3(), inst.funct7(), inst.funct12());
+        let _ = (inst.imm_I(), inst.imm_S(), inst.imm_B(), inst.imm_U(), inst.imm_J());
+        text.clear();
+        write!(text, "{}", Disasm32(inst)).unwrap();
+    }
+});
@@ -0,0 +1,17 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use riscv_vm::{Builder, Machine};
+
+// Enough to get through a riscv-tests prologue, few enough to keep the fuzzer fast
+const MAX_STEPS: u64 = 4_000;
+
+// The input is a raw image on -msimple, like the .bin files the examples build.
+// An odd first byte boots it in S-mode under the built-in SBI, like -kernel.
+fuzz_target!(|data: &[u8]| {
+    let mut builder = Builder::new(Machine::Simple).load(0, data);
+    if data.first().is_some_and(|x| x & 1 != 0) {
+        builder = builder.kernel();
+    }
+    let mut vm = builder.build().unwrap();
+    vm.run_for(MAX_STEPS);
+});
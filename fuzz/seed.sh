@@ -0,0 +1,19 @@
+#!/bin/sh
+# Seeds the corpus from the example images and the riscv-tests binaries.
+# Build the examples first to get their main.bin and sum.bin in as well.
+set -e
+cd "$(dirname "$0")"
+mkdir -p corpus/decode corpus/step
+for bin in ../examples/*/*.bin; do
+    [ -f "$bin" ] || continue
+    name=$(basename "$(dirname "$bin")")-$(basename "$bin" .bin)
+    cp "$bin" "corpus/step/$name"
+    cp "$bin" "corpus/decode/$name"
+done
+# Their loadable segments make raw images too, the code is all PC relative
+for elf in ../tests/riscv-tests/isa/*-p-*; do
+    name=$(basename "$elf")
+    case "$name" in *.*) continue;; esac
+    llvm-objcopy -O binary "$elf" "corpus/step/$name"
+    llvm-objcopy -O binary --only-section=.text.init --only-section=.text "$elf" "corpus/decode/$name"
+done
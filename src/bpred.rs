@@ -0,0 +1,245 @@
+use std::{collections::HashMap, io::{self, Write}};
+
+use crate::{elf::Symbols, inst::Inst32, ops};
+
+// Direction predictor for conditional branches
+pub trait Predictor {
+    fn predict(&self, pc: u32) -> bool;
+    fn update(&mut self, pc: u32, taken: bool);
+}
+
+pub struct StaticNotTaken;
+impl Predictor for StaticNotTaken {
+    fn predict(&self, _: u32) -> bool {
+        false
+    }
+    fn update(&mut self, _: u32, _: bool) {}
+}
+
+// 2 bit saturating counters, 0-1 predict not taken and 2-3 predict taken
+#[inline]
+fn bump(counter: &mut u8, taken: bool) {
+    if taken {
+        *counter = (*counter + 1).min(3);
+    } else {
+        *counter = counter.saturating_sub(1);
+    }
+}
+
+pub struct Bimodal {
+    counters: Vec<u8>,
+}
+impl Bimodal {
+    pub fn new(bits: u32) -> Self {
+        Self { counters: vec![1; 1 << bits] }
+    }
+    fn index(&self, pc: u32) -> usize {
+        (pc >> 2) as usize & (self.counters.len()-1)
+    }
+}
+impl Predictor for Bimodal {
+    fn predict(&self, pc: u32) -> bool {
+        self.counters[self.index(pc)] >= 2
+    }
+    fn update(&mut self, pc: u32, taken: bool) {
+        let i = self.index(pc);
+        bump(&mut self.counters[i], taken);
+    }
+}
+
+pub struct Gshare {
+    counters: Vec<u8>,
+    history: u32,
+    history_bits: u32,
+}
+impl Gshare {
+    pub fn new(bits: u32, history_bits: u32) -> Self {
+        Self { counters: vec![1; 1 << bits], history: 0, history_bits }
+    }
+    fn index(&self, pc: u32) -> usize {
+        ((pc >> 2) ^ self.history) as usize & (self.counters.len()-1)
+    }
+}
+impl Predictor for Gshare {
+    fn predict(&self, pc: u32) -> bool {
+        self.counters[self.index(pc)] >= 2
+    }
+    fn update(&mut self, pc: u32, taken: bool) {
+        let i = self.index(pc);
+        bump(&mut self.counters[i], taken);
+        self.history = ((self.history << 1) | taken as u32) & ((1 << self.history_bits) - 1);
+    }
+}
+
+// Direct mapped branch target buffer for indirect jumps
+pub struct Btb {
+    entries: Vec<Option<(u32, u32)>>,
+}
+impl Btb {
+    pub fn new(entries: usize) -> Self {
+        Self { entries: vec![None; entries] }
+    }
+    fn predict(&self, pc: u32) -> Option<u32> {
+        match self.entries[(pc >> 2) as usize % self.entries.len()] {
+            Some((tag, target)) if tag == pc => Some(target),
+            _ => None,
+        }
+    }
+    fn update(&mut self, pc: u32, target: u32) {
+        let len = self.entries.len();
+        self.entries[(pc >> 2) as usize % len] = Some((pc, target));
+    }
+}
+
+// Return address stack, overflowing by dropping the oldest entry
+pub struct Ras {
+    stack: Vec<u32>,
+    depth: usize,
+}
+impl Ras {
+    pub fn new(depth: usize) -> Self {
+        Self { stack: Vec::with_capacity(depth), depth }
+    }
+    fn push(&mut self, addr: u32) {
+        if self.depth == 0 { return; }
+        if self.stack.len() == self.depth {
+            self.stack.remove(0);
+        }
+        self.stack.push(addr);
+    }
+    fn pop(&mut self) -> Option<u32> {
+        self.stack.pop()
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+struct Stats {
+    executed: u64,
+    correct: u64,
+}
+impl Stats {
+    fn record(&mut self, correct: bool) {
+        self.executed += 1;
+        self.correct += correct as u64;
+    }
+}
+#[derive(Clone, Copy)]
+enum Kind {
+    Branch,
+    Return,
+    Indirect,
+}
+const KIND_NAMES: [&str; 3] = ["branch", "return", "indirect"];
+
+pub struct BranchSim {
+    predictor: Box<dyn Predictor>,
+    btb: Btb,
+    ras: Ras,
+    totals: [Stats; 3],
+    sites: HashMap<u32, (Kind, Stats)>,
+}
+impl BranchSim {
+    // <static|bimodal[:bits]|gshare[:bits[:history]]>[,btb=<entries>][,ras=<depth>]
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut items = spec.split(',');
+        let model = items.next().unwrap_or("");
+        let mut args = model.split(':');
+        let kind = args.next().unwrap_or("");
+        let mut arg = |default: u32| -> Result<u32, String> {
+            match args.next() {
+                Some(v) => match v.parse() {
+                    Ok(v) if (1..=24).contains(&v) => Ok(v),
+                    Ok(v) => Err(format!("Table size of 2^{} entries is out of range", v)),
+                    Err(e) => Err(format!("Invalid table size `{}`: {}", v, e)),
+                },
+                None => Ok(default),
+            }
+        };
+        let predictor: Box<dyn Predictor> = match kind {
+            "static" => Box::new(StaticNotTaken),
+            "bimodal" => Box::new(Bimodal::new(arg(12)?)),
+            "gshare" => {
+                let bits = arg(12)?;
+                Box::new(Gshare::new(bits, arg(bits)?))
+            }
+            _ => return Err(format!("Unknown predictor `{}`", kind)),
+        };
+        let (mut btb, mut ras) = (512, 16);
+        for item in items {
+            let Some((key, value)) = item.split_once('=') else {
+                return Err(format!("Expected `key=value` but got `{}`", item));
+            };
+            let value: usize = value.parse().map_err(|e| format!("Invalid size `{}`: {}", value, e))?;
+            match key {
+                "btb" if value > 0 => btb = value,
+                "ras" => ras = value,
+                _ => return Err(format!("Invalid option `{}`", item)),
+            }
+        }
+        Ok(Self {
+            predictor,
+            btb: Btb::new(btb),
+            ras: Ras::new(ras),
+            totals: [Stats::default(); 3],
+            sites: HashMap::new(),
+        })
+    }
+    fn record(&mut self, pc: u32, kind: Kind, correct: bool) {
+        self.totals[kind as usize].record(correct);
+        self.sites.entry(pc).or_insert((kind, Stats::default())).1.record(correct);
+    }
+    // Returns whether the front end would have fetched down the wrong path
+    // `len` is the size of the instruction, which return addresses are pushed past
+    pub fn resolve(&mut self, pc: u32, inst: Inst32, len: u32, next: u32) -> bool {
+        match inst.opcode() {
+            ops::BRANCH_OP => {
+                let taken = next == pc.wrapping_add(inst.imm_B() as u32);
+                let correct = self.predictor.predict(pc) == taken;
+                self.predictor.update(pc, taken);
+                self.record(pc, Kind::Branch, correct);
+                !correct
+            }
+            ops::JUMP_OP => {
+                if ops::is_link(inst.rd()) {
+                    self.ras.push(pc.wrapping_add(len));
+                }
+                false
+            }
+            ops::JUMP_REG_OP => {
+                let (rd, r1) = (inst.rd(), inst.r1());
+                // Same call/return hints the profiler uses
+                let correct = if ops::is_link(r1) && (!ops::is_link(rd) || rd != r1) {
+                    let correct = self.ras.pop() == Some(next);
+                    self.record(pc, Kind::Return, correct);
+                    correct
+                } else {
+                    let correct = self.btb.predict(pc) == Some(next);
+                    self.btb.update(pc, next);
+                    self.record(pc, Kind::Indirect, correct);
+                    correct
+                };
+                if ops::is_link(rd) {
+                    self.ras.push(pc.wrapping_add(len));
+                }
+                !correct
+            }
+            _ => false,
+        }
+    }
+    pub fn report(&self, out: &mut impl Write, symbols: &Symbols) -> io::Result<()> {
+        let accuracy = |stats: &Stats| stats.correct as f64 * 100.0 / stats.executed.max(1) as f64;
+        writeln!(out, "Branch prediction report:")?;
+        writeln!(out, "  {:<32} {:>10} {:>10} {:>9}", "kind", "executed", "mispredict", "accuracy")?;
+        for (i, stats) in self.totals.iter().enumerate() {
+            writeln!(out, "  {:<32} {:>10} {:>10} {:>8.2}%", KIND_NAMES[i], stats.executed, stats.executed - stats.correct, accuracy(stats))?;
+        }
+        let mut sites: Vec<_> = self.sites.iter().collect();
+        sites.sort_by(|a, b| (b.1.1.executed - b.1.1.correct).cmp(&(a.1.1.executed - a.1.1.correct)).then(a.0.cmp(b.0)));
+        writeln!(out, "  {:<32} {:>10} {:>10} {:>9}", "site", "executed", "mispredict", "accuracy")?;
+        for (&pc, (kind, stats)) in sites {
+            let site = format!("0x{:08X} {} ({})", pc, symbols.name(pc), KIND_NAMES[*kind as usize]);
+            writeln!(out, "  {:<32} {:>10} {:>10} {:>8.2}%", site, stats.executed, stats.executed - stats.correct, accuracy(stats))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,335 @@
+use std::path::PathBuf;
+use crate::{bpred::BranchSim, cache::CacheSim, clint::{Clock, Timebase}, coverage::Coverage, dwarf::LineTable, elf::{self, Elf, Symbols}, fdt, htif::Htif, linux::{Abi, Process}, profile::Profiler, region::{Region, RegionList}, sbi, semihost::Semihost, setup, simple, timing::{Timing, TimingConfig}, trace::Trace, user, virt, vm::VM};
+
+/// The memory maps a machine can start from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Machine {
+    /// RAM from 0 with a serial port at 0x6969 and an exit port at 0x7000, nothing else.
+    Simple,
+    /// QEMU's virt board, 128 MiB of RAM at 0x8000_0000.
+    Virt,
+    /// A single Linux process in U-mode, like qemu-user. Needs [`Builder::program`].
+    User,
+}
+impl Machine {
+    /// Physical address of the first byte of RAM.
+    pub fn ram_base(self) -> usize {
+        match self {
+            Machine::Simple | Machine::User => 0,
+            Machine::Virt => virt::DRAM,
+        }
+    }
+    fn ram_size(self) -> usize {
+        match self {
+            Machine::Simple => simple::RAM_SIZE,
+            Machine::Virt => virt::DRAM_SIZE,
+            Machine::User => user::SIZE,
+        }
+    }
+    fn uart_irq(self) -> Option<usize> {
+        match self {
+            Machine::Virt => Some(virt::UART_IRQ),
+            Machine::Simple | Machine::User => None,
+        }
+    }
+    /// The device tree describing `regions` that the hart boots with, like `-dump-dtb`. None if the machine has none.
+    pub fn dtb(self, regions: &RegionList, clock: Clock) -> Option<Vec<u8>> {
+        self.uart_irq().map(|x| fdt::generate(regions, clock.timebase(), x))
+    }
+}
+
+/// Puts a [`VM`] together: the machine's memory map, extra devices, the images in RAM and how the hart starts.
+///
+/// ```
+/// use riscv_vm::{Builder, Halt, Machine};
+///
+/// // li a0, 42; lui t0, 0x7; sb a0, 0(t0), which writes the exit port
+/// let code = [0x02a00513u32, 0x000072b7, 0x00a28023];
+/// let image: Vec<u8> = code.iter().flat_map(|x| x.to_le_bytes()).collect();
+/// let mut vm = Builder::new(Machine::Simple).load(0, &image).build().unwrap();
+/// assert_eq!(vm.run_for(100), Some(Halt::Exit(42)));
+/// assert_eq!(vm.get_reg(10), 42);
+/// ```
+pub struct Builder {
+    machine: Machine,
+    images: Vec<(usize, Vec<u8>)>,
+    entry: Option<u32>,
+    regions: Vec<Region>,
+    clock: Clock,
+    kernel: bool,
+    newlib: bool,
+    semihost: bool,
+    tohost: Option<(u32, Option<u32>)>,
+    auxv: Vec<(u32, u32)>,
+    exe: PathBuf,
+    args: Vec<String>,
+    env: Vec<String>,
+    profile: bool,
+    coverage: bool,
+    timing: Option<TimingConfig>,
+    cache: Option<CacheSim>,
+    bpred: Option<BranchSim>,
+    trace: Option<String>,
+    stdin: Option<bool>,
+    symbols: Symbols,
+    signature: Option<(u32, u32)>,
+    lines: Option<Result<LineTable, gimli::Error>>,
+}
+impl Builder {
+    /// Starts from `machine`'s memory map with nothing loaded, the hart at the start of RAM in M-mode
+    /// and `time` counting instructions.
+    pub fn new(machine: Machine) -> Self {
+        Self {
+            machine,
+            images: Vec::new(),
+            entry: None,
+            regions: Vec::new(),
+            clock: Clock::Instret(1),
+            kernel: false,
+            newlib: false,
+            semihost: false,
+            tohost: None,
+            auxv: Vec::new(),
+            exe: PathBuf::new(),
+            args: Vec::new(),
+            env: Vec::new(),
+            profile: false,
+            coverage: false,
+            timing: None,
+            cache: None,
+            bpred: None,
+            trace: None,
+            stdin: None,
+            symbols: Symbols::default(),
+            signature: None,
+            lines: None,
+        }
+    }
+    /// Adds a device to the memory map. It can't overlap anything already there.
+    pub fn region(mut self, region: Region) -> Self {
+        self.regions.push(region);
+        self
+    }
+    /// Copies `bytes` into RAM at physical address `addr`. Later images go over earlier ones.
+    pub fn load(mut self, addr: usize, bytes: &[u8]) -> Self {
+        self.images.push((addr, bytes.to_vec()));
+        self
+    }
+    /// Loads an ELF executable's segments and starts at its entry point.
+    /// Its `tohost` and `fromhost` symbols, if it has them, become the HTIF.
+    pub fn elf(mut self, data: &[u8]) -> Result<Self, String> {
+        let elf = Elf::parse(data)?;
+        let base = self.machine.ram_base();
+        let image = elf.load(data, base, self.machine.ram_size())?;
+        self.images.push((base, image));
+        self.entry = Some(elf.entry);
+        self.auxv = crate::user::auxv(&elf);
+        if let Some(tohost) = elf.symbol(data, "tohost") {
+            self.tohost = Some((tohost, elf.symbol(data, "fromhost")));
+        }
+        self.signature = elf.symbol(data, "begin_signature").zip(elf.symbol(data, "end_signature"));
+        self.lines = Some(LineTable::load(&elf, data));
+        self.symbols = elf.symbols;
+        Ok(self)
+    }
+    /// Loads an ELF executable, or anything else as a raw image at the start of RAM.
+    /// A [`Machine::User`] process has to be an ELF.
+    pub fn image(self, data: &[u8]) -> Result<Self, String> {
+        if elf::is_elf(data) {
+            return self.elf(data);
+        }
+        if self.machine == Machine::User {
+            return Err("A process has to be an ELF executable".to_string());
+        }
+        let base = self.machine.ram_base();
+        Ok(self.load(base, data))
+    }
+    /// Where the hart starts, the start of RAM by default.
+    pub fn entry(mut self, pc: u32) -> Self {
+        self.entry = Some(pc);
+        self
+    }
+    /// Starts in S-mode with the built-in SBI standing in for firmware, like `-kernel`.
+    pub fn kernel(mut self) -> Self {
+        self.kernel = true;
+        self
+    }
+    /// What `time` and the CLINT's `mtime` count, one tick per instruction by default.
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+    /// Services ECALLs on the host like the proxy kernel, like `-newlib`.
+    pub fn newlib(mut self) -> Self {
+        self.newlib = true;
+        self
+    }
+    /// Services semihosting calls on the host, like `-semihost`.
+    pub fn semihost(mut self) -> Self {
+        self.semihost = true;
+        self
+    }
+    /// The HTIF's `tohost` and `fromhost` addresses, when they don't come from [`Builder::elf`].
+    pub fn htif(mut self, tohost: u32, fromhost: Option<u32>) -> Self {
+        self.tohost = Some((tohost, fromhost));
+        self
+    }
+    /// The program's path on the host and its arguments, `argv[0]` included.
+    /// They end up on the stack under [`Machine::User`] and in the semihosting command line.
+    pub fn program(mut self, exe: impl Into<PathBuf>, args: Vec<String>) -> Self {
+        self.exe = exe.into();
+        self.args = args;
+        self
+    }
+    /// The environment of a [`Machine::User`] process, as `KEY=value` strings.
+    pub fn env(mut self, env: Vec<String>) -> Self {
+        self.env = env;
+        self
+    }
+    /// Counts instructions per function and call stack, like `-profile`.
+    pub fn profile(mut self) -> Self {
+        self.profile = true;
+        self
+    }
+    /// Records which instructions ran and which way branches went, like `-coverage`.
+    pub fn coverage(mut self) -> Self {
+        self.coverage = true;
+        self
+    }
+    /// Counts cycles with the given pipeline model, like `-timing`.
+    /// Its region latencies have to name regions that start where they say.
+    pub fn timing(mut self, cfg: TimingConfig) -> Self {
+        self.timing = Some(cfg);
+        self
+    }
+    /// Runs every access through a cache model, like `-cache`.
+    pub fn cache(mut self, cache: CacheSim) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+    /// Runs every branch through a predictor model, like `-bpred`.
+    pub fn bpred(mut self, bpred: BranchSim) -> Self {
+        self.bpred = Some(bpred);
+        self
+    }
+    /// Writes a Spike-style commit log to `path`, like `-trace`.
+    pub fn trace(mut self, path: &str) -> Self {
+        self.trace = Some(path.to_string());
+        self
+    }
+
+    /// Feeds the host's stdin to the UART. With `raw` a terminal stays in raw mode as long as the VM does.
+    /// Left alone when a host call interface (`-newlib`, HTIF or semihosting) reads stdin itself.
+    pub fn stdin(mut self, raw: bool) -> Self {
+        self.stdin = Some(raw);
+        self
+    }
+
+    /// Lays out the machine and loads everything into it. Fails when
+    /// - an image starts below RAM,
+    /// - a region from [`Builder::region`] overlaps another one,
+    /// - a [`Machine::User`] process has no arguments from [`Builder::program`], or they don't fit on its stack,
+    /// - a region latency in the [`TimingConfig`] doesn't name a region,
+    /// - the [`Builder::trace`] file can't be created.
+    pub fn build(self) -> Result<VM, String> {
+        let base = self.machine.ram_base();
+        if let Some((addr, _)) = self.images.iter().find(|x| x.0 < base) {
+            return Err(format!("Image at 0x{:08X} is below the start of RAM", addr));
+        }
+        let end = self.images.iter().map(|(addr, bytes)| addr - base + bytes.len()).max().unwrap_or(0);
+        let mut images = self.images.into_iter().peekable();
+        // An ELF image can span most of the address space, so one at the start of RAM becomes RAM rather than getting copied
+        let mut ram = images.next_if(|x| x.0 == base).map(|x| x.1).unwrap_or_default();
+        setup::grow(&mut ram, end);
+        for (addr, bytes) in images {
+            let off = addr - base;
+            ram[off..off+bytes.len()].copy_from_slice(&bytes);
+        }
+        let image_end = base + ram.len();
+        let stdin = self.stdin.filter(|_| !self.newlib && !self.semihost && self.tohost.is_none());
+        let setup = match self.machine {
+            Machine::Simple => simple::setup(ram),
+            Machine::Virt => virt::setup(ram, stdin),
+            Machine::User => user::setup(ram),
+        };
+        let mut layout = setup.layout.0.into_vec();
+        layout.extend(self.regions);
+        layout.sort_by_key(|x| x.addr);
+        if let Some(x) = layout.windows(2).find(|x| x[0].addr + x[0].size > x[1].addr) {
+            return Err(format!("Region at 0x{:08X} overlaps the one at 0x{:08X}", x[1].addr, x[0].addr));
+        }
+        let layout = RegionList(layout.into_boxed_slice());
+        let dtb = self.machine.dtb(&layout, self.clock);
+        let mut vm = VM::new(layout);
+        vm.time = Timebase::new(self.clock);
+        let mut sp = setup.sp;
+        let mut dtb_addr = 0;
+        if let Some(dtb) = dtb {
+            // The device tree goes at the very top of RAM with the stack right below it
+            dtb_addr = (setup.ram_end - dtb.len()) & !0xF;
+            vm.write(dtb_addr, &dtb).map_err(|x| format!("No RAM for the device tree at 0x{:08X}", x))?;
+            sp = sp.min(dtb_addr);
+        } else if self.machine == Machine::User {
+            if self.args.is_empty() {
+                return Err("A process needs its arguments, argv[0] at least".to_string());
+            }
+            sp = user::stack(&mut vm, sp, &self.args, &self.env, self.auxv)
+                .map_err(|x| format!("The arguments and environment don't fit on the stack, ran out at 0x{:08X}", x))?;
+        }
+        vm.set_rsp(sp);
+        if dtb_addr != 0 {
+            // Boot convention: hart id in a0 and the device tree in a1
+            vm.regs[10] = 0;
+            vm.regs[11] = dtb_addr as i32;
+        }
+        vm.ip = self.entry.unwrap_or(base as u32) as i32;
+        if self.kernel {
+            sbi::boot(&mut vm);
+        }
+        if self.machine == Machine::User {
+            user::boot(&mut vm, Process::new(Abi::Linux, self.exe.clone(), image_end, setup.sp));
+        } else if self.newlib {
+            // ECALLs go to the host the way the proxy kernel would take them
+            vm.process = Some(Process::new(Abi::Newlib, self.exe.clone(), image_end, sp));
+        }
+        // riscv-tests and anything else built for spike signal the host through tohost
+        if let Some((tohost, fromhost)) = self.tohost {
+            let process = Process::new(Abi::Newlib, self.exe.clone(), image_end, sp);
+            vm.htif = Some(Htif::new(tohost, fromhost, process));
+        }
+        if self.semihost {
+            vm.semihost = Some(Semihost::new(self.args.join(" ")));
+        }
+        if let Some(cfg) = self.timing {
+            for &(addr, latency) in cfg.regions.iter() {
+                let Some(region) = vm.regions.0.iter_mut().find(|x| x.addr == addr) else {
+                    return Err(format!("No region starts at 0x{:08X}", addr));
+                };
+                region.latency = latency;
+            }
+            vm.timing = Some(Timing::new(cfg));
+        }
+        if self.profile {
+            vm.profile = Some(Profiler::new(vm.ip as u32));
+        }
+        if self.coverage {
+            let lines = match self.lines {
+                Some(Ok(v)) if !v.rows.is_empty() => Some(v),
+                Some(Err(e)) => {
+                    eprintln!("WARN: Ignoring malformed DWARF line info: {}", e);
+                    None
+                }
+                _ => None,
+            };
+            vm.coverage = Some(Coverage::new(lines));
+        }
+        vm.symbols = self.symbols;
+        vm.signature = self.signature;
+        vm.cache = self.cache;
+        vm.bpred = self.bpred;
+        if let Some(path) = self.trace {
+            vm.trace = Some(Trace::create(&path).map_err(|e| format!("Failed to create trace file {}: {}", path, e))?);
+        }
+        Ok(vm)
+    }
+}
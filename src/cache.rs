@@ -0,0 +1,290 @@
+use std::{collections::HashMap, io::{self, Write}};
+
+use crate::elf::Symbols;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Replacement {
+    Lru,
+    Fifo,
+    Random,
+}
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum WritePolicy {
+    // Write-back with write-allocate
+    WriteBack,
+    // Write-through without write-allocate
+    WriteThrough,
+}
+const MAX_LINES: usize = 1 << 24;
+#[derive(Clone, Copy)]
+pub struct CacheConfig {
+    pub size: usize,
+    pub ways: usize,
+    pub line: usize,
+    pub replacement: Replacement,
+    pub write: WritePolicy,
+}
+impl CacheConfig {
+    // <size>/<ways>/<line>[/<lru|fifo|random>][/<wb|wt>], e.g. 32k/4/64/lru/wb
+    fn parse(src: &str, mut cfg: Self) -> Result<Self, String> {
+        let mut parts = src.split('/');
+        if let Some(size) = parts.next() {
+            let (digits, mul) = match size.as_bytes().last() {
+                Some(b'k' | b'K') => (&size[..size.len()-1], 1024),
+                Some(b'm' | b'M') => (&size[..size.len()-1], 1024*1024),
+                _ => (size, 1),
+            };
+            cfg.size = digits.parse::<usize>().map_err(|e| format!("Invalid cache size `{}`: {}", size, e))?
+                .checked_mul(mul).ok_or_else(|| format!("Cache size `{}` is too big", size))?;
+        }
+        if let Some(ways) = parts.next() {
+            cfg.ways = ways.parse().map_err(|e| format!("Invalid associativity `{}`: {}", ways, e))?;
+        }
+        if let Some(line) = parts.next() {
+            cfg.line = line.parse().map_err(|e| format!("Invalid line size `{}`: {}", line, e))?;
+        }
+        for part in parts {
+            match part {
+                "lru" => cfg.replacement = Replacement::Lru,
+                "fifo" => cfg.replacement = Replacement::Fifo,
+                "random" => cfg.replacement = Replacement::Random,
+                "wb" => cfg.write = WritePolicy::WriteBack,
+                "wt" => cfg.write = WritePolicy::WriteThrough,
+                _ => return Err(format!("Unknown cache option `{}`", part)),
+            }
+        }
+        if cfg.size == 0 || cfg.ways == 0 || cfg.line == 0 {
+            return Err(format!("Cache size, associativity and line size have to be non-zero, got {}/{}/{}", cfg.size, cfg.ways, cfg.line));
+        }
+        let set_size = cfg.ways.checked_mul(cfg.line).filter(|&x| x <= cfg.size);
+        if !cfg.line.is_power_of_two() || set_size.is_none_or(|x| !cfg.size.is_multiple_of(x) || !(cfg.size / x).is_power_of_two()) {
+            return Err(format!("{} bytes can't be split into {} way sets of {} byte lines", cfg.size, cfg.ways, cfg.line));
+        }
+        // Every line is simulated, so the count has to stay allocatable
+        if cfg.size / cfg.line > MAX_LINES || cfg.line > u32::MAX as usize {
+            return Err(format!("{} bytes of {} byte lines is more than the {} lines that can be simulated", cfg.size, cfg.line, MAX_LINES));
+        }
+        Ok(cfg)
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct Stats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub writebacks: u64,
+}
+impl Stats {
+    fn add(&mut self, other: &Stats) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.evictions += other.evictions;
+        self.writebacks += other.writebacks;
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Line {
+    valid: bool,
+    dirty: bool,
+    tag: u32,
+    // Last use for LRU, fill time for FIFO
+    stamp: u64,
+}
+struct Outcome {
+    hit: bool,
+    // Line address of a dirty victim that has to be written back
+    writeback: Option<u32>,
+    evicted: bool,
+}
+pub struct Cache {
+    cfg: CacheConfig,
+    lines: Vec<Line>,
+    sets: usize,
+    tick: u64,
+    rng: u64,
+}
+impl Cache {
+    pub fn new(cfg: CacheConfig) -> Self {
+        let sets = cfg.size / (cfg.ways * cfg.line);
+        Self { cfg, lines: vec![Line::default(); sets * cfg.ways], sets, tick: 0, rng: 0x2545F4914F6CDD1D }
+    }
+    fn victim(&mut self, set: usize) -> usize {
+        let ways = &self.lines[set*self.cfg.ways..(set+1)*self.cfg.ways];
+        if let Some(i) = ways.iter().position(|x| !x.valid) {
+            return i;
+        }
+        match self.cfg.replacement {
+            Replacement::Lru | Replacement::Fifo => ways.iter().enumerate().min_by_key(|x| x.1.stamp).unwrap().0,
+            Replacement::Random => {
+                // xorshift64, so runs stay reproducible
+                self.rng ^= self.rng << 13;
+                self.rng ^= self.rng >> 7;
+                self.rng ^= self.rng << 17;
+                (self.rng % self.cfg.ways as u64) as usize
+            }
+        }
+    }
+    fn access(&mut self, addr: u32, write: bool) -> Outcome {
+        self.tick += 1;
+        let line = addr / self.cfg.line as u32;
+        let set = line as usize & (self.sets-1);
+        let tag = line / self.sets as u32;
+        let base = set*self.cfg.ways;
+        if let Some(way) = self.lines[base..base+self.cfg.ways].iter().position(|x| x.valid && x.tag == tag) {
+            let entry = &mut self.lines[base+way];
+            if self.cfg.replacement == Replacement::Lru {
+                entry.stamp = self.tick;
+            }
+            entry.dirty |= write && self.cfg.write == WritePolicy::WriteBack;
+            return Outcome { hit: true, writeback: None, evicted: false };
+        }
+        if write && self.cfg.write == WritePolicy::WriteThrough {
+            return Outcome { hit: false, writeback: None, evicted: false };
+        }
+        let way = self.victim(set);
+        let entry = &mut self.lines[base+way];
+        let evicted = entry.valid;
+        let writeback = if entry.valid && entry.dirty {
+            Some((entry.tag * self.sets as u32 + set as u32) * self.cfg.line as u32)
+        } else {
+            None
+        };
+        *entry = Line { valid: true, dirty: write, tag, stamp: self.tick };
+        Outcome { hit: false, writeback, evicted }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Level {
+    L1I,
+    L1D,
+    L2,
+}
+const LEVEL_NAMES: [&str; 3] = ["l1i", "l1d", "l2"];
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Fetch,
+    Read,
+    Write,
+}
+pub struct CacheSim {
+    l1i: Cache,
+    l1d: Cache,
+    l2: Option<Cache>,
+    // Cycles to fetch a line from the L2 and from memory
+    l2_latency: u32,
+    mem_latency: u32,
+    totals: [Stats; 3],
+    regions: HashMap<(Level, usize), Stats>,
+    pcs: HashMap<(Level, u32), Stats>,
+}
+impl CacheSim {
+    // Comma separated list of <level>=<config> and latencies, e.g.
+    //  l1i=16k/2/32/lru,l1d=16k/4/32/lru/wb,l2=256k/8/64/lru/wb,l2lat=10,memlat=100
+    // Anything left out keeps its default, `default` alone picks just the defaults.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let l1 = CacheConfig { size: 16*1024, ways: 2, line: 32, replacement: Replacement::Lru, write: WritePolicy::WriteBack };
+        let (mut l1i, mut l1d, mut l2) = (l1, l1, None);
+        let (mut l2_latency, mut mem_latency) = (10, 100);
+        for item in spec.split(',').filter(|x| !x.is_empty() && *x != "default") {
+            let Some((key, value)) = item.split_once('=') else {
+                return Err(format!("Expected `key=value` but got `{}`", item));
+            };
+            match key {
+                "l1i" => l1i = CacheConfig::parse(value, l1i)?,
+                "l1d" => l1d = CacheConfig::parse(value, l1d)?,
+                "l2" => l2 = Some(CacheConfig::parse(value, CacheConfig { size: 256*1024, ways: 8, line: 64, ..l1 })?),
+                "l2lat" => l2_latency = value.parse().map_err(|e| format!("Invalid latency `{}`: {}", value, e))?,
+                "memlat" => mem_latency = value.parse().map_err(|e| format!("Invalid latency `{}`: {}", value, e))?,
+                _ => return Err(format!("Unknown cache key `{}`", key)),
+            }
+        }
+        Ok(Self {
+            l1i: Cache::new(l1i),
+            l1d: Cache::new(l1d),
+            l2: l2.map(Cache::new),
+            l2_latency,
+            mem_latency,
+            totals: [Stats::default(); 3],
+            regions: HashMap::new(),
+            pcs: HashMap::new(),
+        })
+    }
+    fn record(&mut self, level: Level, region: usize, pc: u32, outcome: &Outcome) {
+        let stats = Stats {
+            hits: outcome.hit as u64,
+            misses: !outcome.hit as u64,
+            evictions: outcome.evicted as u64,
+            writebacks: outcome.writeback.is_some() as u64,
+        };
+        self.totals[level as usize].add(&stats);
+        self.regions.entry((level, region)).or_default().add(&stats);
+        self.pcs.entry((level, pc)).or_default().add(&stats);
+    }
+    // Goes to the L2 if there is one, returns the cycles spent below the L1
+    fn next_level(&mut self, addr: u32, write: bool, region: usize, pc: u32) -> u32 {
+        let Some(l2) = self.l2.as_mut() else { return self.mem_latency; };
+        let outcome = l2.access(addr, write);
+        self.record(Level::L2, region, pc, &outcome);
+        if outcome.hit { self.l2_latency } else { self.l2_latency + self.mem_latency }
+    }
+    // Returns the extra cycles the access took because of misses
+    pub fn access(&mut self, addr: u32, kind: Access, region: usize, pc: u32) -> u32 {
+        let (level, l1) = match kind {
+            Access::Fetch => (Level::L1I, &mut self.l1i),
+            Access::Read | Access::Write => (Level::L1D, &mut self.l1d),
+        };
+        let write = kind == Access::Write;
+        let write_through = l1.cfg.write == WritePolicy::WriteThrough;
+        let outcome = l1.access(addr, write);
+        self.record(level, region, pc, &outcome);
+        let mut latency = 0;
+        if let Some(victim) = outcome.writeback {
+            latency += self.next_level(victim, true, region, pc);
+        }
+        if !outcome.hit {
+            // A write-through miss goes straight to the next level without allocating
+            latency += self.next_level(addr, write, region, pc);
+        } else if write && write_through {
+            // Write hits are assumed to drain through a write buffer without stalling
+            self.next_level(addr, true, region, pc);
+        }
+        latency
+    }
+    pub fn report(&self, out: &mut impl Write, symbols: &Symbols) -> io::Result<()> {
+        let row = |out: &mut dyn Write, name: &str, stats: &Stats| {
+            let total = stats.hits + stats.misses;
+            writeln!(out, "  {:<24} {:>10} {:>10} {:>10} {:>10} {:>7.2}%",
+                name, stats.hits, stats.misses, stats.evictions, stats.writebacks,
+                stats.hits as f64 * 100.0 / total.max(1) as f64)
+        };
+        let header = |out: &mut dyn Write, title: &str| {
+            writeln!(out, "  {:<24} {:>10} {:>10} {:>10} {:>10} {:>8}", title, "hits", "misses", "evictions", "writebacks", "hit rate")
+        };
+        writeln!(out, "Cache report:")?;
+        header(out, "cache")?;
+        for (i, stats) in self.totals.iter().enumerate() {
+            if i == Level::L2 as usize && self.l2.is_none() { continue; }
+            row(out, LEVEL_NAMES[i], stats)?;
+        }
+        let mut regions: Vec<_> = self.regions.iter().collect();
+        regions.sort_by_key(|x| x.0);
+        header(out, "region")?;
+        for (&(level, region), stats) in regions {
+            row(out, &format!("{} 0x{:08X}", LEVEL_NAMES[level as usize], region), stats)?;
+        }
+        let mut funcs: HashMap<(Level, String), Stats> = HashMap::new();
+        for (&(level, pc), stats) in self.pcs.iter() {
+            funcs.entry((level, symbols.name(pc))).or_default().add(stats);
+        }
+        let mut funcs: Vec<_> = funcs.into_iter().collect();
+        funcs.sort_by(|a, b| a.0.0.cmp(&b.0.0).then(b.1.misses.cmp(&a.1.misses)).then_with(|| a.0.1.cmp(&b.0.1)));
+        header(out, "function")?;
+        for ((level, name), stats) in funcs {
+            row(out, &format!("{} {}", LEVEL_NAMES[level as usize], name), &stats)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use std::{thread, time::{Duration, Instant}};
+
+use crate::csr::irq;
+
+// Register offsets of the SiFive CLINT, with a single hart
+pub const MSIP    : usize = 0x0;
+pub const MTIMECMP: usize = 0x4000;
+pub const MTIME   : usize = 0xBFF8;
+pub const SIZE    : usize = 0x10000;
+
+// Same timebase as QEMU's virt machine
+const DEFAULT_HZ: u64 = 10_000_000;
+
+#[derive(Clone, Copy)]
+pub enum Clock {
+    // mtime advances once every N retired instructions, so runs stay deterministic
+    Instret(u64),
+    // mtime follows the host's wall clock at the given frequency
+    Host { start: Instant, hz: u64 },
+}
+impl Clock {
+    // instret[:<instructions per tick>] or host[:<hz>]
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (kind, arg) = match spec.split_once(':') {
+            Some((kind, arg)) => (kind, Some(arg)),
+            None => (spec, None),
+        };
+        let arg = |default: u64| -> Result<u64, String> {
+            match arg {
+                Some(v) => match v.parse() {
+                    Ok(0) => Err("Clock rate can't be zero".to_string()),
+                    Ok(v) => Ok(v),
+                    Err(e) => Err(format!("Invalid clock rate `{}`: {}", v, e)),
+                },
+                None => Ok(default),
+            }
+        };
+        match kind {
+            "instret" => Ok(Clock::Instret(arg(1)?)),
+            "host" => Ok(Clock::Host { start: Instant::now(), hz: arg(DEFAULT_HZ)? }),
+            _ => Err(format!("Unknown clock `{}`", kind)),
+        }
+    }
+    // Ticks per second as advertised to the guest, which is only nominal for instret
+    pub fn timebase(&self) -> u64 {
+        match *self {
+            Clock::Instret(_) => DEFAULT_HZ,
+            Clock::Host { hz, .. } => hz,
+        }
+    }
+}
+
+// The platform's clock, which mtime in the CLINT and the time CSR both read
+pub struct Timebase {
+    pub clock: Clock,
+    retired: u64,
+    // Added to the raw tick count, so the guest can write mtime
+    offset: u64,
+}
+impl Timebase {
+    pub fn new(clock: Clock) -> Self {
+        Self { clock, retired: 0, offset: 0 }
+    }
+    fn ticks(&self) -> u64 {
+        match self.clock {
+            Clock::Instret(div) => self.retired / div,
+            Clock::Host { start, hz } => (start.elapsed().as_nanos() * hz as u128 / 1_000_000_000) as u64,
+        }
+    }
+    pub fn mtime(&self) -> u64 {
+        self.ticks().wrapping_add(self.offset)
+    }
+    pub fn set_mtime(&mut self, v: u64) {
+        self.offset = v.wrapping_sub(self.ticks());
+    }
+    #[inline]
+    pub fn retire(&mut self) {
+        self.retired += 1;
+    }
+    // Lets time pass until mtime reaches `until`
+    fn skip(&mut self, until: u64) {
+        let now = self.mtime();
+        if now >= until { return; }
+        match self.clock {
+            Clock::Instret(_) => self.offset = self.offset.wrapping_add(until - now),
+            Clock::Host { hz, .. } => thread::sleep(Duration::from_nanos(((until - now) as u128 * 1_000_000_000 / hz as u128) as u64)),
+        }
+    }
+}
+
+// The comparator and software interrupt of hart 0, time itself comes from the Timebase
+pub struct Clint {
+    pub mtimecmp: u64,
+    pub msip: bool,
+}
+impl Default for Clint {
+    fn default() -> Self {
+        Self { mtimecmp: u64::MAX, msip: false }
+    }
+}
+impl Clint {
+    // MSIP and MTIP as they should show up in mip
+    pub fn pending(&self, time: &Timebase) -> u32 {
+        let mut bits = 0;
+        if self.msip { bits |= irq::MSI; }
+        if time.mtime() >= self.mtimecmp { bits |= irq::MTI; }
+        bits
+    }
+    // Lets time pass until the timer fires, false if it never will
+    pub fn wait(&self, time: &mut Timebase) -> bool {
+        if self.mtimecmp == u64::MAX && time.mtime() < self.mtimecmp { return false; }
+        time.skip(self.mtimecmp);
+        true
+    }
+}
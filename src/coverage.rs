@@ -0,0 +1,124 @@
+use std::{collections::{BTreeMap, HashMap}, io::{self, Write}};
+
+use crate::{dwarf::LineTable, elf::Symbols, inst::{inst_len, Inst32}, ops, rvc};
+
+#[derive(Default, Clone, Copy)]
+struct Branch {
+    taken: u64,
+    not_taken: u64,
+}
+// Records every executed instruction address and the direction of every conditional branch
+#[derive(Default)]
+pub struct Coverage {
+    hits: HashMap<u32, u64>,
+    branches: HashMap<u32, Branch>,
+    // Where the source lines are, from the ELF's DWARF
+    pub lines: Option<LineTable>,
+}
+#[derive(Default)]
+struct FileReport {
+    lines: BTreeMap<u32, u64>,
+    // (line, address) -> outcome, None if the branch never executed
+    branches: BTreeMap<(u32, u32), Option<Branch>>,
+    funcs: Vec<(u32, String, u64)>,
+}
+impl Coverage {
+    pub fn new(lines: Option<LineTable>) -> Self {
+        Self { hits: HashMap::new(), branches: HashMap::new(), lines }
+    }
+    pub fn retire(&mut self, pc: u32, inst: Inst32, next: u32) {
+        *self.hits.entry(pc).or_insert(0) += 1;
+        if inst.opcode() == ops::BRANCH_OP {
+            let branch = self.branches.entry(pc).or_default();
+            if next == pc.wrapping_add(inst.imm_B() as u32) {
+                branch.taken += 1;
+            } else {
+                branch.not_taken += 1;
+            }
+        }
+    }
+    // One line per executed address:
+    //  <address> <hits> [<taken> <not taken>]
+    pub fn write_hitmap(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut hits: Vec<_> = self.hits.iter().collect();
+        hits.sort();
+        writeln!(out, "# address hits [taken not_taken]")?;
+        for (&addr, &count) in hits {
+            write!(out, "0x{:08x} {}", addr, count)?;
+            if let Some(branch) = self.branches.get(&addr) {
+                write!(out, " {} {}", branch.taken, branch.not_taken)?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+    // `code` reads the word at an address, so branches can be told apart from other instructions
+    // Nothing without a line table
+    pub fn write_lcov(&self, out: &mut impl Write, symbols: &Symbols, mut code: impl FnMut(u32) -> Option<u32>) -> io::Result<()> {
+        let Some(lines) = &self.lines else { return Ok(()); };
+        let mut files: Vec<FileReport> = lines.files.iter().map(|_| FileReport::default()).collect();
+        for (line, end) in lines.ranges() {
+            let report = &mut files[line.file];
+            let mut addr = line.addr;
+            while addr < end {
+                let count = self.hits.get(&addr).copied().unwrap_or(0);
+                let hits = report.lines.entry(line.line).or_insert(0);
+                *hits = (*hits).max(count);
+                let Some(raw) = code(addr) else { break; };
+                let inst = if inst_len(raw as u16) == 1 { rvc::expand(raw as u16) } else { Some(Inst32::new(raw)) };
+                if inst.is_some_and(|x| x.opcode() == ops::BRANCH_OP) {
+                    report.branches.insert((line.line, addr), self.branches.get(&addr).copied());
+                }
+                addr += (inst_len(raw as u16).max(1) * 2) as u32;
+            }
+        }
+        for sym in symbols.0.iter() {
+            if let Some(line) = lines.lookup(sym.addr) {
+                let count = self.hits.get(&sym.addr).copied().unwrap_or(0);
+                files[line.file].funcs.push((line.line, sym.name.clone(), count));
+            }
+        }
+        let mut order: Vec<usize> = (0..files.len()).collect();
+        order.sort_by_key(|&i| &lines.files[i]);
+        for i in order {
+            let report = &files[i];
+            if report.lines.is_empty() { continue; }
+            writeln!(out, "TN:")?;
+            writeln!(out, "SF:{}", lines.files[i])?;
+            for (line, name, _) in report.funcs.iter() {
+                writeln!(out, "FN:{},{}", line, name)?;
+            }
+            for (_, name, count) in report.funcs.iter() {
+                writeln!(out, "FNDA:{},{}", count, name)?;
+            }
+            writeln!(out, "FNF:{}", report.funcs.len())?;
+            writeln!(out, "FNH:{}", report.funcs.iter().filter(|x| x.2 > 0).count())?;
+            let mut line_branches: HashMap<u32, usize> = HashMap::new();
+            let mut brh = 0;
+            for (&(line, _), branch) in report.branches.iter() {
+                let index = line_branches.entry(line).or_insert(0);
+                match branch {
+                    Some(branch) => {
+                        writeln!(out, "BRDA:{},0,{},{}", line, *index, branch.taken)?;
+                        writeln!(out, "BRDA:{},0,{},{}", line, *index + 1, branch.not_taken)?;
+                        brh += (branch.taken > 0) as usize + (branch.not_taken > 0) as usize;
+                    }
+                    None => {
+                        writeln!(out, "BRDA:{},0,{},-", line, *index)?;
+                        writeln!(out, "BRDA:{},0,{},-", line, *index + 1)?;
+                    }
+                }
+                *index += 2;
+            }
+            writeln!(out, "BRF:{}", report.branches.len() * 2)?;
+            writeln!(out, "BRH:{}", brh)?;
+            for (line, hits) in report.lines.iter() {
+                writeln!(out, "DA:{},{}", line, hits)?;
+            }
+            writeln!(out, "LF:{}", report.lines.len())?;
+            writeln!(out, "LH:{}", report.lines.values().filter(|&&x| x > 0).count())?;
+            writeln!(out, "end_of_record")?;
+        }
+        Ok(())
+    }
+}
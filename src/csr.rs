@@ -0,0 +1,135 @@
+// NOTE: Page 8 of the privileged manual for CSR address mapping
+pub const CYCLE     : u32 = 0xC00;
+pub const TIME      : u32 = 0xC01;
+pub const INSTRET   : u32 = 0xC02;
+pub const CYCLEH    : u32 = 0xC80;
+pub const TIMEH     : u32 = 0xC81;
+pub const INSTRETH  : u32 = 0xC82;
+
+pub const SSTATUS   : u32 = 0x100;
+pub const SIE       : u32 = 0x104;
+pub const STVEC     : u32 = 0x105;
+pub const SCOUNTEREN: u32 = 0x106;
+pub const SSCRATCH  : u32 = 0x140;
+pub const SEPC      : u32 = 0x141;
+pub const SCAUSE    : u32 = 0x142;
+pub const STVAL     : u32 = 0x143;
+pub const SIP       : u32 = 0x144;
+pub const SATP      : u32 = 0x180;
+
+pub const MVENDORID : u32 = 0xF11;
+pub const MARCHID   : u32 = 0xF12;
+pub const MIMPID    : u32 = 0xF13;
+pub const MHARTID   : u32 = 0xF14;
+pub const MSTATUS   : u32 = 0x300;
+pub const MISA      : u32 = 0x301;
+pub const MEDELEG   : u32 = 0x302;
+pub const MIDELEG   : u32 = 0x303;
+pub const MIE       : u32 = 0x304;
+pub const MTVEC     : u32 = 0x305;
+pub const MCOUNTEREN: u32 = 0x306;
+pub const MSTATUSH  : u32 = 0x310;
+pub const MSCRATCH  : u32 = 0x340;
+pub const MEPC      : u32 = 0x341;
+pub const MCAUSE    : u32 = 0x342;
+pub const MTVAL     : u32 = 0x343;
+pub const MIP       : u32 = 0x344;
+pub const PMPCFG0   : u32 = 0x3A0;
+pub const PMPCFG3   : u32 = 0x3A3;
+pub const PMPADDR0  : u32 = 0x3B0;
+pub const PMPADDR15 : u32 = 0x3BF;
+pub const MCYCLE    : u32 = 0xB00;
+pub const MINSTRET  : u32 = 0xB02;
+pub const MCYCLEH   : u32 = 0xB80;
+pub const MINSTRETH : u32 = 0xB82;
+
+pub mod mstatus {
+    pub const SIE : u32 = 1 << 1;
+    pub const MIE : u32 = 1 << 3;
+    pub const SPIE: u32 = 1 << 5;
+    pub const MPIE: u32 = 1 << 7;
+    pub const SPP : u32 = 1 << 8;
+    pub const MPP_SHIFT: u32 = 11;
+    pub const MPP : u32 = 0b11 << MPP_SHIFT;
+    pub const MPRV: u32 = 1 << 17;
+    pub const SUM : u32 = 1 << 18;
+    pub const MXR : u32 = 1 << 19;
+    pub const TVM : u32 = 1 << 20;
+    pub const TW  : u32 = 1 << 21;
+    pub const TSR : u32 = 1 << 22;
+    // The subset of mstatus visible through sstatus
+    pub const SSTATUS_MASK: u32 = SIE | SPIE | SPP | SUM | MXR;
+    pub const WRITE_MASK: u32 = SSTATUS_MASK | MIE | MPIE | MPP | MPRV | TVM | TW | TSR;
+}
+// Interrupt bits of mip/mie
+pub mod irq {
+    pub const SSI: u32 = 1 << 1;
+    pub const MSI: u32 = 1 << 3;
+    pub const STI: u32 = 1 << 5;
+    pub const MTI: u32 = 1 << 7;
+    pub const SEI: u32 = 1 << 9;
+    pub const MEI: u32 = 1 << 11;
+    pub const S_MASK: u32 = SSI | STI | SEI;
+    pub const ALL: u32 = S_MASK | MSI | MTI | MEI;
+    // Order in which simultaneous interrupts are taken
+    pub const PRIORITY: [u32; 6] = [MEI, MSI, MTI, SEI, SSI, STI];
+}
+
+// MXL=32, A, C, I, M, S and U
+pub const MISA_VALUE: u32 = (1 << 30) | (1 << 0) | (1 << 2) | (1 << 8) | (1 << 12) | (1 << 18) | (1 << 20);
+// Every exception except an ecall from M-mode can be delegated
+pub const MEDELEG_MASK: u32 = 0xB3FF;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Mode {
+    User = 0,
+    Supervisor = 1,
+    Machine = 3,
+}
+impl Mode {
+    #[inline]
+    pub const fn from_bits(bits: u32) -> Self {
+        match bits & 0b11 {
+            0 => Mode::User,
+            1 => Mode::Supervisor,
+            _ => Mode::Machine,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Csrs {
+    pub mstatus: u32,
+    pub medeleg: u32,
+    pub mideleg: u32,
+    pub mie: u32,
+    pub mip: u32,
+    pub mtvec: u32,
+    pub mcounteren: u32,
+    pub mscratch: u32,
+    pub mepc: u32,
+    pub mcause: u32,
+    pub mtval: u32,
+    pub stvec: u32,
+    pub scounteren: u32,
+    pub sscratch: u32,
+    pub sepc: u32,
+    pub scause: u32,
+    pub stval: u32,
+    pub satp: u32,
+}
+
+#[inline]
+pub const fn is_read_only(csr: u32) -> bool {
+    (csr >> 10) & 0b11 == 0b11
+}
+// Lowest privilege level allowed to access the CSR
+#[inline]
+pub const fn min_mode(csr: u32) -> Mode {
+    Mode::from_bits(csr >> 8)
+}
+// cycle, time, instret and the hpmcounters, along with their high halves
+#[inline]
+pub const fn is_user_counter(csr: u32) -> bool {
+    matches!(csr, 0xC00..=0xC1F | 0xC80..=0xC9F)
+}
@@ -1,14 +1,23 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, io::{self, BufRead, Write}};
 
-use crate::vm::VM;
+use crate::{cache::Access, csr::Mode, disasm::Disasm32, mmu, plic, region::Kind, vm::VM};
 
-pub struct Dbg<'a, 'rlist> {
+impl VM {
+    /// Runs the debugger on commands from `input`, like `-dbg`, until it ends, the guest halts or the user quits.
+    pub fn debug(self, input: impl BufRead) -> VM {
+        let mut debugger = Dbg::new(self);
+        debugger.repl(input);
+        debugger.vm
+    }
+}
+
+pub struct Dbg {
     pub breakpoints: HashSet<u32>,
-    pub vm: VM<'a, 'rlist>
+    pub vm: VM
 }
-impl <'a, 'rlist> Dbg <'a, 'rlist> {
+impl Dbg {
     #[inline]
-    pub fn new(vm: VM<'a, 'rlist>) -> Self {
+    pub fn new(vm: VM) -> Self {
         Self { vm, breakpoints: HashSet::new() }
     }
 
@@ -17,14 +26,188 @@ impl <'a, 'rlist> Dbg <'a, 'rlist> {
     }
     pub fn r#continue(&mut self) {
         loop {
-            if self.breakpoints.contains(&(self.vm.ip as u32)) {
+            if self.vm.halt.is_some() || self.breakpoints.contains(&(self.vm.ip as u32)) {
                 return;
             }
             self.next();
         }
     }
     pub fn disasm(&mut self) {
-        eprint!("{:08X}>",self.vm.ip);
-        self.vm.disasm(self.vm.ip());
+        self.show(self.vm.ip());
+    }
+    fn show(&mut self, addr: usize) {
+        eprint!("{:08X}>", addr);
+        match self.vm.disasm(addr) {
+            Ok((inst, _)) => println!("{}", Disasm32(inst)),
+            Err(e) => println!("{}", e),
+        }
+    }
+    // Shows how the hart would translate a load from `vaddr` right now
+    pub fn translate(&mut self, vaddr: u32) {
+        let satp = self.vm.csr.satp;
+        if satp & mmu::SATP_MODE == 0 {
+            eprintln!("satp=0x{:08X} (bare), 0x{:08X} is used as is", satp, vaddr);
+            return;
+        }
+        eprintln!("satp=0x{:08X} (Sv32) root=0x{:08X}", satp, ((satp & mmu::SATP_PPN) as usize) << mmu::PAGE_SHIFT);
+        if self.vm.mode == Mode::Machine {
+            eprintln!("NOTE: The hart is in M-mode, where translation doesn't apply");
+        }
+        let mut steps = [None; 2];
+        let walk = self.vm.walk(vaddr, Access::Read, &mut steps);
+        for (i, (addr, pte)) in steps.iter().flatten().enumerate() {
+            eprintln!("  L{} pte@0x{:08X} = 0x{:08X} ppn=0x{:06X} {}", 1-i, addr, pte, pte >> 10, mmu::flags_str(*pte));
+        }
+        match walk {
+            Ok(entry) => eprintln!("0x{:08X} -> 0x{:08X}{}", vaddr,
+                ((entry.ppn as usize) << mmu::PAGE_SHIFT) | (vaddr & (mmu::PAGE_SIZE-1)) as usize,
+                if entry.mega { " (megapage)" } else { "" }),
+            Err(trap) => eprintln!("0x{:08X} does not translate, cause {}", vaddr, trap.cause),
+        }
+    }
+    // Reads commands from `input` until it ends, the guest halts or the user quits
+    pub fn repl(&mut self, input: impl BufRead) {
+        self.disasm();
+        eprint!(":");
+        io::stderr().flush().unwrap();
+        let mut lastline = String::new();
+        for mut l in input.lines().map_while(Result::ok) {
+            if l.is_empty() {
+                if lastline.is_empty() { continue; }
+                l = lastline 
+            }
+            let line = l.as_str();
+            let (cmd, arg) = line.split_at(line.find(' ').unwrap_or(line.len()));
+            let arg = arg.trim_start();
+            match cmd {
+                "n" | "next" => {
+                    self.next();
+                }
+                "c" | "continue" => {
+                    self.r#continue();
+                }
+                "b" | "bp" | "break" => {
+                    if let Some(hex) = arg.strip_prefix("0x") {
+                        match u32::from_str_radix(hex, 16) {
+                            Err(e) => {
+                                eprintln!("ERROR: Failed to parse hex literal: {}", e)
+                            }
+                            Ok(v) => {
+                                eprintln!("Set breakpoint at 0x{:08X}", v);
+                                self.breakpoints.insert(v);
+                            }
+                        }
+                    } else {
+                        eprintln!("ERROR: Invalid usage of break command:");
+                        eprintln!(" b|bp|break <address>");
+                        eprintln!("But got argument: {}", arg)
+                    }
+                } 
+                "rb" | "delbreakpoint" | "db" => {
+                    if let Some(hex) = arg.strip_prefix("0x") {
+                        match u32::from_str_radix(hex, 16) {
+                            Err(e) => {
+                                eprintln!("ERROR: Failed to parse hex literal: {}", e)
+                            }
+                            Ok(v) => {
+                                if !self.breakpoints.remove(&v) {
+                                    eprintln!("ERROR: Breakpoint: 0x{:08X} does not exist",v);
+                                }
+                            }
+                        }
+                    } else {
+                        eprintln!("ERROR: Invalid usage of remove break command:");
+                        eprintln!(" rb|db|delbreakpoint <address>");
+                    }
+                }
+                "d" | "disasm" => {
+                    if let Some(hex) = arg.strip_prefix("0x") {
+                        match u32::from_str_radix(hex, 16) {
+                            Err(e) => {
+                                eprintln!("ERROR: Failed to parse hex literal: {}", e)
+                            }
+                            Ok(v) => {
+                                self.show(v as usize);
+                            }
+                        }
+                    } else {
+                        eprintln!("ERROR: Invalid usage of disasm command:");
+                        eprintln!(" d|disasm <address>");
+                    }
+                }
+                "t" | "translate" => {
+                    if let Some(hex) = arg.strip_prefix("0x") {
+                        match u32::from_str_radix(hex, 16) {
+                            Err(e) => {
+                                eprintln!("ERROR: Failed to parse hex literal: {}", e)
+                            }
+                            Ok(v) => self.translate(v),
+                        }
+                    } else {
+                        eprintln!("ERROR: Invalid usage of translate command:");
+                        eprintln!(" t|translate <address>");
+                    }
+                }
+                "irq" => {
+                    let mut parts = arg.split_whitespace();
+                    let controller = self.vm.regions.find_kind(Kind::Plic).map(|x| x.addr);
+                    match (parts.next().map(str::parse::<usize>), parts.next()) {
+                        _ if controller.is_none() => eprintln!("ERROR: This machine has no PLIC"),
+                        (Some(Ok(line)), Some(level @ ("0" | "1"))) if line > 0 && line < plic::SOURCES => {
+                            self.vm.set_line(controller.unwrap(), line, level == "1");
+                        }
+                        _ => {
+                            eprintln!("ERROR: Invalid usage of irq command:");
+                            eprintln!(" irq <line 1-{}> <0|1>", plic::SOURCES-1);
+                        }
+                    }
+                }
+                "q" | "quit" | "exit" => {
+                    break;
+                }
+                "i" | "info" => {
+                    match arg {
+                        "regs" => {
+                            eprintln!("IP={:08X}", self.vm.ip);
+                            for (i, reg) in self.vm.regs.iter().copied().enumerate() {
+                                if i > 0 {
+                                    eprint!(" ");
+                                    if i % 8 == 0 {
+                                        eprintln!()
+                                    }
+                                }
+                                eprint!("x{:<2}={:08X}", i, reg);
+                            }
+                            eprintln!()
+                        }
+                        "devices" => {
+                            for (region, state) in self.vm.regions.0.iter().zip(self.vm.snapshot_devices()) {
+                                eprint!("{:08X}-{:08X} {:?}", region.addr, region.addr+region.size-1, region.device.kind());
+                                if !state.is_empty() {
+                                    eprint!(" ");
+                                    for byte in state.iter().take(64) {
+                                        eprint!("{:02X}", byte);
+                                    }
+                                    if state.len() > 64 { eprint!("..."); }
+                                }
+                                eprintln!()
+                            }
+                        }
+                        _ => {
+                            eprintln!("ERROR: Invalid usage of info command with arg: {}", arg);
+                            eprintln!(" i|info <regs|devices>");
+                        }
+                    }
+                }
+                _ => eprintln!("Unknown cmd {}",cmd)
+            }
+            if self.vm.halt.is_some() {
+                break;
+            }
+            self.disasm();
+            eprint!(":");
+            io::stderr().flush().unwrap();
+            lastline = l;
+        }
     }
 }
@@ -0,0 +1,339 @@
+use crate::{disasm::Disasm32, inst::Inst32, region::{Memory, Region, RegionList}, vm::VM};
+
+// NOTE: Differential testing. Random RV32IMA programs are run on the VM and on the
+// reference executor below, and the architectural state is compared after every instruction.
+// The reference decodes from the raw bits on its own and shares no code with vm.rs or inst.rs,
+// so a bug has to be made twice, the same way, to slip through.
+//
+// Programs only branch and jump forward, so they always run off the end, and loads, stores
+// and atomics are all relative to x31, which is never written and points into the data window.
+// DIFFTEST_SEED=<n> reruns a single program, DIFFTEST_PROGRAMS=<n> runs more of them.
+const RAM_SIZE: usize = 0x2000;
+// Low enough that JALR off x0 reaches all of it
+const CODE: u32 = 0x100;
+const MAX_LEN: u32 = 256;
+const DATA: u32 = 0x1000;
+// Everything x31 +- a 12 bit offset can reach, plus the bytes a word access can spill into
+const WINDOW: std::ops::Range<usize> = (DATA as usize - 2048)..(DATA as usize + 2048 + 4);
+const BASE: u32 = 31;
+const PROGRAMS: u64 = 200;
+
+// xorshift64*, good enough to pick instructions with
+struct Rng(u64);
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E3779B97F4A7C15) | 1)
+    }
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545F4914F6CDD1D) >> 32) as u32
+    }
+    fn below(&mut self, n: u32) -> u32 {
+        self.next() % n
+    }
+    fn pick<T: Copy>(&mut self, from: &[T]) -> T {
+        from[self.below(from.len() as u32) as usize]
+    }
+    // Register values that tend to find edge cases
+    fn value(&mut self) -> u32 {
+        match self.below(8) {
+            0 => 0,
+            1 => self.pick(&[1, u32::MAX, 0x8000_0000, 0x7FFF_FFFF, 0xFFFF_8000, 0x8000]),
+            2 => self.below(64).wrapping_sub(32),
+            _ => self.next(),
+        }
+    }
+}
+
+// Encoders for the base instruction formats
+fn r_type(opcode: u32, funct3: u32, funct7: u32, rd: u32, rs1: u32, rs2: u32) -> u32 {
+    funct7 << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+fn i_type(opcode: u32, funct3: u32, rd: u32, rs1: u32, imm: u32) -> u32 {
+    (imm & 0xFFF) << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+fn s_type(funct3: u32, rs1: u32, rs2: u32, imm: u32) -> u32 {
+    (imm >> 5 & 0x7F) << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | (imm & 0x1F) << 7 | 0x23
+}
+fn b_type(funct3: u32, rs1: u32, rs2: u32, imm: u32) -> u32 {
+    (imm >> 12 & 1) << 31 | (imm >> 5 & 0x3F) << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12
+        | (imm >> 1 & 0xF) << 8 | (imm >> 11 & 1) << 7 | 0x63
+}
+fn j_type(rd: u32, imm: u32) -> u32 {
+    (imm >> 20 & 1) << 31 | (imm >> 1 & 0x3FF) << 21 | (imm >> 11 & 1) << 20 | (imm >> 12 & 0xFF) << 12 | rd << 7 | 0x6F
+}
+
+fn program(rng: &mut Rng) -> Vec<u32> {
+    let len = 16 + rng.below(MAX_LEN - 16);
+    let mut code = Vec::new();
+    while (code.len() as u32) < len {
+        let at = code.len() as u32;
+        // Anything but x31, x0 now and then to check writes to it are dropped
+        let rd = rng.below(31);
+        let rs1 = rng.below(32);
+        let rs2 = rng.below(32);
+        let imm = rng.next() & 0xFFF;
+        // A forward offset that lands at most on the end of the program
+        let forward = |rng: &mut Rng, from: u32| 4 * (1 + rng.below(16)).min(len - from);
+        let inst = match rng.below(16) {
+            0..=3 => {
+                let (funct3, funct7) = rng.pick(&[(0, 0), (0, 0x20), (1, 0), (2, 0), (3, 0), (4, 0), (5, 0), (5, 0x20), (6, 0), (7, 0)]);
+                r_type(0x33, funct3, funct7, rd, rs1, rs2)
+            }
+            4 => r_type(0x33, rng.below(8), 1, rd, rs1, rs2),
+            5..=7 => match rng.below(8) {
+                1 => i_type(0x13, 1, rd, rs1, rng.below(32)),
+                5 => i_type(0x13, 5, rd, rs1, rng.pick(&[0, 0x400]) | rng.below(32)),
+                funct3 => i_type(0x13, funct3, rd, rs1, imm),
+            },
+            8 => rng.next() & !0xFFF | rd << 7 | rng.pick(&[0x37, 0x17]),
+            9 => i_type(0x03, rng.pick(&[0, 1, 2, 4, 5]), rd, BASE, imm),
+            10 => s_type(rng.below(3), BASE, rs2, imm),
+            11 => {
+                let funct3 = rng.pick(&[0, 1, 4, 5, 6, 7]);
+                b_type(funct3, rs1, rs2, forward(rng, at))
+            }
+            12 => j_type(rd, forward(rng, at)),
+            // Off x0 since any other register could be stale if a branch skipped the setup,
+            // the low bit is set now and then since JALR has to clear it
+            13 => i_type(0x67, 0, rd, 0, (CODE + 4*at + forward(rng, at)) | rng.below(2)),
+            14 => {
+                let aqrl = rng.below(4);
+                match rng.pick(&[0x02, 0x03, 0x01, 0x00, 0x04, 0x0C, 0x08, 0x10, 0x14, 0x18, 0x1C]) {
+                    0x02 => r_type(0x2F, 2, 0x02 << 2 | aqrl, rd, BASE, 0),
+                    funct5 => r_type(0x2F, 2, funct5 << 2 | aqrl, rd, BASE, rs2),
+                }
+            }
+            _ => i_type(0x0F, 0, 0, 0, 0x0FF),
+        };
+        code.push(inst);
+    }
+    code
+}
+
+// The reference executor, kept as plain as possible
+struct Reference {
+    pc: u32,
+    x: [u32; 32],
+    mem: Vec<u8>,
+    reservation: Option<u32>,
+}
+impl Reference {
+    fn load(&self, addr: u32, n: u32) -> u32 {
+        (0..n).fold(0, |v, i| v | (self.mem[(addr + i) as usize] as u32) << (8 * i))
+    }
+    fn store(&mut self, addr: u32, n: u32, v: u32) {
+        for i in 0..n {
+            self.mem[(addr + i) as usize] = (v >> (8 * i)) as u8;
+        }
+    }
+    fn step(&mut self) {
+        let inst = self.load(self.pc, 4);
+        let rd = (inst >> 7 & 31) as usize;
+        let a = self.x[(inst >> 15 & 31) as usize];
+        let b = self.x[(inst >> 20 & 31) as usize];
+        let funct3 = inst >> 12 & 7;
+        let funct7 = inst >> 25;
+        let imm_i = (inst as i32 >> 20) as u32;
+        let imm_s = (inst as i32 >> 25 << 5) as u32 | (inst >> 7 & 0x1F);
+        let imm_b = (inst as i32 >> 31 << 12) as u32 | (inst << 4 & 0x800) | (inst >> 20 & 0x7E0) | (inst >> 7 & 0x1E);
+        let imm_j = (inst as i32 >> 31 << 20) as u32 | (inst & 0xFF000) | (inst >> 9 & 0x800) | (inst >> 20 & 0x7FE);
+        let (sa, sb) = (a as i32, b as i32);
+        let mut next = self.pc.wrapping_add(4);
+        let mut out = None;
+        match inst & 0x7F {
+            0x37 => out = Some(inst & 0xFFFFF000),
+            0x17 => out = Some(self.pc.wrapping_add(inst & 0xFFFFF000)),
+            0x6F => { out = Some(next); next = self.pc.wrapping_add(imm_j); }
+            0x67 => { out = Some(next); next = a.wrapping_add(imm_i) & !1; }
+            0x63 => {
+                let taken = match funct3 {
+                    0 => a == b,
+                    1 => a != b,
+                    4 => sa < sb,
+                    5 => sa >= sb,
+                    6 => a < b,
+                    7 => a >= b,
+                    _ => panic!("reference: bad branch {:08x}", inst),
+                };
+                if taken { next = self.pc.wrapping_add(imm_b); }
+            }
+            0x03 => {
+                let addr = a.wrapping_add(imm_i);
+                out = Some(match funct3 {
+                    0 => self.load(addr, 1) as i8 as u32,
+                    1 => self.load(addr, 2) as i16 as u32,
+                    2 => self.load(addr, 4),
+                    4 => self.load(addr, 1),
+                    5 => self.load(addr, 2),
+                    _ => panic!("reference: bad load {:08x}", inst),
+                });
+            }
+            0x23 => self.store(a.wrapping_add(imm_s), 1 << funct3, b),
+            0x13 => {
+                let shamt = imm_i & 31;
+                out = Some(match funct3 {
+                    0 => a.wrapping_add(imm_i),
+                    1 => a << shamt,
+                    2 => (sa < imm_i as i32) as u32,
+                    3 => (a < imm_i) as u32,
+                    4 => a ^ imm_i,
+                    5 if funct7 == 0x20 => (sa >> shamt) as u32,
+                    5 => a >> shamt,
+                    6 => a | imm_i,
+                    _ => a & imm_i,
+                });
+            }
+            0x33 if funct7 == 1 => {
+                let (wa, wb) = (sa as i64, sb as i64);
+                out = Some(match funct3 {
+                    0 => (wa * wb) as u32,
+                    1 => ((wa * wb) >> 32) as u32,
+                    2 => ((wa * b as i64) >> 32) as u32,
+                    3 => ((a as u64 * b as u64) >> 32) as u32,
+                    4 if b == 0 => u32::MAX,
+                    4 => (wa / wb) as u32,
+                    5 if b == 0 => u32::MAX,
+                    5 => a / b,
+                    6 if b == 0 => a,
+                    6 => (wa % wb) as u32,
+                    _ if b == 0 => a,
+                    _ => a % b,
+                });
+            }
+            0x33 => {
+                let shamt = b & 31;
+                out = Some(match (funct3, funct7) {
+                    (0, 0x20) => a.wrapping_sub(b),
+                    (0, _) => a.wrapping_add(b),
+                    (1, _) => a << shamt,
+                    (2, _) => (sa < sb) as u32,
+                    (3, _) => (a < b) as u32,
+                    (4, _) => a ^ b,
+                    (5, 0x20) => (sa >> shamt) as u32,
+                    (5, _) => a >> shamt,
+                    (6, _) => a | b,
+                    _ => a & b,
+                });
+            }
+            0x0F => {}
+            0x2F => {
+                let old = self.load(a, 4);
+                out = Some(old);
+                match funct7 >> 2 {
+                    0x02 => self.reservation = Some(a),
+                    0x03 => {
+                        let ok = self.reservation.take() == Some(a);
+                        if ok { self.store(a, 4, b); }
+                        out = Some(!ok as u32);
+                    }
+                    funct5 => {
+                        let v = match funct5 {
+                            0x01 => b,
+                            0x00 => old.wrapping_add(b),
+                            0x04 => old ^ b,
+                            0x0C => old & b,
+                            0x08 => old | b,
+                            0x10 => (old as i32).min(sb) as u32,
+                            0x14 => (old as i32).max(sb) as u32,
+                            0x18 => old.min(b),
+                            0x1C => old.max(b),
+                            _ => panic!("reference: bad atomic {:08x}", inst),
+                        };
+                        self.store(a, 4, v);
+                    }
+                }
+            }
+            _ => panic!("reference: can't execute {:08x}", inst),
+        }
+        if let Some(v) = out {
+            if rd != 0 { self.x[rd] = v; }
+        }
+        self.pc = next;
+    }
+}
+
+// What differs between the VM and the reference, empty if nothing does
+fn compare(vm: &mut VM, re: &Reference, window: std::ops::Range<usize>) -> Vec<String> {
+    let mut diffs = Vec::new();
+    if vm.ip as u32 != re.pc {
+        diffs.push(format!("pc: vm 0x{:08x} reference 0x{:08x} (mcause {})", vm.ip as u32, re.pc, vm.csr.mcause));
+    }
+    for i in 0..32 {
+        if vm.get_reg(i) as u32 != re.x[i] {
+            diffs.push(format!("x{}: vm 0x{:08x} reference 0x{:08x}", i, vm.get_reg(i) as u32, re.x[i]));
+        }
+    }
+    if vm.reservation != re.reservation {
+        diffs.push(format!("reservation: vm {:x?} reference {:x?}", vm.reservation, re.reservation));
+    }
+    let mem = vm.memory(window.start, window.len()).unwrap();
+    for (i, addr) in window.enumerate().filter(|&(i, x)| mem[i] != re.mem[x]) {
+        diffs.push(format!("[0x{:08x}]: vm 0x{:02x} reference 0x{:02x}", addr, mem[i], re.mem[addr]));
+    }
+    diffs
+}
+
+// Runs one program, returning where it first diverged
+fn run(seed: u64) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    let code = program(&mut rng);
+    let end = CODE + 4 * code.len() as u32;
+    let mut ram = vec![0; RAM_SIZE];
+    for (i, inst) in code.iter().enumerate() {
+        let at = CODE as usize + 4 * i;
+        ram[at..at+4].copy_from_slice(&inst.to_le_bytes());
+    }
+    for byte in &mut ram[WINDOW] {
+        *byte = rng.next() as u8;
+    }
+    let mut x = [0; 32];
+    for reg in &mut x[1..31] {
+        *reg = rng.value();
+    }
+    x[BASE as usize] = DATA;
+    let mut re = Reference { pc: CODE, x, mem: ram.clone(), reservation: None };
+    let regions = RegionList(vec![Region { device: Box::new(Memory::new(ram)), addr: 0, size: RAM_SIZE, latency: 0 }].into_boxed_slice());
+    let mut vm = VM::new(regions);
+    vm.ip = CODE as i32;
+    for (i, &v) in x.iter().enumerate() {
+        vm.set_reg(i, v as i32);
+    }
+    let mut step = 0;
+    while re.pc != end {
+        let pc = re.pc;
+        let inst = re.load(pc, 4);
+        vm.run();
+        re.step();
+        let diffs = compare(&mut vm, &re, WINDOW);
+        if !diffs.is_empty() {
+            return Err(format!("seed {} diverged at step {}\n  0x{:08x}: 0x{:08x} {}\n  {}\nrerun with DIFFTEST_SEED={}",
+                seed, step, pc, inst, Disasm32(Inst32::new(inst)), diffs.join("\n  "), seed));
+        }
+        step += 1;
+        // Only possible if both went backwards the same way
+        if step > code.len() {
+            return Err(format!("seed {} didn't run off the end", seed));
+        }
+    }
+    match compare(&mut vm, &re, 0..RAM_SIZE) {
+        diffs if diffs.is_empty() => Ok(()),
+        diffs => Err(format!("seed {} memory differs at the end\n  {}", seed, diffs.join("\n  "))),
+    }
+}
+
+#[test]
+fn random_programs() {
+    let env = |name| std::env::var(name).ok().map(|x: String| x.parse::<u64>().expect(name));
+    let seeds = match env("DIFFTEST_SEED") {
+        Some(seed) => seed..seed+1,
+        None => 0..env("DIFFTEST_PROGRAMS").unwrap_or(PROGRAMS),
+    };
+    for seed in seeds {
+        if let Err(diff) = run(seed) {
+            panic!("{}", diff);
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use core::fmt;
 
-use crate::{inst::Inst32, off::Off32, ops::{self, branch, imm_math, jump_reg, load, reg_math, store}};
+use crate::{inst::Inst32, off::Off32, ops::{self, amo, branch, imm_math, jump_reg, load, misc_mem, reg_math, store, system}};
 
 pub struct Disasm32(pub Inst32);
 impl fmt::Display for Disasm32 {
@@ -18,9 +18,17 @@ impl fmt::Display for Disasm32 {
             ops::IMM_MATH_OP => {
                 write!(f,
                     "{} x{}, x{}, {}",
-                    match inst.funct3() {
-                        imm_math::ADDI => "addi",
-                        funct3 => return write!(f, "Undisassemblable Immediate math op 0x{:01X}",funct3)
+                    match (inst.funct3(), inst.funct7()) {
+                        (imm_math::ADDI , _) => "addi",
+                        (imm_math::SLTI , _) => "slti",
+                        (imm_math::SLTIU, _) => "sltiu",
+                        (imm_math::XORI , _) => "xori",
+                        (imm_math::ORI  , _) => "ori",
+                        (imm_math::ANDI , _) => "andi",
+                        (imm_math::SLLI , 0) => return write!(f, "slli x{}, x{}, {}", inst.rd(), inst.r1(), inst.r2()),
+                        (imm_math::SRLI , 0) => return write!(f, "srli x{}, x{}, {}", inst.rd(), inst.r1(), inst.r2()),
+                        (imm_math::SRLI , imm_math::ARITH_SHIFT) => return write!(f, "srai x{}, x{}, {}", inst.rd(), inst.r1(), inst.r2()),
+                        (funct3, _) => return write!(f, "Undisassemblable Immediate math op 0x{:01X}",funct3)
                     }, inst.rd(), inst.r1(), inst.imm_I()
                 )
             }
@@ -28,7 +36,24 @@ impl fmt::Display for Disasm32 {
                 write!(f,
                     "{} x{}, x{}, x{}",
                     match (inst.funct3(), inst.funct7()) {
-                        reg_math::ADD => "add",
+                        reg_math::ADD  => "add",
+                        reg_math::SUB  => "sub",
+                        reg_math::SLL  => "sll",
+                        reg_math::SLT  => "slt",
+                        reg_math::SLTU => "sltu",
+                        reg_math::XOR  => "xor",
+                        reg_math::SRL  => "srl",
+                        reg_math::SRA  => "sra",
+                        reg_math::OR   => "or",
+                        reg_math::AND  => "and",
+                        reg_math::MUL    => "mul",
+                        reg_math::MULH   => "mulh",
+                        reg_math::MULHSU => "mulhsu",
+                        reg_math::MULHU  => "mulhu",
+                        reg_math::DIV  => "div",
+                        reg_math::DIVU => "divu",
+                        reg_math::REM  => "rem",
+                        reg_math::REMU => "remu",
                         (funct3, funct7) => return write!(f, "Undisassemblable Register math op funct3=0x{:01X} funct7=0x{:02X}", funct3, funct7)
                     }, inst.rd(), inst.r1(), inst.r2()
                 )
@@ -84,6 +109,52 @@ impl fmt::Display for Disasm32 {
                     }, inst.r1(), inst.imm_I()
                 )
             }
+            ops::AMO_OP if inst.funct3() == amo::W => {
+                let name = match inst.funct7() >> 2 {
+                    amo::LR => return write!(f, "lr.w x{}, (x{})", inst.rd(), inst.r1()),
+                    amo::SC   => "sc.w",
+                    amo::SWAP => "amoswap.w",
+                    amo::ADD  => "amoadd.w",
+                    amo::XOR  => "amoxor.w",
+                    amo::AND  => "amoand.w",
+                    amo::OR   => "amoor.w",
+                    amo::MIN  => "amomin.w",
+                    amo::MAX  => "amomax.w",
+                    amo::MINU => "amominu.w",
+                    amo::MAXU => "amomaxu.w",
+                    funct5 => return write!(f, "Undisassemblable atomic op funct5=0x{:02X}", funct5)
+                };
+                write!(f, "{} x{}, x{}, (x{})", name, inst.rd(), inst.r2(), inst.r1())
+            }
+            ops::MISC_MEM_OP => {
+                match inst.funct3() {
+                    misc_mem::FENCE   => write!(f, "fence"),
+                    misc_mem::FENCE_I => write!(f, "fence.i"),
+                    funct3 => write!(f, "Undisassemblable fence op funct3=0x{:01X}", funct3)
+                }
+            }
+            ops::SYSTEM_OP => {
+                match (inst.funct3(), inst.funct12()) {
+                    (system::PRIV, system::ECALL)  => write!(f, "ecall"),
+                    (system::PRIV, system::EBREAK) => write!(f, "ebreak"),
+                    (system::PRIV, _) if inst.funct7() == system::SFENCE_VMA => write!(f, "sfence.vma x{}, x{}", inst.r1(), inst.r2()),
+                    (system::PRIV, system::SRET)   => write!(f, "sret"),
+                    (system::PRIV, system::MRET)   => write!(f, "mret"),
+                    (system::PRIV, system::WFI)    => write!(f, "wfi"),
+                    (system::PRIV, funct12) => write!(f, "Undisassemblable system op funct12=0x{:03X}", funct12),
+                    (funct3 @ (system::CSRRW | system::CSRRS | system::CSRRC), csr) => write!(f,
+                        "{} x{}, 0x{:03X}, x{}",
+                        match funct3 { system::CSRRW => "csrrw", system::CSRRS => "csrrs", _ => "csrrc" },
+                        inst.rd(), csr, inst.r1()
+                    ),
+                    (funct3 @ (system::CSRRWI | system::CSRRSI | system::CSRRCI), csr) => write!(f,
+                        "{} x{}, 0x{:03X}, {}",
+                        match funct3 { system::CSRRWI => "csrrwi", system::CSRRSI => "csrrsi", _ => "csrrci" },
+                        inst.rd(), csr, inst.r1()
+                    ),
+                    (funct3, _) => write!(f, "Undisassemblable system op funct3=0x{:01X}", funct3)
+                }
+            }
             op => write!(f, "Undisassemblable Opcode {:07b}",op),
         }
     }
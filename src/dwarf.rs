@@ -0,0 +1,77 @@
+use gimli::{Dwarf, EndianSlice, LittleEndian};
+
+use crate::elf::Elf;
+
+#[derive(Clone, Copy)]
+pub struct Line {
+    pub addr: u32,
+    pub file: usize,
+    pub line: u32,
+}
+// Address to source line mapping built from .debug_line.
+// Rows are sorted by address, and each one covers everything up to the next row.
+pub struct LineTable {
+    pub files: Vec<String>,
+    pub rows: Vec<Line>,
+}
+// NOTE: Marks the end of a sequence, which covers no source line
+const END_SEQUENCE: usize = usize::MAX;
+impl LineTable {
+    pub fn load(elf: &Elf, data: &[u8]) -> Result<Self, gimli::Error> {
+        let dwarf = Dwarf::load(|id| -> Result<_, gimli::Error> {
+            Ok(EndianSlice::new(elf.section(data, id.name()).unwrap_or(&[]), LittleEndian))
+        })?;
+        let mut files: Vec<String> = Vec::new();
+        let mut rows = Vec::new();
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let Some(program) = unit.line_program.clone() else { continue; };
+            let comp_dir = unit.comp_dir.map(|x| x.to_string_lossy().into_owned());
+            let mut program = program.rows();
+            while let Some((header, row)) = program.next_row()? {
+                let addr = row.address() as u32;
+                if row.end_sequence() {
+                    rows.push(Line { addr, file: END_SEQUENCE, line: 0 });
+                    continue;
+                }
+                let (Some(file), Some(line)) = (row.file(header), row.line()) else { continue; };
+                let mut path = String::new();
+                if let Some(dir) = file.directory(header) {
+                    path = dwarf.attr_string(&unit, dir)?.to_string_lossy().into_owned();
+                }
+                if !path.starts_with('/') {
+                    if let Some(comp_dir) = &comp_dir {
+                        path = if path.is_empty() { comp_dir.clone() } else { format!("{}/{}", comp_dir, path) };
+                    }
+                }
+                let name = dwarf.attr_string(&unit, file.path_name())?.to_string_lossy().into_owned();
+                if name.starts_with('/') || path.is_empty() {
+                    path = name;
+                } else {
+                    path = format!("{}/{}", path, name);
+                }
+                let file = match files.iter().position(|x| *x == path) {
+                    Some(i) => i,
+                    None => {
+                        files.push(path);
+                        files.len()-1
+                    }
+                };
+                rows.push(Line { addr, file, line: line.get() as u32 });
+            }
+        }
+        // An end of sequence has to precede a sequence starting at the same address
+        rows.sort_by_key(|x| (x.addr, x.file != END_SEQUENCE));
+        Ok(Self { files, rows })
+    }
+    pub fn lookup(&self, addr: u32) -> Option<Line> {
+        let i = self.rows.partition_point(|x| x.addr <= addr).checked_sub(1)?;
+        let row = self.rows[i];
+        if row.file == END_SEQUENCE { None } else { Some(row) }
+    }
+    // Address ranges of every row, along with the line they belong to
+    pub fn ranges(&self) -> impl Iterator<Item = (Line, u32)> + '_ {
+        self.rows.windows(2).filter(|x| x[0].file != END_SEQUENCE).map(|x| (x[0], x[1].addr))
+    }
+}
@@ -0,0 +1,181 @@
+// NOTE: Only what's needed to run a statically linked ELF32 RISC-V image:
+// the PT_LOAD segments, the entry point and the symbol table.
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const STT_NOTYPE: u8 = 0;
+const STT_FUNC: u8 = 2;
+
+pub fn is_elf(data: &[u8]) -> bool {
+    data.starts_with(b"\x7fELF")
+}
+const MALFORMED: &str = "Truncated or malformed ELF";
+fn u8_at(data: &[u8], off: usize) -> Result<u8, &'static str> {
+    data.get(off).copied().ok_or(MALFORMED)
+}
+fn u16_at(data: &[u8], off: usize) -> Result<u16, &'static str> {
+    Ok(u16::from_le_bytes([u8_at(data, off)?, u8_at(data, off+1)?]))
+}
+fn u32_at(data: &[u8], off: usize) -> Result<u32, &'static str> {
+    Ok(u16_at(data, off)? as u32 | ((u16_at(data, off+2)? as u32) << 16))
+}
+fn str_at(data: &[u8], off: usize) -> Result<&str, &'static str> {
+    let bytes = data.get(off..).ok_or(MALFORMED)?;
+    let len = bytes.iter().position(|&b| b == 0).ok_or(MALFORMED)?;
+    std::str::from_utf8(&bytes[..len]).map_err(|_| MALFORMED)
+}
+
+pub struct Segment {
+    pub addr: u32,
+    pub offset: usize,
+    pub filesz: usize,
+    pub memsz: usize,
+}
+pub struct Section {
+    pub name: String,
+    pub kind: u32,
+    pub offset: usize,
+    pub size: usize,
+    pub link: usize,
+}
+#[derive(Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub addr: u32,
+    pub size: u32,
+}
+#[derive(Default)]
+pub struct Symbols(pub Vec<Symbol>);
+impl Symbols {
+    pub fn lookup(&self, addr: u32) -> Option<&Symbol> {
+        let i = self.0.partition_point(|x| x.addr <= addr).checked_sub(1)?;
+        let sym = &self.0[i];
+        if sym.size == 0 || addr - sym.addr < sym.size { Some(sym) } else { None }
+    }
+    pub fn name(&self, addr: u32) -> String {
+        match self.lookup(addr) {
+            Some(sym) => sym.name.clone(),
+            None => format!("0x{:08x}", addr),
+        }
+    }
+}
+
+pub struct Elf {
+    pub entry: u32,
+    // Where the program headers end up in memory, if a segment covers them
+    pub phdr: Option<u32>,
+    pub phentsize: usize,
+    pub phnum: usize,
+    pub segments: Vec<Segment>,
+    pub sections: Vec<Section>,
+    pub symbols: Symbols,
+}
+impl Elf {
+    pub fn parse(data: &[u8]) -> Result<Self, &'static str> {
+        if !is_elf(data) { return Err("Not an ELF file"); }
+        // EI_CLASS == ELFCLASS32, EI_DATA == ELFDATA2LSB
+        if u8_at(data, 4)? != 1 || u8_at(data, 5)? != 1 {
+            return Err("Only little endian ELF32 is supported");
+        }
+        if u16_at(data, 18)? != EM_RISCV {
+            return Err("Not a RISC-V ELF");
+        }
+        let entry = u32_at(data, 24)?;
+        let phoff = u32_at(data, 28)? as usize;
+        let shoff = u32_at(data, 32)? as usize;
+        let phentsize = u16_at(data, 42)? as usize;
+        let phnum = u16_at(data, 44)? as usize;
+        let shentsize = u16_at(data, 46)? as usize;
+        let shnum = u16_at(data, 48)? as usize;
+        let shstrndx = u16_at(data, 50)? as usize;
+
+        let mut segments = Vec::new();
+        for i in 0..phnum {
+            let ph = phoff + i*phentsize;
+            if u32_at(data, ph)? != PT_LOAD { continue; }
+            let seg = Segment {
+                offset: u32_at(data, ph+4)? as usize,
+                addr: u32_at(data, ph+12)?,
+                filesz: u32_at(data, ph+16)? as usize,
+                memsz: u32_at(data, ph+20)? as usize,
+            };
+            if seg.filesz > seg.memsz || data.get(seg.offset..seg.offset+seg.filesz).is_none() {
+                return Err(MALFORMED);
+            }
+            segments.push(seg);
+        }
+        let phdr = segments.iter()
+            .find(|x| x.offset <= phoff && phoff < x.offset + x.filesz)
+            .map(|x| x.addr + (phoff - x.offset) as u32);
+
+        let mut sections = Vec::new();
+        for i in 0..shnum {
+            let sh = shoff + i*shentsize;
+            sections.push(Section {
+                name: String::new(),
+                kind: u32_at(data, sh+4)?,
+                offset: u32_at(data, sh+16)? as usize,
+                size: u32_at(data, sh+20)? as usize,
+                link: u32_at(data, sh+24)? as usize,
+            });
+        }
+        if let Some(shstr) = sections.get(shstrndx).map(|x| x.offset) {
+            for (i, section) in sections.iter_mut().enumerate() {
+                let name = u32_at(data, shoff + i*shentsize)? as usize;
+                section.name = str_at(data, shstr+name)?.to_string();
+            }
+        }
+
+        let mut symbols = Vec::new();
+        for symtab in sections.iter().filter(|x| x.kind == SHT_SYMTAB) {
+            let strtab = sections.get(symtab.link).ok_or(MALFORMED)?.offset;
+            for sym in (symtab.offset..symtab.offset+symtab.size).step_by(16) {
+                let kind = u8_at(data, sym+12)? & 0xF;
+                let shndx = u16_at(data, sym+14)?;
+                // Skip undefined symbols and anything that isn't code or a plain label
+                if shndx == 0 || (kind != STT_FUNC && kind != STT_NOTYPE) { continue; }
+                let name = str_at(data, strtab + u32_at(data, sym)? as usize)?;
+                // Local labels and mapping symbols
+                if name.is_empty() || name.starts_with(".L") || name.starts_with('$') { continue; }
+                symbols.push(Symbol { name: name.to_string(), addr: u32_at(data, sym+4)?, size: u32_at(data, sym+8)? });
+            }
+        }
+        symbols.sort_by_key(|x| x.addr);
+        symbols.dedup_by_key(|x| x.addr);
+        Ok(Self { entry, phdr, phentsize, phnum, segments, sections, symbols: Symbols(symbols) })
+    }
+    // Lays the loadable segments out as a flat image starting at `base`.
+    // The sizes come from the file, so nothing past the `size` bytes of RAM is allocated.
+    pub fn load(&self, data: &[u8], base: usize, size: usize) -> Result<Vec<u8>, &'static str> {
+        if self.segments.iter().any(|x| (x.addr as usize) < base) {
+            return Err("Segment is below the start of RAM");
+        }
+        if self.segments.iter().any(|x| x.addr as usize + x.memsz > base + size) {
+            return Err("Segment doesn't fit in RAM");
+        }
+        let end = self.segments.iter().map(|x| x.addr as usize + x.memsz - base).max().unwrap_or(0);
+        let mut ram = vec![0; end];
+        for seg in self.segments.iter() {
+            let addr = seg.addr as usize - base;
+            ram[addr..addr+seg.filesz].copy_from_slice(&data[seg.offset..seg.offset+seg.filesz]);
+        }
+        Ok(ram)
+    }
+    // Any defined symbol by name, data included, unlike the code symbols kept in `symbols`
+    pub fn symbol(&self, data: &[u8], name: &str) -> Option<u32> {
+        for symtab in self.sections.iter().filter(|x| x.kind == SHT_SYMTAB) {
+            let strtab = self.sections.get(symtab.link)?.offset;
+            for sym in (symtab.offset..symtab.offset+symtab.size).step_by(16) {
+                if u16_at(data, sym+14).ok()? == 0 { continue; }
+                if str_at(data, strtab + u32_at(data, sym).ok()? as usize).ok()? == name {
+                    return u32_at(data, sym+4).ok();
+                }
+            }
+        }
+        None
+    }
+    pub fn section<'d>(&self, data: &'d [u8], name: &str) -> Option<&'d [u8]> {
+        let section = self.sections.iter().find(|x| x.name == name)?;
+        data.get(section.offset..section.offset+section.size)
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+
+use crate::{csr, plic, region::{Kind, RegionList}};
+
+// NOTE: Chapter 5 of the devicetree specification for the blob format
+const MAGIC: u32 = 0xD00DFEED;
+const BEGIN_NODE: u32 = 1;
+const END_NODE: u32 = 2;
+const PROP: u32 = 3;
+const END: u32 = 9;
+const HEADER_SIZE: usize = 40;
+// Empty memory reservation map, a single terminating entry
+const RSVMAP_SIZE: usize = 16;
+
+#[derive(Default)]
+pub struct Fdt {
+    structure: Vec<u8>,
+    strings: Vec<u8>,
+    names: HashMap<String, u32>,
+}
+impl Fdt {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    fn u32(&mut self, v: u32) {
+        self.structure.extend_from_slice(&v.to_be_bytes());
+    }
+    fn pad(&mut self) {
+        while !self.structure.len().is_multiple_of(4) { self.structure.push(0); }
+    }
+    pub fn begin(&mut self, name: &str) {
+        self.u32(BEGIN_NODE);
+        self.structure.extend_from_slice(name.as_bytes());
+        self.structure.push(0);
+        self.pad();
+    }
+    pub fn end(&mut self) {
+        self.u32(END_NODE);
+    }
+    pub fn prop(&mut self, name: &str, value: &[u8]) {
+        let off = match self.names.get(name) {
+            Some(&v) => v,
+            None => {
+                let off = self.strings.len() as u32;
+                self.strings.extend_from_slice(name.as_bytes());
+                self.strings.push(0);
+                self.names.insert(name.to_string(), off);
+                off
+            }
+        };
+        self.u32(PROP);
+        self.u32(value.len() as u32);
+        self.u32(off);
+        self.structure.extend_from_slice(value);
+        self.pad();
+    }
+    pub fn prop_cells(&mut self, name: &str, cells: &[u32]) {
+        let value: Vec<u8> = cells.iter().flat_map(|x| x.to_be_bytes()).collect();
+        self.prop(name, &value);
+    }
+    pub fn prop_u32(&mut self, name: &str, v: u32) {
+        self.prop_cells(name, &[v]);
+    }
+    pub fn prop_strs(&mut self, name: &str, strs: &[&str]) {
+        let value: Vec<u8> = strs.iter().flat_map(|x| x.bytes().chain([0])).collect();
+        self.prop(name, &value);
+    }
+    pub fn prop_str(&mut self, name: &str, s: &str) {
+        self.prop_strs(name, &[s]);
+    }
+    pub fn finish(mut self) -> Vec<u8> {
+        self.u32(END);
+        let off_rsvmap = HEADER_SIZE;
+        let off_struct = off_rsvmap + RSVMAP_SIZE;
+        let off_strings = off_struct + self.structure.len();
+        let total = off_strings + self.strings.len();
+        let header = [
+            MAGIC, total as u32, off_struct as u32, off_strings as u32, off_rsvmap as u32,
+            // Version 17, compatible back to 16
+            17, 16,
+            // Boot hart
+            0,
+            self.strings.len() as u32, self.structure.len() as u32,
+        ];
+        let mut blob: Vec<u8> = header.iter().flat_map(|x| x.to_be_bytes()).collect();
+        blob.resize(off_struct, 0);
+        blob.extend_from_slice(&self.structure);
+        blob.extend_from_slice(&self.strings);
+        blob
+    }
+}
+
+// Standard extensions in canonical order, from misa
+fn isa_string() -> String {
+    let mut isa = "rv32".to_string();
+    for c in "IEMAFDQC".chars() {
+        if csr::MISA_VALUE & (1 << (c as u32 - 'A' as u32)) != 0 {
+            isa.push(c.to_ascii_lowercase());
+        }
+    }
+    isa + "_zicsr_zifencei"
+}
+// Addresses and sizes are two cells each, like on QEMU
+fn reg(addr: usize, size: usize) -> [u32; 4] {
+    [(addr as u64 >> 32) as u32, addr as u32, (size as u64 >> 32) as u32, size as u32]
+}
+
+const CPU_INTC: u32 = 1;
+const PLIC: u32 = 2;
+const FINISHER: u32 = 3;
+// Describes the machine laid out in `layout` with a single hart
+pub fn generate(layout: &RegionList, timebase: u64, uart_irq: usize) -> Vec<u8> {
+    let mut fdt = Fdt::new();
+    fdt.begin("");
+    fdt.prop_u32("#address-cells", 2);
+    fdt.prop_u32("#size-cells", 2);
+    fdt.prop_str("compatible", "riscv-virtio");
+    fdt.prop_str("model", "riscv-virtio,qemu");
+
+    fdt.begin("chosen");
+    if let Some(uart) = layout.0.iter().find(|x| x.device.kind() == Kind::Uart) {
+        fdt.prop_str("stdout-path", &format!("/soc/serial@{:x}", uart.addr));
+    }
+    fdt.end();
+
+    fdt.begin("cpus");
+    fdt.prop_u32("#address-cells", 1);
+    fdt.prop_u32("#size-cells", 0);
+    fdt.prop_u32("timebase-frequency", timebase as u32);
+    fdt.begin("cpu@0");
+    fdt.prop_str("device_type", "cpu");
+    fdt.prop_u32("reg", 0);
+    fdt.prop_str("status", "okay");
+    fdt.prop_str("compatible", "riscv");
+    fdt.prop_str("riscv,isa", &isa_string());
+    fdt.prop_str("mmu-type", "riscv,sv32");
+    fdt.begin("interrupt-controller");
+    fdt.prop_u32("#interrupt-cells", 1);
+    fdt.prop("interrupt-controller", &[]);
+    fdt.prop_str("compatible", "riscv,cpu-intc");
+    fdt.prop_u32("phandle", CPU_INTC);
+    fdt.end();
+    fdt.end();
+    fdt.end();
+
+    let memory: Vec<u32> = layout.0.iter().filter(|x| x.device.kind() == Kind::Memory).flat_map(|x| reg(x.addr, x.size)).collect();
+    if !memory.is_empty() {
+        let base = layout.0.iter().find(|x| x.device.kind() == Kind::Memory).unwrap().addr;
+        fdt.begin(&format!("memory@{:x}", base));
+        fdt.prop_str("device_type", "memory");
+        fdt.prop_cells("reg", &memory);
+        fdt.end();
+    }
+
+    fdt.begin("soc");
+    fdt.prop_u32("#address-cells", 2);
+    fdt.prop_u32("#size-cells", 2);
+    fdt.prop_str("compatible", "simple-bus");
+    fdt.prop("ranges", &[]);
+    for region in layout.0.iter() {
+        let (addr, size) = (region.addr, region.size);
+        match region.device.kind() {
+            Kind::Clint => {
+                fdt.begin(&format!("clint@{:x}", addr));
+                fdt.prop_strs("compatible", &["sifive,clint0", "riscv,clint0"]);
+                fdt.prop_cells("reg", &reg(addr, size));
+                // Software and timer interrupts of the M-mode hart
+                fdt.prop_cells("interrupts-extended", &[CPU_INTC, 3, CPU_INTC, 7]);
+                fdt.end();
+            }
+            Kind::Plic => {
+                fdt.begin(&format!("plic@{:x}", addr));
+                fdt.prop_strs("compatible", &["sifive,plic-1.0.0", "riscv,plic0"]);
+                fdt.prop_cells("reg", &reg(addr, size));
+                fdt.prop_u32("#address-cells", 0);
+                fdt.prop_u32("#interrupt-cells", 1);
+                fdt.prop("interrupt-controller", &[]);
+                // Contexts in order, M-mode and S-mode external interrupts
+                fdt.prop_cells("interrupts-extended", &[CPU_INTC, 11, CPU_INTC, 9]);
+                fdt.prop_u32("riscv,ndev", plic::SOURCES as u32 - 1);
+                fdt.prop_u32("phandle", PLIC);
+                fdt.end();
+            }
+            Kind::Uart => {
+                fdt.begin(&format!("serial@{:x}", addr));
+                fdt.prop_str("compatible", "ns16550a");
+                fdt.prop_cells("reg", &reg(addr, size));
+                // Same as QEMU, the divisor is never used for anything
+                fdt.prop_u32("clock-frequency", 3686400);
+                fdt.prop_u32("interrupt-parent", PLIC);
+                fdt.prop_u32("interrupts", uart_irq as u32);
+                fdt.end();
+            }
+            Kind::Finisher => {
+                fdt.begin(&format!("test@{:x}", addr));
+                fdt.prop_strs("compatible", &["sifive,test1", "sifive,test0", "syscon"]);
+                fdt.prop_cells("reg", &reg(addr, size));
+                fdt.prop_u32("phandle", FINISHER);
+                fdt.end();
+            }
+            // Memory is described above, the rest have no binding
+            Kind::Memory | Kind::Serial | Kind::Exit | Kind::Other => {}
+        }
+    }
+    fdt.end();
+    if layout.0.iter().any(|x| x.device.kind() == Kind::Finisher) {
+        fdt.begin("poweroff");
+        fdt.prop_str("compatible", "syscon-poweroff");
+        fdt.prop_u32("regmap", FINISHER);
+        fdt.prop_u32("offset", 0);
+        fdt.prop_u32("value", 0x5555);
+        fdt.end();
+    }
+    fdt.end();
+    fdt.finish()
+}
@@ -0,0 +1,75 @@
+use crate::{linux::{self, Process}, newlib, uart, vm::{Halt, VM}};
+
+// NOTE: The Berkeley host-target interface, as spike and riscv-pk speak it.
+// The guest writes a command to the 64-bit `tohost` and the host answers through `fromhost`:
+//   device in bits 63:56, command in bits 55:48, payload below.
+// Device 0 command 0 is the system call proxy, or exit when the payload's low bit is set.
+// Device 1 is the console, command 1 prints the payload's low byte.
+// A 32-bit guest writes tohost in two halves, low first, so the host picks the command up
+// once the high half is stored. Whatever is left there is seen when the guest goes idle.
+const SYSCALL: u64 = 0;
+const CONSOLE: u64 = 1;
+const PUTCHAR: u64 = 1;
+const PAYLOAD: u64 = (1 << 48) - 1;
+
+pub struct Htif {
+    tohost: u32,
+    fromhost: Option<u32>,
+    // Proxied system calls use the same table as libgloss
+    process: Process,
+}
+impl Htif {
+    pub fn new(tohost: u32, fromhost: Option<u32>, process: Process) -> Self {
+        Self { tohost, fromhost, process }
+    }
+    // Whether a store to physical `addr` touches the high half of tohost
+    pub fn watches(&self, addr: usize, len: usize) -> bool {
+        let high = self.tohost as usize + 4;
+        addr < high + 4 && addr + len > high
+    }
+}
+
+fn read_u64(vm: &mut VM, addr: u32) -> Option<u64> {
+    Some(u64::from_le_bytes(linux::buf(vm, addr, 8).ok()?.try_into().unwrap()))
+}
+fn write_u64(vm: &mut VM, addr: u32, v: u64) -> Option<()> {
+    linux::put(vm, addr, &v.to_le_bytes()).ok()
+}
+
+pub fn poll(vm: &mut VM) {
+    let Some(mut htif) = vm.htif.take() else { return; };
+    if let Some(cmd) = read_u64(vm, htif.tohost).filter(|&x| x != 0) {
+        write_u64(vm, htif.tohost, 0);
+        let (dev, op, payload) = (cmd >> 56, (cmd >> 48) & 0xFF, cmd & PAYLOAD);
+        let reply = match (dev, op) {
+            (SYSCALL, 0) if payload & 1 != 0 => {
+                vm.halt = Some(Halt::Exit((payload >> 1) as u8));
+                None
+            }
+            // The payload points at the number and arguments, all 64 bits wide.
+            // The result goes back in place of the number.
+            (SYSCALL, 0) => {
+                let addr = payload as u32;
+                let words: Option<Vec<u64>> = (0..8).map(|i| read_u64(vm, addr.wrapping_add(i*8))).collect();
+                if let Some(words) = words {
+                    let args = std::array::from_fn(|i| words[i+1] as u32);
+                    let ret = newlib::call(vm, &mut htif.process, words[0] as u32, args).unwrap_or_else(|e| -e);
+                    write_u64(vm, addr, ret as i64 as u64);
+                }
+                Some(1)
+            }
+            (CONSOLE, PUTCHAR) => {
+                uart::putchar(payload as u8);
+                Some(0)
+            }
+            // Nothing else answers, console input included
+            _ => None,
+        };
+        if let (Some(v), Some(fromhost)) = (reply, htif.fromhost) {
+            if vm.halt.is_none() {
+                write_u64(vm, fromhost, dev << 56 | op << 48 | v);
+            }
+        }
+    }
+    vm.htif = Some(htif);
+}
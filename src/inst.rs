@@ -2,7 +2,7 @@
 pub const fn inst_len(begin: u16) -> usize {
     if begin & 0b11 != 0b11 {
         1
-    } else if (begin >> 2) & 0b11 != 0b111 {
+    } else if (begin >> 2) & 0b111 != 0b111 {
         2
     } else if (begin >> 5) & 0b1 != 0b1 {
         4
@@ -22,9 +22,19 @@ impl Inst32 {
     pub const fn new(data: u32) -> Self {
         Self { data: data as i32 }
     }
+    /// The instruction `bytes` start with, a compressed one expanded, and its length in bytes.
+    /// None if it's illegal, cut short or longer than 32 bits.
+    pub fn decode(bytes: &[u8]) -> Option<(Self, usize)> {
+        let half = u16::from_le_bytes([*bytes.first()?, *bytes.get(1)?]);
+        match inst_len(half) {
+            1 => Some((crate::rvc::expand(half)?, 2)),
+            2 => Some((Self::new(u32::from_le_bytes(bytes.get(..4)?.try_into().unwrap())), 4)),
+            _ => None,
+        }
+    }
     #[inline]
     pub const fn opcode(self) -> i32 {
-        self.data & 0b111_111_1
+        self.data & 0b1111111
     }
 
     #[inline]
@@ -34,7 +44,7 @@ impl Inst32 {
 
     #[inline]
     pub const fn funct7(self) -> i32 {
-        (self.data >> 25) & 0b111_111_1
+        (self.data >> 25) & 0b1111111
     }
 
     #[inline]
@@ -52,6 +62,11 @@ impl Inst32 {
         (self.data >> 20) & 0b11111
     }
 
+    #[inline]
+    pub const fn funct12(self) -> i32 {
+        (self.data >> 20) & 0b111111111111
+    }
+
     #[inline]
     pub const fn imm_U(self) -> i32 {
         self.data >> 12
@@ -64,17 +79,17 @@ impl Inst32 {
 
     #[inline]
     pub const fn imm_S(self) -> i32 {
-        (((self.data >> 7) & 0b11111) ) |
-        (((self.data >> 25)) << 5)
+        ((self.data >> 7) & 0b11111) |
+        ((self.data >> 25) << 5)
     }
     #[inline]
     pub const fn imm_J(self) -> i32 {
         // NOTE: (<< 12) >> 12 to sign extend the whole integer
         (( 
-            (((self.data >> 20 ) & 0b11111111110)      )|
+            ((self.data >> 20 ) & 0b11111111110)|
             (((self.data >> 20 ) & 0b00000000001) << 11)|
             (((self.data >> 12 ) & 0b00011111111) << 12)|
-            (((self.data >> 30 ) & 0b00000000001) << 20)
+            (((self.data >> 31 ) & 0b00000000001) << 20)
         ) << 11) >> 11
         // self.imm_sign() | ((self.data >> 12) & 0b11111_11111_11111_1111)
         // self.data >> 12
@@ -85,10 +100,10 @@ impl Inst32 {
     pub const fn imm_B(self) -> i32 {
         // NOTE: (<< 20) >> 20 to sign extend the whole integer
         ((
-            (((self.data >> 7 ) & 0b011110)      )|
+            ((self.data >> 7 ) & 0b011110)|
             (((self.data >> 25) & 0b111111) << 5 )|
             (((self.data >> 7 ) & 0b000001) << 11)|
-            (((self.data >> 30) & 0b000001) << 12)
+            (((self.data >> 31) & 0b000001) << 12)
         ) << 20 ) >> 20
     }
 }
@@ -0,0 +1,53 @@
+//! An RV32IMAC virtual machine with M, S and U modes, Sv32 and the devices of QEMU's virt board.
+//!
+//! [`Builder`] puts a machine together, [`VM::run`] steps it and everything about the hart is public
+//! on [`VM`] to inspect between steps. [`Disasm32`] shows an instruction the way the debugger does.
+//! Peripherals of your own implement [`Device`] and go in with [`Builder::region`].
+mod region;
+mod inst;
+mod rvc;
+mod off;
+mod ops;
+mod vm;
+mod disasm;
+mod dbg;
+mod setup;
+mod simple;
+mod trace;
+mod elf;
+mod profile;
+mod coverage;
+mod dwarf;
+mod timing;
+mod csr;
+mod cache;
+mod bpred;
+mod trap;
+mod mmu;
+mod pmp;
+mod clint;
+mod plic;
+mod uart;
+mod virt;
+mod fdt;
+mod sbi;
+mod user;
+mod linux;
+mod newlib;
+mod semihost;
+mod htif;
+mod builder;
+#[cfg(test)]
+mod difftest;
+#[cfg(test)]
+mod sv32test;
+
+pub use builder::{Builder, Machine};
+pub use bpred::BranchSim;
+pub use cache::CacheSim;
+pub use clint::Clock;
+pub use disasm::Disasm32;
+pub use inst::Inst32;
+pub use region::{Device, Kind, Region, RegionList};
+pub use timing::TimingConfig;
+pub use vm::{Halt, VM};
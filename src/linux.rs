@@ -0,0 +1,739 @@
+use std::{collections::{hash_map::RandomState, BTreeMap}, env, ffi::OsStr, fs::{self, DirBuilder, File, Metadata, OpenOptions}, hash::{BuildHasher, Hasher}, io::{self, IsTerminal, PipeReader, PipeWriter, Read, Seek, SeekFrom, Write}, os::unix::{ffi::OsStrExt, fs::{DirBuilderExt, DirEntryExt, FileTypeExt, FileExt, MetadataExt, OpenOptionsExt}}, path::PathBuf, thread, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+use crate::{mmu, newlib, trap::{self, Trap}, user, vm::{Halt, VM}};
+
+// NOTE: The Linux rv32 system call ABI, serviced on the host like qemu-user does.
+// Number in a7, arguments in a0-a5, result or negated errno in a0.
+// rv32 only has the generic table with 64-bit time and file offsets, include/uapi/asm-generic/unistd.h
+pub mod nr {
+    pub const GETCWD      : u32 = 17;
+    pub const DUP         : u32 = 23;
+    pub const DUP3        : u32 = 24;
+    pub const FCNTL64     : u32 = 25;
+    pub const IOCTL       : u32 = 29;
+    pub const MKDIRAT     : u32 = 34;
+    pub const UNLINKAT    : u32 = 35;
+    pub const FTRUNCATE64 : u32 = 46;
+    pub const FACCESSAT   : u32 = 48;
+    pub const CHDIR       : u32 = 49;
+    pub const OPENAT      : u32 = 56;
+    pub const CLOSE       : u32 = 57;
+    pub const PIPE2       : u32 = 59;
+    pub const GETDENTS64  : u32 = 61;
+    pub const LLSEEK      : u32 = 62;
+    pub const READ        : u32 = 63;
+    pub const WRITE       : u32 = 64;
+    pub const READV       : u32 = 65;
+    pub const WRITEV      : u32 = 66;
+    pub const PREAD64     : u32 = 67;
+    pub const PWRITE64    : u32 = 68;
+    pub const READLINKAT  : u32 = 78;
+    pub const FSYNC       : u32 = 82;
+    pub const FDATASYNC   : u32 = 83;
+    pub const EXIT        : u32 = 93;
+    pub const EXIT_GROUP  : u32 = 94;
+    pub const SET_TID_ADDRESS: u32 = 96;
+    pub const SET_ROBUST_LIST: u32 = 99;
+    pub const SCHED_YIELD : u32 = 124;
+    pub const KILL        : u32 = 129;
+    pub const TKILL       : u32 = 130;
+    pub const TGKILL      : u32 = 131;
+    pub const SIGALTSTACK : u32 = 132;
+    pub const RT_SIGACTION: u32 = 134;
+    pub const RT_SIGPROCMASK: u32 = 135;
+    pub const UNAME       : u32 = 160;
+    pub const UMASK       : u32 = 166;
+    pub const GETPID      : u32 = 172;
+    pub const GETPPID     : u32 = 173;
+    pub const GETUID      : u32 = 174;
+    pub const GETEUID     : u32 = 175;
+    pub const GETGID      : u32 = 176;
+    pub const GETEGID     : u32 = 177;
+    pub const GETTID      : u32 = 178;
+    pub const BRK         : u32 = 214;
+    pub const MUNMAP      : u32 = 215;
+    pub const MREMAP      : u32 = 216;
+    pub const MMAP2       : u32 = 222;
+    pub const MPROTECT    : u32 = 226;
+    pub const MSYNC       : u32 = 227;
+    pub const MADVISE     : u32 = 233;
+    pub const RISCV_FLUSH_ICACHE: u32 = 259;
+    pub const PRLIMIT64   : u32 = 261;
+    pub const RENAMEAT2   : u32 = 276;
+    pub const GETRANDOM   : u32 = 278;
+    pub const STATX       : u32 = 291;
+    pub const CLOCK_GETTIME64: u32 = 403;
+    pub const CLOCK_GETRES_TIME64: u32 = 406;
+    pub const CLOCK_NANOSLEEP_TIME64: u32 = 407;
+    pub const FUTEX_TIME64: u32 = 422;
+    pub const FACCESSAT2  : u32 = 439;
+}
+pub mod errno {
+    pub const ESRCH : i32 = 3;
+    pub const EIO   : i32 = 5;
+    pub const EBADF : i32 = 9;
+    pub const EAGAIN: i32 = 11;
+    pub const ENOMEM: i32 = 12;
+    pub const EFAULT: i32 = 14;
+    pub const EINVAL: i32 = 22;
+    pub const ENOTTY: i32 = 25;
+    pub const ESPIPE: i32 = 29;
+    pub const ERANGE: i32 = 34;
+    pub const ENOSYS: i32 = 38;
+}
+use errno::*;
+
+pub const AT_FDCWD: u32 = -100i32 as u32;
+pub const AT_SYMLINK_NOFOLLOW: u32 = 0x100;
+const AT_REMOVEDIR: u32 = 0x200;
+const AT_EMPTY_PATH: u32 = 0x1000;
+const O_ACCMODE: u32 = 0o3;
+const O_CREAT: u32 = 0o100;
+const O_EXCL: u32 = 0o200;
+const O_TRUNC: u32 = 0o1000;
+const O_APPEND: u32 = 0o2000;
+// The rest of the asm-generic O_* flags and what they are on the host, anything else is dropped
+// the way qemu-user drops it. That leaves out O_CLOEXEC since the guest can't exec, O_LARGEFILE
+// since every host file is large, and O_DIRECT since the buffers the host reads into aren't aligned.
+const OPEN_FLAGS: [(u32, i32); 9] = [
+    (0o400, libc::O_NOCTTY),
+    (0o4000, libc::O_NONBLOCK),
+    (0o10000, libc::O_DSYNC),
+    (0o200000, libc::O_DIRECTORY),
+    (0o400000, libc::O_NOFOLLOW),
+    (0o1000000, libc::O_NOATIME),
+    (0o4010000, libc::O_SYNC),
+    (0o10000000, libc::O_PATH),
+    (0o20200000, libc::O_TMPFILE),
+];
+const F_DUPFD: u32 = 0;
+const F_GETFL: u32 = 3;
+const F_DUPFD_CLOEXEC: u32 = 1030;
+const TCGETS: u32 = 0x5401;
+const TIOCGWINSZ: u32 = 0x5413;
+const MAP_FIXED: u32 = 0x10;
+const MAP_ANONYMOUS: u32 = 0x20;
+const MREMAP_FIXED: u32 = 2;
+const FUTEX_WAIT: u32 = 0;
+const FUTEX_WAKE: u32 = 1;
+const TIMER_ABSTIME: u32 = 1;
+const CLOCK_REALTIME: u32 = 0;
+const RLIMIT_STACK: u32 = 3;
+const RLIMIT_NOFILE: u32 = 7;
+const MAX_FILES: usize = 1024;
+// There's only ever the one process and its one thread
+const PID: i32 = 1;
+
+pub fn host(e: io::Error) -> i32 {
+    e.raw_os_error().unwrap_or(EIO)
+}
+fn badf() -> io::Error {
+    io::Error::from_raw_os_error(EBADF)
+}
+
+pub enum Fd {
+    Stdin,
+    Stdout,
+    Stderr,
+    // Directories are files too, `next` is how far getdents64 got
+    File { file: File, path: PathBuf, next: usize },
+    PipeIn(PipeReader),
+    PipeOut(PipeWriter),
+}
+impl Fd {
+    fn try_clone(&self) -> io::Result<Fd> {
+        Ok(match self {
+            Fd::Stdin => Fd::Stdin,
+            Fd::Stdout => Fd::Stdout,
+            Fd::Stderr => Fd::Stderr,
+            Fd::File { file, path, next } => Fd::File { file: file.try_clone()?, path: path.clone(), next: *next },
+            Fd::PipeIn(x) => Fd::PipeIn(x.try_clone()?),
+            Fd::PipeOut(x) => Fd::PipeOut(x.try_clone()?),
+        })
+    }
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Fd::Stdin => io::stdin().read(buf),
+            Fd::File { file, .. } => file.read(buf),
+            Fd::PipeIn(x) => x.read(buf),
+            _ => Err(badf()),
+        }
+    }
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // The guest does its own buffering
+        fn unbuffered(out: &mut dyn Write, buf: &[u8]) -> io::Result<usize> {
+            out.write_all(buf).and_then(|_| out.flush()).map(|_| buf.len())
+        }
+        match self {
+            Fd::Stdout => unbuffered(&mut io::stdout(), buf),
+            Fd::Stderr => unbuffered(&mut io::stderr(), buf),
+            Fd::File { file, .. } => file.write(buf),
+            Fd::PipeOut(x) => x.write(buf),
+            _ => Err(badf()),
+        }
+    }
+    pub fn file(&mut self) -> Result<&mut File, i32> {
+        match self {
+            Fd::File { file, .. } => Ok(file),
+            _ => Err(ESPIPE),
+        }
+    }
+    fn is_terminal(&self) -> bool {
+        match self {
+            Fd::Stdin => io::stdin().is_terminal(),
+            Fd::Stdout => io::stdout().is_terminal(),
+            Fd::Stderr => io::stderr().is_terminal(),
+            _ => false,
+        }
+    }
+    pub fn metadata(&self) -> io::Result<Metadata> {
+        match self {
+            Fd::Stdin => fs::metadata("/proc/self/fd/0"),
+            Fd::Stdout => fs::metadata("/proc/self/fd/1"),
+            Fd::Stderr => fs::metadata("/proc/self/fd/2"),
+            Fd::File { file, .. } => file.metadata(),
+            // No way to stat a pipe without going around std
+            _ => Err(io::Error::from_raw_os_error(ENOSYS)),
+        }
+    }
+}
+
+// Which system call table ECALL goes to
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Abi {
+    Linux,
+    // Bare-metal programs linked against libgloss, see newlib.rs
+    Newlib,
+}
+pub struct Process {
+    pub abi: Abi,
+    exe: PathBuf,
+    fds: Vec<Option<Fd>>,
+    brk_start: u32,
+    brk: u32,
+    // Live mappings, start to end. New ones go in the highest gap below the stack.
+    maps: BTreeMap<u32, u32>,
+    mmap_top: u32,
+    start: Instant,
+}
+impl Process {
+    pub fn new(abi: Abi, exe: PathBuf, image_end: usize, stack_top: usize) -> Self {
+        let brk = page_up(image_end as u32);
+        Self {
+            abi,
+            exe,
+            fds: vec![Some(Fd::Stdin), Some(Fd::Stdout), Some(Fd::Stderr)],
+            brk_start: brk,
+            brk,
+            maps: BTreeMap::new(),
+            mmap_top: stack_top.saturating_sub(user::STACK_SIZE) as u32,
+            start: Instant::now(),
+        }
+    }
+    pub fn fd(&mut self, fd: u32) -> Result<&mut Fd, i32> {
+        self.fds.get_mut(fd as usize).and_then(Option::as_mut).ok_or(EBADF)
+    }
+    // Lowest free descriptor at or above `min`
+    fn insert(&mut self, fd: Fd, min: usize) -> Result<i32, i32> {
+        let i = (min..MAX_FILES).find(|&i| self.fds.get(i).is_none_or(Option::is_none)).ok_or(EINVAL)?;
+        if i >= self.fds.len() { self.fds.resize_with(i+1, || None); }
+        self.fds[i] = Some(fd);
+        Ok(i as i32)
+    }
+    // Turns a path relative to `dirfd` into one the host can use
+    pub fn path(&mut self, vm: &mut VM, dirfd: u32, addr: u32) -> Result<PathBuf, i32> {
+        let path = PathBuf::from(OsStr::from_bytes(string(vm, addr)?));
+        if path.is_absolute() || dirfd == AT_FDCWD { return Ok(path); }
+        match self.fd(dirfd)? {
+            Fd::File { path: dir, .. } => Ok(dir.join(path)),
+            _ => Err(EBADF),
+        }
+    }
+    fn unmap(&mut self, start: u32, end: u32) {
+        let overlapping: Vec<_> = self.maps.range(..end).filter(|(_, &e)| e > start).map(|(&s, &e)| (s, e)).collect();
+        for (s, e) in overlapping {
+            self.maps.remove(&s);
+            if s < start { self.maps.insert(s, start); }
+            if e > end { self.maps.insert(end, e); }
+        }
+    }
+    fn find_free(&self, len: u32) -> Option<u32> {
+        let mut end = self.mmap_top;
+        for (&s, &e) in self.maps.range(..self.mmap_top).rev() {
+            if e <= end && end - e >= len { return Some(end - len); }
+            end = end.min(s);
+        }
+        (end.checked_sub(len)? >= self.brk).then(|| end - len)
+    }
+}
+
+fn page_up(addr: u32) -> u32 {
+    addr.wrapping_add(mmu::PAGE_SIZE - 1) & !(mmu::PAGE_SIZE - 1)
+}
+// A guest buffer, as long as a single memory region backs all of it
+pub fn buf(vm: &mut VM, addr: u32, len: usize) -> Result<&mut [u8], i32> {
+    if len == 0 { return Ok(&mut []); }
+    vm.memory(addr as usize, len).ok_or(EFAULT)
+}
+pub fn string(vm: &mut VM, addr: u32) -> Result<&[u8], i32> {
+    // It can run up to the end of its region
+    let region = vm.regions.find_region(addr as usize).ok_or(EFAULT)?;
+    let bytes = buf(vm, addr, region.addr + region.size - addr as usize)?;
+    let len = bytes.iter().position(|&b| b == 0).ok_or(EFAULT)?;
+    Ok(&bytes[..len])
+}
+pub fn put(vm: &mut VM, addr: u32, bytes: &[u8]) -> Result<(), i32> {
+    buf(vm, addr, bytes.len())?.copy_from_slice(bytes);
+    Ok(())
+}
+fn get_u32(vm: &mut VM, addr: u32) -> Result<u32, i32> {
+    Ok(u32::from_le_bytes(buf(vm, addr, 4)?.try_into().unwrap()))
+}
+// struct timespec with a 64-bit tv_sec
+fn get_timespec(vm: &mut VM, addr: u32) -> Result<Duration, i32> {
+    let sec = get_u32(vm, addr)? as u64 | (get_u32(vm, addr.wrapping_add(4))? as u64) << 32;
+    let nsec = get_u32(vm, addr.wrapping_add(8))?;
+    if nsec >= 1_000_000_000 { return Err(EINVAL); }
+    Ok(Duration::new(sec, nsec))
+}
+fn put_timespec(vm: &mut VM, addr: u32, t: Duration) -> Result<(), i32> {
+    put(vm, addr, &[&t.as_secs().to_le_bytes()[..], &t.subsec_nanos().to_le_bytes(), &[0; 4]].concat())
+}
+fn clock(p: &Process, id: u32) -> Duration {
+    match id {
+        CLOCK_REALTIME => SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default(),
+        // Every other clock counts from when the process started
+        _ => p.start.elapsed(),
+    }
+}
+// The iovec array of readv and writev, as addresses and lengths that all point into memory
+fn iovecs(vm: &mut VM, addr: u32, count: u32) -> Result<Vec<(u32, usize)>, i32> {
+    if count > 1024 { return Err(EINVAL); }
+    (0..count).map(|i| {
+        let at = addr.wrapping_add(i*8);
+        let iov = (get_u32(vm, at)?, get_u32(vm, at.wrapping_add(4))? as usize);
+        buf(vm, iov.0, iov.1)?;
+        Ok(iov)
+    }).collect()
+}
+
+// struct statx from include/uapi/linux/stat.h
+fn statx(meta: &Metadata) -> [u8; 256] {
+    let mut out = [0; 256];
+    let mut put = |off: usize, bytes: &[u8]| out[off..off+bytes.len()].copy_from_slice(bytes);
+    // STATX_BASIC_STATS
+    put(0, &0x7FFu32.to_le_bytes());
+    put(4, &(meta.blksize() as u32).to_le_bytes());
+    put(16, &(meta.nlink() as u32).to_le_bytes());
+    put(20, &meta.uid().to_le_bytes());
+    put(24, &meta.gid().to_le_bytes());
+    put(28, &(meta.mode() as u16).to_le_bytes());
+    put(32, &meta.ino().to_le_bytes());
+    put(40, &meta.size().to_le_bytes());
+    put(48, &meta.blocks().to_le_bytes());
+    for (off, sec, nsec) in [(64, meta.atime(), meta.atime_nsec()), (96, meta.ctime(), meta.ctime_nsec()), (112, meta.mtime(), meta.mtime_nsec())] {
+        put(off, &sec.to_le_bytes());
+        put(off+8, &(nsec as u32).to_le_bytes());
+    }
+    // The host's dev_t split the way glibc encodes it
+    for (off, dev) in [(128, meta.rdev()), (136, meta.dev())] {
+        put(off, &(((dev >> 32) & !0xFFF | (dev >> 8) & 0xFFF) as u32).to_le_bytes());
+        put(off+4, &(((dev >> 12) & !0xFF | dev & 0xFF) as u32).to_le_bytes());
+    }
+    out
+}
+// A struct linux_dirent64 for getdents64
+fn dirent(ino: u64, off: usize, kind: Option<fs::FileType>, name: &[u8]) -> Vec<u8> {
+    let len = (19 + name.len() + 1).next_multiple_of(8);
+    let kind = match kind {
+        Some(x) if x.is_fifo() => 1,
+        Some(x) if x.is_char_device() => 2,
+        Some(x) if x.is_dir() => 4,
+        Some(x) if x.is_block_device() => 6,
+        Some(x) if x.is_file() => 8,
+        Some(x) if x.is_symlink() => 10,
+        Some(x) if x.is_socket() => 12,
+        _ => 0,
+    };
+    let mut out = Vec::with_capacity(len);
+    out.extend(ino.to_le_bytes());
+    out.extend((off as u64).to_le_bytes());
+    out.extend((len as u16).to_le_bytes());
+    out.push(kind);
+    out.extend(name);
+    out.resize(len, 0);
+    out
+}
+fn signal_name(sig: u32) -> &'static str {
+    match sig {
+        4 => "SIGILL",
+        5 => "SIGTRAP",
+        6 => "SIGABRT",
+        7 => "SIGBUS",
+        9 => "SIGKILL",
+        11 => "SIGSEGV",
+        15 => "SIGTERM",
+        _ => "signal",
+    }
+}
+// Nothing handles signals, so they all end the process with the shell's 128+N
+fn kill(vm: &mut VM, sig: u32) {
+    let _ = io::stdout().flush();
+    eprintln!("ERROR: Guest killed by {} ({}) at ip=0x{:08X}", signal_name(sig), sig, vm.ip);
+    vm.halt = Some(Halt::Exit((128 + sig) as u8));
+}
+// Traps a kernel would turn into signals
+pub fn fault(vm: &mut VM, trap: Trap) {
+    let sig = match trap.cause {
+        trap::ILLEGAL_INST => 4,
+        trap::BREAKPOINT => 5,
+        trap::INST_MISALIGNED => 7,
+        _ => 11,
+    };
+    kill(vm, sig);
+}
+
+// Handles an ECALL from the program
+pub fn syscall(vm: &mut VM) {
+    let Some(mut p) = vm.process.take() else { return; };
+    let a: [u32; 6] = std::array::from_fn(|i| vm.regs[10+i] as u32);
+    let nr = vm.regs[17] as u32;
+    let ret = match p.abi {
+        Abi::Linux => call(vm, &mut p, nr, a),
+        Abi::Newlib => newlib::call(vm, &mut p, nr, a),
+    }.unwrap_or_else(|e| -e);
+    vm.process = Some(p);
+    if vm.halt.is_none() {
+        vm.set_reg(10, ret);
+    }
+}
+pub fn call(vm: &mut VM, p: &mut Process, nr: u32, a: [u32; 6]) -> Result<i32, i32> {
+    Ok(match nr {
+        nr::READ => {
+            p.fd(a[0])?.read(buf(vm, a[1], a[2] as usize)?).map_err(host)? as i32
+        }
+        nr::WRITE => {
+            p.fd(a[0])?.write(buf(vm, a[1], a[2] as usize)?).map_err(host)? as i32
+        }
+        nr::READV => {
+            let fd = p.fd(a[0])?;
+            let mut total = 0;
+            for (addr, len) in iovecs(vm, a[1], a[2])? {
+                let n = fd.read(buf(vm, addr, len)?).map_err(host)?;
+                total += n;
+                if n < len { break; }
+            }
+            total as i32
+        }
+        nr::WRITEV => {
+            let fd = p.fd(a[0])?;
+            let mut data = Vec::new();
+            for (addr, len) in iovecs(vm, a[1], a[2])? {
+                data.extend_from_slice(buf(vm, addr, len)?);
+            }
+            fd.write(&data).map_err(host)? as i32
+        }
+        nr::PREAD64 => {
+            let pos = a[3] as u64 | (a[4] as u64) << 32;
+            p.fd(a[0])?.file()?.read_at(buf(vm, a[1], a[2] as usize)?, pos).map_err(host)? as i32
+        }
+        nr::PWRITE64 => {
+            let pos = a[3] as u64 | (a[4] as u64) << 32;
+            p.fd(a[0])?.file()?.write_at(buf(vm, a[1], a[2] as usize)?, pos).map_err(host)? as i32
+        }
+        nr::LLSEEK => {
+            let off = (a[1] as u64) << 32 | a[2] as u64;
+            let from = match a[4] {
+                0 => SeekFrom::Start(off),
+                1 => SeekFrom::Current(off as i64),
+                2 => SeekFrom::End(off as i64),
+                _ => return Err(EINVAL),
+            };
+            let pos = p.fd(a[0])?.file()?.seek(from).map_err(host)?;
+            put(vm, a[3], &pos.to_le_bytes())?;
+            if let Fd::File { next, .. } = p.fd(a[0])? {
+                // Rewinding a directory starts the listing over
+                if pos == 0 { *next = 0; }
+            }
+            0
+        }
+        nr::OPENAT => {
+            let path = p.path(vm, a[0], a[1])?;
+            let flags = a[2];
+            let file = OpenOptions::new()
+                .read(flags & O_ACCMODE != 1)
+                .write(flags & O_ACCMODE != 0 && flags & O_APPEND == 0)
+                .append(flags & O_APPEND != 0)
+                .truncate(flags & O_TRUNC != 0)
+                .create(flags & O_CREAT != 0)
+                .create_new(flags & (O_CREAT | O_EXCL) == O_CREAT | O_EXCL)
+                .mode(a[3])
+                .custom_flags(OPEN_FLAGS.iter().filter(|x| flags & x.0 == x.0).fold(0, |acc, x| acc | x.1))
+                .open(&path)
+                .map_err(host)?;
+            p.insert(Fd::File { file, path, next: 0 }, 0)?
+        }
+        nr::CLOSE => {
+            p.fd(a[0])?;
+            p.fds[a[0] as usize] = None;
+            0
+        }
+        nr::DUP => {
+            let fd = p.fd(a[0])?.try_clone().map_err(host)?;
+            p.insert(fd, 0)?
+        }
+        nr::DUP3 => {
+            if a[0] == a[1] { return Err(EINVAL); }
+            let fd = p.fd(a[0])?.try_clone().map_err(host)?;
+            let i = a[1] as usize;
+            if i >= MAX_FILES { return Err(EBADF); }
+            if i >= p.fds.len() { p.fds.resize_with(i+1, || None); }
+            p.fds[i] = Some(fd);
+            i as i32
+        }
+        nr::FCNTL64 => {
+            let fd = p.fd(a[0])?;
+            match a[1] {
+                F_DUPFD | F_DUPFD_CLOEXEC => {
+                    let fd = fd.try_clone().map_err(host)?;
+                    p.insert(fd, a[2] as usize)?
+                }
+                F_GETFL => match fd {
+                    Fd::Stdin | Fd::PipeIn(_) => 0,
+                    Fd::Stdout | Fd::Stderr | Fd::PipeOut(_) => 1,
+                    Fd::File { .. } => 2,
+                },
+                // Close-on-exec and non-blocking mean nothing here
+                _ => 0,
+            }
+        }
+        nr::IOCTL => {
+            let fd = p.fd(a[0])?;
+            match a[1] {
+                // What isatty() asks
+                TIOCGWINSZ if fd.is_terminal() => {
+                    put(vm, a[2], &[24u16.to_le_bytes(), 80u16.to_le_bytes(), [0; 2], [0; 2]].concat())?;
+                    0
+                }
+                TCGETS if fd.is_terminal() => {
+                    put(vm, a[2], &[0; 36])?;
+                    0
+                }
+                _ => return Err(ENOTTY),
+            }
+        }
+        nr::GETDENTS64 => {
+            let (path, next) = match p.fd(a[0])? {
+                Fd::File { path, next, .. } => (path.clone(), next),
+                _ => return Err(EBADF),
+            };
+            let dir = fs::metadata(&path).map_err(host)?;
+            let parent = fs::metadata(path.join("..")).map(|x| x.ino()).unwrap_or(0);
+            let mut entries = vec![
+                (dir.ino(), Some(dir.file_type()), b".".to_vec()),
+                (parent, Some(dir.file_type()), b"..".to_vec()),
+            ];
+            for entry in fs::read_dir(&path).map_err(host)? {
+                let entry = entry.map_err(host)?;
+                entries.push((entry.ino(), entry.file_type().ok(), entry.file_name().as_bytes().to_vec()));
+            }
+            let total = entries.len();
+            let mut out = Vec::new();
+            for (i, (ino, kind, name)) in entries.into_iter().enumerate().skip(*next) {
+                let rec = dirent(ino, i+1, kind, &name);
+                if out.len() + rec.len() > a[2] as usize { break; }
+                out.extend(rec);
+                *next = i+1;
+            }
+            // Not even one entry fits
+            if out.is_empty() && *next < total { return Err(EINVAL); }
+            put(vm, a[1], &out)?;
+            out.len() as i32
+        }
+        nr::FTRUNCATE64 => {
+            p.fd(a[0])?.file()?.set_len(a[1] as u64 | (a[2] as u64) << 32).map_err(host)?;
+            0
+        }
+        nr::FSYNC | nr::FDATASYNC => {
+            if let Fd::File { file, .. } = p.fd(a[0])? {
+                file.sync_all().map_err(host)?;
+            }
+            0
+        }
+        nr::PIPE2 => {
+            let (r, w) = io::pipe().map_err(host)?;
+            let r = p.insert(Fd::PipeIn(r), 0)?;
+            let w = p.insert(Fd::PipeOut(w), 0)?;
+            put(vm, a[0], &[r.to_le_bytes(), w.to_le_bytes()].concat())?;
+            0
+        }
+        nr::STATX => {
+            let meta = if a[2] & AT_EMPTY_PATH != 0 && string(vm, a[1])?.is_empty() {
+                p.fd(a[0])?.metadata().map_err(host)?
+            } else {
+                let path = p.path(vm, a[0], a[1])?;
+                if a[2] & AT_SYMLINK_NOFOLLOW != 0 { fs::symlink_metadata(path) } else { fs::metadata(path) }.map_err(host)?
+            };
+            put(vm, a[4], &statx(&meta))?;
+            0
+        }
+        nr::FACCESSAT | nr::FACCESSAT2 => {
+            fs::metadata(p.path(vm, a[0], a[1])?).map_err(host)?;
+            0
+        }
+        nr::GETCWD => {
+            let cwd = env::current_dir().map_err(host)?;
+            let bytes = [cwd.as_os_str().as_bytes(), &[0]].concat();
+            if bytes.len() > a[1] as usize { return Err(ERANGE); }
+            put(vm, a[0], &bytes)?;
+            bytes.len() as i32
+        }
+        nr::CHDIR => {
+            env::set_current_dir(OsStr::from_bytes(string(vm, a[0])?)).map_err(host)?;
+            0
+        }
+        nr::MKDIRAT => {
+            DirBuilder::new().mode(a[2]).create(p.path(vm, a[0], a[1])?).map_err(host)?;
+            0
+        }
+        nr::UNLINKAT => {
+            let path = p.path(vm, a[0], a[1])?;
+            if a[2] & AT_REMOVEDIR != 0 { fs::remove_dir(path) } else { fs::remove_file(path) }.map_err(host)?;
+            0
+        }
+        nr::RENAMEAT2 => {
+            if a[4] != 0 { return Err(EINVAL); }
+            fs::rename(p.path(vm, a[0], a[1])?, p.path(vm, a[2], a[3])?).map_err(host)?;
+            0
+        }
+        nr::READLINKAT => {
+            let path = p.path(vm, a[0], a[1])?;
+            // The host's answer would be the VM itself
+            let target = if path.as_os_str() == "/proc/self/exe" { p.exe.clone() } else { fs::read_link(path).map_err(host)? };
+            let bytes = target.as_os_str().as_bytes();
+            let len = bytes.len().min(a[3] as usize);
+            put(vm, a[2], &bytes[..len])?;
+            len as i32
+        }
+        nr::EXIT | nr::EXIT_GROUP => {
+            let _ = io::stdout().flush();
+            vm.halt = Some(Halt::Exit(a[0] as u8));
+            0
+        }
+        nr::SET_TID_ADDRESS | nr::GETPID | nr::GETTID => PID,
+        nr::GETPPID | nr::GETUID | nr::GETEUID | nr::GETGID | nr::GETEGID => 0,
+        nr::SET_ROBUST_LIST | nr::SCHED_YIELD | nr::SIGALTSTACK | nr::RISCV_FLUSH_ICACHE => 0,
+        nr::MPROTECT | nr::MSYNC | nr::MADVISE => 0,
+        nr::UMASK => 0o022,
+        nr::KILL | nr::TKILL | nr::TGKILL => {
+            let (target, sig) = if nr == nr::TGKILL { (a[1] as i32, a[2]) } else { (a[0] as i32, a[1]) };
+            if target != PID && !(nr == nr::KILL && (target == 0 || target == -1)) { return Err(ESRCH); }
+            if sig > 64 { return Err(EINVAL); }
+            if sig != 0 { kill(vm, sig); }
+            0
+        }
+        // Handlers are accepted but never run, nothing delivers signals.
+        // The kernel's sigset_t is 64 bits, anything else is EINVAL there too.
+        nr::RT_SIGACTION => {
+            if a[3] != 8 { return Err(EINVAL); }
+            if a[2] != 0 { put(vm, a[2], &[0; 16])?; }
+            0
+        }
+        nr::RT_SIGPROCMASK => {
+            if a[3] != 8 { return Err(EINVAL); }
+            if a[2] != 0 { put(vm, a[2], &[0; 8])?; }
+            0
+        }
+        nr::UNAME => {
+            let mut out = [0; 65 * 6];
+            for (i, field) in ["Linux", "riscv-vm", "6.1.0", "#1", "riscv32", "(none)"].into_iter().enumerate() {
+                out[i*65..i*65+field.len()].copy_from_slice(field.as_bytes());
+            }
+            put(vm, a[0], &out)?;
+            0
+        }
+        nr::PRLIMIT64 => {
+            if a[0] != 0 && a[0] as i32 != PID { return Err(ESRCH); }
+            if a[3] != 0 {
+                let limit = match a[1] {
+                    RLIMIT_STACK => user::STACK_SIZE as u64,
+                    RLIMIT_NOFILE => MAX_FILES as u64,
+                    _ => u64::MAX,
+                };
+                put(vm, a[3], &[limit.to_le_bytes(), limit.to_le_bytes()].concat())?;
+            }
+            0
+        }
+        nr::CLOCK_GETTIME64 => {
+            put_timespec(vm, a[1], clock(p, a[0]))?;
+            0
+        }
+        nr::CLOCK_GETRES_TIME64 => {
+            if a[1] != 0 { put_timespec(vm, a[1], Duration::from_nanos(1))?; }
+            0
+        }
+        nr::CLOCK_NANOSLEEP_TIME64 => {
+            let t = get_timespec(vm, a[2])?;
+            thread::sleep(if a[1] & TIMER_ABSTIME != 0 { t.saturating_sub(clock(p, a[0])) } else { t });
+            0
+        }
+        // With one thread a wait could never be woken
+        nr::FUTEX_TIME64 => match a[1] & 0x7F {
+            FUTEX_WAIT => return Err(EAGAIN),
+            FUTEX_WAKE => 0,
+            _ => return Err(ENOSYS),
+        },
+        nr::GETRANDOM => {
+            for chunk in buf(vm, a[0], a[1] as usize)?.chunks_mut(8) {
+                let x = RandomState::new().build_hasher().finish().to_le_bytes();
+                chunk.copy_from_slice(&x[..chunk.len()]);
+            }
+            a[1] as i32
+        }
+        nr::BRK => {
+            let limit = p.maps.keys().next().copied().unwrap_or(p.mmap_top).min(p.mmap_top);
+            if a[0] >= p.brk_start && a[0] <= limit {
+                // Memory handed back and taken again comes back zeroed
+                if a[0] > p.brk {
+                    buf(vm, p.brk, (a[0] - p.brk) as usize)?.fill(0);
+                }
+                p.brk = a[0];
+            }
+            p.brk as i32
+        }
+        nr::MMAP2 => {
+            let (addr, flags) = (a[0], a[3]);
+            let len = page_up(a[1]);
+            if len == 0 || addr & (mmu::PAGE_SIZE - 1) != 0 { return Err(EINVAL); }
+            let start = if flags & MAP_FIXED != 0 { addr } else { p.find_free(len).ok_or(ENOMEM)? };
+            let bytes = buf(vm, start, len as usize)?;
+            bytes.fill(0);
+            if flags & MAP_ANONYMOUS == 0 {
+                // Private file mappings are a copy, nothing is written back
+                let file = p.fd(a[4])?.file()?;
+                let mut off = 0;
+                while off < bytes.len() {
+                    let n = file.read_at(&mut bytes[off..], a[5] as u64 * mmu::PAGE_SIZE as u64 + off as u64).map_err(host)?;
+                    if n == 0 { break; }
+                    off += n;
+                }
+            }
+            p.unmap(start, start + len);
+            p.maps.insert(start, start + len);
+            start as i32
+        }
+        nr::MUNMAP => {
+            if a[0] & (mmu::PAGE_SIZE - 1) != 0 || a[1] == 0 { return Err(EINVAL); }
+            p.unmap(a[0], a[0].saturating_add(page_up(a[1])));
+            0
+        }
+        nr::MREMAP => {
+            // Shrinking in place is all there is, callers fall back to a copy otherwise
+            let (old, new) = (page_up(a[1]), page_up(a[2]));
+            if a[0] & (mmu::PAGE_SIZE - 1) != 0 || new == 0 || a[3] & MREMAP_FIXED != 0 { return Err(EINVAL); }
+            if new > old { return Err(ENOMEM); }
+            p.unmap(a[0].saturating_add(new), a[0].saturating_add(old));
+            a[0] as i32
+        }
+        _ => return Err(ENOSYS),
+    })
+}
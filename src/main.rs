@@ -1,40 +1,183 @@
-use std::{env, fs, io::{self, BufRead, Write}, process::ExitCode};
-use region::RegionList;
-mod region;
-mod inst;
-mod off;
-mod ops;
-mod vm;
-mod disasm;
-mod dbg;
-mod setup;
-mod simple;
+use std::{env, fs::{self, File}, io::{self, BufWriter, Write}, mem, process::ExitCode};
+use riscv_vm::{BranchSim, Builder, CacheSim, Clock, Halt, Kind, Machine, TimingConfig};
 
-#[allow(dead_code)]
 struct Build {
-    exe: String,
     ipath: String,
     dbg: bool,
+    idle_exit: Option<u8>,
+    trace: Option<String>,
+    profile: Option<String>,
+    coverage: Option<String>,
+    timing: Option<TimingConfig>,
+    cache: Option<CacheSim>,
+    bpred: Option<BranchSim>,
+    clock: Option<Clock>,
+    raw: bool,
+    dump_dtb: Option<String>,
+    sbi: bool,
+    newlib: bool,
+    semihost: bool,
+    // Where to dump begin_signature..end_signature for RISCOF
+    signature: Option<String>,
+    // Passed on to the program under -muser
+    args: Vec<String>,
 }
 
-enum Machine {
-    Simple,
-}
 fn main() -> ExitCode {
-    let mut args = env::args();
+    let mut args = env::args().skip(1);
     let mut build = Build {
-        exe: args.next().expect("exe"),
         ipath: String::new(),
-        dbg: false
+        dbg: false,
+        idle_exit: None,
+        trace: None,
+        profile: None,
+        coverage: None,
+        timing: None,
+        cache: None,
+        bpred: None,
+        clock: None,
+        raw: false,
+        dump_dtb: None,
+        sbi: false,
+        newlib: false,
+        semihost: false,
+        signature: None,
+        args: Vec::new(),
     };
     let mut machine = Machine::Simple;
     while let Some(arg) = args.next() {
         match arg.as_str() {
             "-dbg" => build.dbg = true,
+            "-raw" => build.raw = true,
+            "-newlib" => build.newlib = true,
+            "-semihost" => build.semihost = true,
+            "-idle-exit" => {
+                let Some(code) = args.next() else {
+                    eprintln!("ERROR: Missing exit code after -idle-exit");
+                    return ExitCode::FAILURE;
+                };
+                build.idle_exit = match code.parse() {
+                    Ok(v) => Some(v),
+                    Err(e) => {
+                        eprintln!("ERROR: Invalid exit code `{}`: {}", code, e);
+                        return ExitCode::FAILURE;
+                    }
+                };
+            }
+            "-trace" => {
+                let Some(path) = args.next() else {
+                    eprintln!("ERROR: Missing file path after -trace");
+                    return ExitCode::FAILURE;
+                };
+                build.trace = Some(path);
+            }
+            "-profile" => {
+                let Some(path) = args.next() else {
+                    eprintln!("ERROR: Missing file path after -profile");
+                    return ExitCode::FAILURE;
+                };
+                build.profile = Some(path);
+            }
+            "-coverage" => {
+                let Some(path) = args.next() else {
+                    eprintln!("ERROR: Missing file path after -coverage");
+                    return ExitCode::FAILURE;
+                };
+                build.coverage = Some(path);
+            }
+            "-timing" => {
+                if build.timing.is_none() {
+                    build.timing = Some(TimingConfig::default());
+                }
+            }
+            "-timing-config" => {
+                let Some(path) = args.next() else {
+                    eprintln!("ERROR: Missing file path after -timing-config");
+                    return ExitCode::FAILURE;
+                };
+                let src = match fs::read_to_string(&path) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        eprintln!("ERROR: Failed to read {}: {}", path, e);
+                        return ExitCode::FAILURE;
+                    }
+                };
+                build.timing = match TimingConfig::parse(&src) {
+                    Ok(v) => Some(v),
+                    Err(e) => {
+                        eprintln!("ERROR: {}: {}", path, e);
+                        return ExitCode::FAILURE;
+                    }
+                };
+            }
+            "-cache" => {
+                let Some(spec) = args.next() else {
+                    eprintln!("ERROR: Missing cache configuration after -cache");
+                    return ExitCode::FAILURE;
+                };
+                build.cache = match CacheSim::parse(&spec) {
+                    Ok(v) => Some(v),
+                    Err(e) => {
+                        eprintln!("ERROR: Invalid cache configuration: {}", e);
+                        return ExitCode::FAILURE;
+                    }
+                };
+            }
+            "-bpred" => {
+                let Some(spec) = args.next() else {
+                    eprintln!("ERROR: Missing predictor after -bpred");
+                    return ExitCode::FAILURE;
+                };
+                build.bpred = match BranchSim::parse(&spec) {
+                    Ok(v) => Some(v),
+                    Err(e) => {
+                        eprintln!("ERROR: Invalid branch predictor: {}", e);
+                        return ExitCode::FAILURE;
+                    }
+                };
+            }
+            "-kernel" => {
+                let Some(path) = args.next() else {
+                    eprintln!("ERROR: Missing kernel path after -kernel");
+                    return ExitCode::FAILURE;
+                };
+                // Booted straight into S-mode with the built-in SBI standing in for firmware
+                build.ipath = path;
+                build.sbi = true;
+            }
+            "-signature" => {
+                let Some(path) = args.next() else {
+                    eprintln!("ERROR: Missing file path after -signature");
+                    return ExitCode::FAILURE;
+                };
+                build.signature = Some(path);
+            }
+            "-dump-dtb" => {
+                let Some(path) = args.next() else {
+                    eprintln!("ERROR: Missing file path after -dump-dtb");
+                    return ExitCode::FAILURE;
+                };
+                build.dump_dtb = Some(path);
+            }
+            "-clock" => {
+                let Some(spec) = args.next() else {
+                    eprintln!("ERROR: Missing clock source after -clock");
+                    return ExitCode::FAILURE;
+                };
+                build.clock = match Clock::parse(&spec) {
+                    Ok(v) => Some(v),
+                    Err(e) => {
+                        eprintln!("ERROR: Invalid clock source: {}", e);
+                        return ExitCode::FAILURE;
+                    }
+                };
+            }
             arg if arg.starts_with("-m") => {
                 let arg = arg.strip_prefix("-m").unwrap();
                 machine = match arg {
                     "s" | "simple" => Machine::Simple,
+                    "virt" => Machine::Virt,
+                    "user" => Machine::User,
                     _ => {
                         eprintln!("ERROR: Unknown machine: `{}`", arg);
                         return ExitCode::FAILURE;
@@ -42,8 +185,11 @@ fn main() -> ExitCode {
                 }
             }
             _ => {
-                if build.ipath.is_empty() { build.ipath = arg; }
-                else {
+                if build.ipath.is_empty() {
+                    build.ipath = arg;
+                    // Everything after the program is its own arguments
+                    if machine == Machine::User || build.semihost { build.args.extend(args.by_ref()); }
+                } else {
                     eprintln!("ERROR: Unknown argument: {}",arg);
                     return ExitCode::FAILURE;
                 }
@@ -54,128 +200,69 @@ fn main() -> ExitCode {
         eprintln!("ERROR: Missing input path");
         return ExitCode::FAILURE;
     }
-    let mut data = match fs::read(&build.ipath) {
+    let data = match fs::read(&build.ipath) {
         Err(e) => {
             eprintln!("ERROR: Failed to read {}: {}",build.ipath,e);
             return ExitCode::FAILURE;
         }
         Ok(v) => v,
     };
-    let setup = match machine {
-        Machine::Simple => simple::setup(&mut data),
+    let clock = build.clock.unwrap_or(Clock::Instret(1));
+    let exe = fs::canonicalize(&build.ipath).unwrap_or_else(|_| build.ipath.clone().into());
+    let args = [build.ipath.clone()].into_iter().chain(build.args.drain(..)).collect();
+    let env = env::vars_os().map(|(k, v)| format!("{}={}", k.to_string_lossy(), v.to_string_lossy())).collect();
+    let mut builder = match Builder::new(machine).clock(clock).program(exe, args).env(env).image(&data) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("ERROR: Failed to load {}: {}", build.ipath, e);
+            return ExitCode::FAILURE;
+        }
     };
-    let mut vm = vm::VM::new(&setup.layout, &mut data);
-    vm.set_rsp(setup.sp);
-    if build.dbg {
-        let mut debugger = dbg::Dbg::new(vm);
-        let stdin = io::stdin();
-        debugger.disasm();
-        eprint!(":");
-        io::stderr().flush().unwrap();
-        let mut lastline = String::new();
-        for line in stdin.lock().lines() {
-            if let Ok(mut l) = line {
-                if l.is_empty() {
-                    if lastline.is_empty() { continue; }
-                    l = lastline 
-                }
-                let line = l.as_str();
-                let (cmd, arg) = line.split_at(line.find(' ').unwrap_or(line.len()));
-                let arg = arg.trim_start();
-                match cmd {
-                    "n" | "next" => {
-                        debugger.next();
-                    }
-                    "c" | "continue" => {
-                        debugger.r#continue();
-                    }
-                    "b" | "bp" | "break" => {
-                        if let Some(hex) = arg.strip_prefix("0x") {
-                            match u32::from_str_radix(hex, 16) {
-                                Err(e) => {
-                                    eprintln!("ERROR: Failed to parse hex literal: {}", e)
-                                }
-                                Ok(v) => {
-                                    eprintln!("Set breakpoint at 0x{:08X}", v);
-                                    debugger.breakpoints.insert(v);
-                                }
-                            }
-                        } else {
-                            eprintln!("ERROR: Invalid usage of break command:");
-                            eprintln!(" b|bp|break <address>");
-                            eprintln!("But got argument: {}", arg)
-                        }
-                    } 
-                    "rb" | "delbreakpoint" | "db" => {
-                        if let Some(hex) = arg.strip_prefix("0x") {
-                            match u32::from_str_radix(hex, 16) {
-                                Err(e) => {
-                                    eprintln!("ERROR: Failed to parse hex literal: {}", e)
-                                }
-                                Ok(v) => {
-                                    if !debugger.breakpoints.remove(&v) {
-                                        eprintln!("ERROR: Breakpoint: 0x{:08X} does not exist",v);
-                                    }
-                                }
-                            }
-                        } else {
-                            eprintln!("ERROR: Invalid usage of remove break command:");
-                            eprintln!(" rb|db|delbreakpoint <address>");
-                        }
-                    }
-                    "d" | "disasm" => {
-                        if let Some(hex) = arg.strip_prefix("0x") {
-                            match u32::from_str_radix(hex, 16) {
-                                Err(e) => {
-                                    eprintln!("ERROR: Failed to parse hex literal: {}", e)
-                                }
-                                Ok(v) => {
-                                    eprint!("{:08X}>",v);
-                                    debugger.vm.disasm(v as usize);
-                                }
-                            }
-                        } else {
-                            eprintln!("ERROR: Invalid usage of disasm command:");
-                            eprintln!(" d|disasm <address>");
-                        }
-                    }
-                    "q" | "quit" | "exit" => {
-                        break;
-                    }
-                    "i" | "info" => {
-                        match arg {
-                            "regs" => {
-                                eprintln!("IP={:08X}", debugger.vm.ip);
-                                for (i, reg) in debugger.vm.regs.iter().copied().enumerate() {
-                                    if i > 0 {
-                                        eprint!(" ");
-                                        if i % 8 == 0 {
-                                            eprintln!()
-                                        }
-                                    }
-                                    eprint!("x{:<2}={:08X}", i, reg);
-                                }
-                                eprintln!()
-                            }
-                            _ => {
-                                eprintln!("ERROR: Invalid usage of info command with arg: {}", arg);
-                                eprintln!(" i|info <regs>");
-                            }
-                        }
-                    }
-                    _ => eprintln!("Unknown cmd {}",cmd)
-                }
-                debugger.disasm();
-                eprint!(":");
-                io::stderr().flush().unwrap();
-                lastline = l;
-            }
+    if build.sbi { builder = builder.kernel(); }
+    if build.newlib { builder = builder.newlib(); }
+    if build.semihost { builder = builder.semihost(); }
+    if build.profile.is_some() { builder = builder.profile(); }
+    if build.coverage.is_some() { builder = builder.coverage(); }
+    if let Some(cfg) = build.timing.take() { builder = builder.timing(cfg); }
+    if let Some(cache) = build.cache.take() { builder = builder.cache(cache); }
+    if let Some(bpred) = build.bpred.take() { builder = builder.bpred(bpred); }
+    if let Some(path) = &build.trace { builder = builder.trace(path); }
+    // The debugger owns stdin, otherwise it feeds the UART once the guest starts reading from it.
+    // Host calls under -muser, -newlib, HTIF or semihosting read stdin themselves, so the Builder leaves it to them.
+    if !build.dbg { builder = builder.stdin(build.raw); }
+    let mut vm = match builder.build() {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if build.signature.is_some() && vm.signature.is_none() {
+        eprintln!("ERROR: -signature needs an ELF with begin_signature and end_signature");
+        return ExitCode::FAILURE;
+    }
+    if let Some(path) = &build.dump_dtb {
+        let Some(dtb) = machine.dtb(&vm.regions, clock) else {
+            eprintln!("ERROR: There is no device tree without a machine");
+            return ExitCode::FAILURE;
+        };
+        if let Err(e) = fs::write(path, dtb) {
+            eprintln!("ERROR: Failed to write {}: {}", path, e);
+            return ExitCode::FAILURE;
         }
+        return ExitCode::SUCCESS;
+    }
+    let mut vm = if build.dbg {
+        vm.debug(io::stdin().lock())
     } else {
-        while vm.ip() < vm.ram.len() {
+        // Running off the end of the image ends a program on the simple machine.
+        // Elsewhere that's an access fault the guest gets to handle.
+        let end = vm.regions.0.iter().filter(|x| x.device.kind() == Kind::Memory).map(|x| x.addr + x.size).max().unwrap_or(0);
+        while vm.halt.is_none() && (machine != Machine::Simple || vm.ip() < end) {
             vm.next();
         }
-    }
+        vm
+    };
     /*
     while data.has_remaining() {
         let tag = data.get_u16_le();
@@ -193,5 +280,80 @@ fn main() -> ExitCode {
         }
     }
     */
-    ExitCode::SUCCESS
+    io::stdout().flush().unwrap();
+    // The reports need the VM's memory as well as its symbols
+    let symbols = mem::take(&mut vm.symbols);
+    if let (Some(path), Some(profile)) = (&build.profile, &vm.profile) {
+        let res = File::create(path).and_then(|f| {
+            let mut out = BufWriter::new(f);
+            profile.write_folded(&mut out, &symbols)?;
+            out.flush()
+        });
+        if let Err(e) = res {
+            eprintln!("ERROR: Failed to write profile {}: {}", path, e);
+            return ExitCode::FAILURE;
+        }
+        profile.write_flat(&mut io::stderr(), &symbols).unwrap();
+    }
+    if let Some(timing) = &vm.timing {
+        timing.report(&mut io::stderr()).unwrap();
+    }
+    if let Some(cache) = &vm.cache {
+        cache.report(&mut io::stderr(), &symbols).unwrap();
+    }
+    if let Some(bpred) = &vm.bpred {
+        bpred.report(&mut io::stderr(), &symbols).unwrap();
+    }
+    if let (Some(path), Some(coverage)) = (&build.coverage, vm.coverage.take()) {
+        let res = File::create(path).and_then(|f| {
+            let mut out = BufWriter::new(f);
+            coverage.write_hitmap(&mut out)?;
+            out.flush()
+        });
+        if let Err(e) = res {
+            eprintln!("ERROR: Failed to write coverage {}: {}", path, e);
+            return ExitCode::FAILURE;
+        }
+        if coverage.lines.is_some() {
+            let path = format!("{}.info", path);
+            let res = File::create(&path).and_then(|f| {
+                let mut out = BufWriter::new(f);
+                let code = |addr: u32| Some(u32::from_le_bytes(vm.memory(addr as usize, 4)?.try_into().unwrap()));
+                coverage.write_lcov(&mut out, &symbols, code)?;
+                out.flush()
+            });
+            if let Err(e) = res {
+                eprintln!("ERROR: Failed to write lcov tracefile {}: {}", path, e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    // The RISCOF signature format: one 32-bit word per line, in hex
+    if let (Some(path), Some((begin, end))) = (&build.signature, vm.signature) {
+        let words: Vec<u32> = (begin as usize..end as usize).step_by(4).filter_map(|addr| {
+            Some(u32::from_le_bytes(vm.memory(addr, 4)?.try_into().unwrap()))
+        }).collect();
+        let res = File::create(path).and_then(|f| {
+            let mut out = BufWriter::new(f);
+            for word in words {
+                writeln!(out, "{:08x}", word)?;
+            }
+            out.flush()
+        });
+        if let Err(e) = res {
+            eprintln!("ERROR: Failed to write signature {}: {}", path, e);
+            return ExitCode::FAILURE;
+        }
+    }
+    match vm.halt {
+        Some(Halt::Exit(code)) => ExitCode::from(code),
+        Some(Halt::Idle) => match build.idle_exit {
+            Some(code) => ExitCode::from(code),
+            None => {
+                eprintln!("ERROR: Guest hung at ip=0x{:08X}", vm.ip);
+                ExitCode::FAILURE
+            }
+        },
+        None => ExitCode::SUCCESS,
+    }
 }
@@ -0,0 +1,111 @@
+use crate::cache::Access;
+use crate::csr::{mstatus, Mode};
+use crate::trap::{self, Trap};
+
+// NOTE: Section 4.3 of the privileged manual for Sv32
+pub mod pte {
+    pub const V: u32 = 1 << 0;
+    pub const R: u32 = 1 << 1;
+    pub const W: u32 = 1 << 2;
+    pub const X: u32 = 1 << 3;
+    pub const U: u32 = 1 << 4;
+    pub const A: u32 = 1 << 6;
+    pub const D: u32 = 1 << 7;
+}
+pub const PAGE_SHIFT: u32 = 12;
+pub const PAGE_SIZE: u32 = 1 << PAGE_SHIFT;
+pub const SATP_MODE: u32 = 1 << 31;
+pub const SATP_PPN: u32 = 0x3F_FFFF;
+// ASIDs aren't implemented, so the field is hardwired to zero
+pub const SATP_MASK: u32 = SATP_MODE | SATP_PPN;
+const TLB_SIZE: usize = 64;
+
+#[inline]
+pub const fn page_fault(access: Access, vaddr: u32) -> Trap {
+    Trap::new(match access {
+        Access::Fetch => trap::INST_PAGE_FAULT,
+        Access::Read => trap::LOAD_PAGE_FAULT,
+        Access::Write => trap::STORE_PAGE_FAULT,
+    }, vaddr)
+}
+#[inline]
+pub const fn access_fault(access: Access, addr: u32) -> Trap {
+    Trap::new(match access {
+        Access::Fetch => trap::INST_ACCESS_FAULT,
+        Access::Read => trap::LOAD_ACCESS_FAULT,
+        Access::Write => trap::STORE_ACCESS_FAULT,
+    }, addr)
+}
+// Checks a leaf PTE against an access made from `mode`, SUM and MXR come from mstatus.
+// A and D are never set by the hart, software has to set them and a clear bit faults.
+pub const fn allowed(flags: u32, access: Access, mode: Mode, status: u32) -> bool {
+    if flags & pte::U != 0 {
+        if matches!(mode, Mode::Supervisor) && (matches!(access, Access::Fetch) || status & mstatus::SUM == 0) {
+            return false;
+        }
+    } else if matches!(mode, Mode::User) {
+        return false;
+    }
+    if flags & pte::A == 0 {
+        return false;
+    }
+    match access {
+        Access::Fetch => flags & pte::X != 0,
+        Access::Read => flags & pte::R != 0 || (status & mstatus::MXR != 0 && flags & pte::X != 0),
+        Access::Write => flags & pte::W != 0 && flags & pte::D != 0,
+    }
+}
+pub fn flags_str(flags: u32) -> String {
+    "vrwxugad".chars().enumerate().map(|(i, c)| if flags & (1 << i) != 0 { c } else { '-' }).collect()
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TlbEntry {
+    // Virtual and physical 4 KiB page numbers, megapages are cached one 4 KiB page at a time
+    pub vpn: u32,
+    pub ppn: u32,
+    pub flags: u32,
+    pub mega: bool,
+}
+// Direct mapped by virtual page number
+pub struct Tlb {
+    entries: [Option<TlbEntry>; TLB_SIZE],
+    pub hits: u64,
+    pub misses: u64,
+}
+impl Default for Tlb {
+    fn default() -> Self {
+        Self { entries: [None; TLB_SIZE], hits: 0, misses: 0 }
+    }
+}
+impl Tlb {
+    pub fn lookup(&mut self, vpn: u32) -> Option<TlbEntry> {
+        match self.entries[vpn as usize % TLB_SIZE] {
+            Some(entry) if entry.vpn == vpn => {
+                self.hits += 1;
+                Some(entry)
+            }
+            _ => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+    pub fn insert(&mut self, entry: TlbEntry) {
+        self.entries[entry.vpn as usize % TLB_SIZE] = Some(entry);
+    }
+    // Drops every entry, or only the ones mapping `vaddr` (any page of a megapage counts)
+    pub fn flush(&mut self, vaddr: Option<u32>) {
+        for slot in self.entries.iter_mut() {
+            let Some(entry) = slot else { continue; };
+            let hit = match vaddr {
+                None => true,
+                Some(vaddr) if entry.mega => entry.vpn >> 10 == vaddr >> 22,
+                Some(vaddr) => entry.vpn == vaddr >> PAGE_SHIFT,
+            };
+            if hit {
+                *slot = None;
+            }
+        }
+    }
+}
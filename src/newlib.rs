@@ -0,0 +1,88 @@
+use std::{fs::{self, Metadata}, io::{Seek, SeekFrom}, os::unix::fs::MetadataExt, time::{SystemTime, UNIX_EPOCH}};
+use crate::{linux::{self, errno::*, Process, AT_FDCWD, AT_SYMLINK_NOFOLLOW}, vm::VM};
+
+// NOTE: The ECALL convention of libgloss/riscv, what the proxy kernel implements:
+// number in a7, arguments in a0-a3, result or negated errno in a0.
+// Numbers below 1024 are the Linux generic ones, so everything shared goes to linux.rs.
+// The rest are libgloss' own for the calls Linux only has in their *at form.
+mod nr {
+    pub const LSEEK  : u32 = 62;
+    pub const FSTATAT: u32 = 79;
+    pub const FSTAT  : u32 = 80;
+    pub const GETTIMEOFDAY: u32 = 169;
+    pub const OPEN   : u32 = 1024;
+    pub const LINK   : u32 = 1025;
+    pub const UNLINK : u32 = 1026;
+    pub const MKDIR  : u32 = 1030;
+    pub const ACCESS : u32 = 1033;
+    pub const STAT   : u32 = 1038;
+    pub const LSTAT  : u32 = 1039;
+}
+const EOVERFLOW: i32 = 75;
+
+// libgloss/riscv/kernel_stat.h
+fn kernel_stat(meta: &Metadata) -> [u8; 128] {
+    let mut out = [0; 128];
+    let mut put = |off: usize, bytes: &[u8]| out[off..off+bytes.len()].copy_from_slice(bytes);
+    put(0, &meta.dev().to_le_bytes());
+    put(8, &meta.ino().to_le_bytes());
+    put(16, &meta.mode().to_le_bytes());
+    put(20, &(meta.nlink() as u32).to_le_bytes());
+    put(24, &meta.uid().to_le_bytes());
+    put(28, &meta.gid().to_le_bytes());
+    put(32, &meta.rdev().to_le_bytes());
+    put(48, &meta.size().to_le_bytes());
+    put(56, &(meta.blksize() as u32).to_le_bytes());
+    put(64, &meta.blocks().to_le_bytes());
+    // newlib's time_t is 64 bits
+    for (off, sec, nsec) in [(72, meta.atime(), meta.atime_nsec()), (88, meta.mtime(), meta.mtime_nsec()), (104, meta.ctime(), meta.ctime_nsec())] {
+        put(off, &sec.to_le_bytes());
+        put(off+8, &(nsec as u32).to_le_bytes());
+    }
+    out
+}
+
+pub fn call(vm: &mut VM, p: &mut Process, nr: u32, a: [u32; 6]) -> Result<i32, i32> {
+    let stat = |vm: &mut VM, p: &mut Process, dirfd: u32, path: u32, buf: u32, flags: u32| {
+        let path = p.path(vm, dirfd, path)?;
+        let meta = if flags & AT_SYMLINK_NOFOLLOW != 0 { fs::symlink_metadata(path) } else { fs::metadata(path) };
+        linux::put(vm, buf, &kernel_stat(&meta.map_err(linux::host)?))?;
+        Ok(0)
+    };
+    match nr {
+        // Offsets are a plain long here
+        nr::LSEEK => {
+            let from = match a[2] {
+                0 => SeekFrom::Start(a[1] as u64),
+                1 => SeekFrom::Current(a[1] as i32 as i64),
+                2 => SeekFrom::End(a[1] as i32 as i64),
+                _ => return Err(EINVAL),
+            };
+            let pos = p.fd(a[0])?.file()?.seek(from).map_err(linux::host)?;
+            i32::try_from(pos).map_err(|_| EOVERFLOW)
+        }
+        nr::FSTAT => {
+            let meta = p.fd(a[0])?.metadata().map_err(linux::host)?;
+            linux::put(vm, a[1], &kernel_stat(&meta))?;
+            Ok(0)
+        }
+        nr::FSTATAT => stat(vm, p, a[0], a[1], a[2], a[3]),
+        nr::STAT => stat(vm, p, AT_FDCWD, a[0], a[1], 0),
+        nr::LSTAT => stat(vm, p, AT_FDCWD, a[0], a[1], AT_SYMLINK_NOFOLLOW),
+        nr::GETTIMEOFDAY => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            let tv = [&now.as_secs().to_le_bytes()[..], &now.subsec_micros().to_le_bytes(), &[0; 4]].concat();
+            linux::put(vm, a[0], &tv)?;
+            Ok(0)
+        }
+        nr::LINK => {
+            fs::hard_link(p.path(vm, AT_FDCWD, a[0])?, p.path(vm, AT_FDCWD, a[1])?).map_err(linux::host)?;
+            Ok(0)
+        }
+        nr::OPEN => linux::call(vm, p, linux::nr::OPENAT, [AT_FDCWD, a[0], a[1], a[2], 0, 0]),
+        nr::UNLINK => linux::call(vm, p, linux::nr::UNLINKAT, [AT_FDCWD, a[0], 0, 0, 0, 0]),
+        nr::MKDIR => linux::call(vm, p, linux::nr::MKDIRAT, [AT_FDCWD, a[0], a[1], 0, 0, 0]),
+        nr::ACCESS => linux::call(vm, p, linux::nr::FACCESSAT, [AT_FDCWD, a[0], a[1], 0, 0, 0]),
+        _ => linux::call(vm, p, nr, a),
+    }
+}
@@ -2,11 +2,38 @@ pub const AUIPC_OP   : i32 = 0b0010111;
 pub const LUI_OP     : i32 = 0b0110111;
 pub const IMM_MATH_OP: i32 = 0b0010011;
 pub mod imm_math {
-    pub const ADDI: i32 = 0x0;
+    pub const ADDI : i32 = 0x0;
+    pub const SLLI : i32 = 0x1;
+    pub const SLTI : i32 = 0x2;
+    pub const SLTIU: i32 = 0x3;
+    pub const XORI : i32 = 0x4;
+    // SRAI when funct7 is ARITH_SHIFT
+    pub const SRLI : i32 = 0x5;
+    pub const ORI  : i32 = 0x6;
+    pub const ANDI : i32 = 0x7;
+    pub const ARITH_SHIFT: i32 = 0x20;
 }
 pub const REG_MATH_OP: i32 = 0b0110011;
 pub mod reg_math {
-    pub const ADD: (i32, i32) = (0x0, 0x0);
+    pub const ADD : (i32, i32) = (0x0, 0x00);
+    pub const SUB : (i32, i32) = (0x0, 0x20);
+    pub const SLL : (i32, i32) = (0x1, 0x00);
+    pub const SLT : (i32, i32) = (0x2, 0x00);
+    pub const SLTU: (i32, i32) = (0x3, 0x00);
+    pub const XOR : (i32, i32) = (0x4, 0x00);
+    pub const SRL : (i32, i32) = (0x5, 0x00);
+    pub const SRA : (i32, i32) = (0x5, 0x20);
+    pub const OR  : (i32, i32) = (0x6, 0x00);
+    pub const AND : (i32, i32) = (0x7, 0x00);
+    // M extension
+    pub const MUL   : (i32, i32) = (0x0, 0x01);
+    pub const MULH  : (i32, i32) = (0x1, 0x01);
+    pub const MULHSU: (i32, i32) = (0x2, 0x01);
+    pub const MULHU : (i32, i32) = (0x3, 0x01);
+    pub const DIV   : (i32, i32) = (0x4, 0x01);
+    pub const DIVU  : (i32, i32) = (0x5, 0x01);
+    pub const REM   : (i32, i32) = (0x6, 0x01);
+    pub const REMU  : (i32, i32) = (0x7, 0x01);
 }
 pub const STORE_OP   : i32 = 0b0100011;
 pub mod store {
@@ -27,6 +54,12 @@ pub const JUMP_REG_OP: i32 = 0b1100111;
 pub mod jump_reg {
     pub const JALR: i32 = 0x0;
 }
+// Link registers as defined by the RISC-V calling convention (ra and t0),
+// a jump writing or reading one is a call or return hint
+#[inline]
+pub const fn is_link(reg: i32) -> bool {
+    reg == 1 || reg == 5
+}
 pub const BRANCH_OP  : i32 = 0b1100011;
 pub mod branch {
     pub const BEQ : i32 = 0x0;
@@ -36,3 +69,42 @@ pub mod branch {
     pub const BLTU: i32 = 0x6;
     pub const BGEU: i32 = 0x7;
 }
+pub const MISC_MEM_OP: i32 = 0b0001111;
+pub mod misc_mem {
+    pub const FENCE  : i32 = 0x0;
+    pub const FENCE_I: i32 = 0x1;
+}
+pub const AMO_OP     : i32 = 0b0101111;
+pub mod amo {
+    // Only word sized atomics on RV32
+    pub const W: i32 = 0x2;
+    // funct5, the top of funct7 above aq and rl
+    pub const LR  : i32 = 0x02;
+    pub const SC  : i32 = 0x03;
+    pub const SWAP: i32 = 0x01;
+    pub const ADD : i32 = 0x00;
+    pub const XOR : i32 = 0x04;
+    pub const AND : i32 = 0x0C;
+    pub const OR  : i32 = 0x08;
+    pub const MIN : i32 = 0x10;
+    pub const MAX : i32 = 0x14;
+    pub const MINU: i32 = 0x18;
+    pub const MAXU: i32 = 0x1C;
+}
+pub const SYSTEM_OP  : i32 = 0b1110011;
+pub mod system {
+    pub const PRIV  : i32 = 0x0;
+    pub const CSRRW : i32 = 0x1;
+    pub const CSRRS : i32 = 0x2;
+    pub const CSRRC : i32 = 0x3;
+    pub const CSRRWI: i32 = 0x5;
+    pub const CSRRSI: i32 = 0x6;
+    pub const CSRRCI: i32 = 0x7;
+    pub const ECALL : i32 = 0x000;
+    pub const EBREAK: i32 = 0x001;
+    pub const SRET  : i32 = 0x102;
+    pub const WFI   : i32 = 0x105;
+    pub const MRET  : i32 = 0x302;
+    // funct7, rs1 and rs2 hold the address and ASID
+    pub const SFENCE_VMA: i32 = 0x09;
+}
@@ -0,0 +1,87 @@
+use crate::csr::irq;
+
+// Register layout of the SiFive PLIC
+pub const PRIORITY : usize = 0x0;
+pub const PENDING  : usize = 0x1000;
+pub const ENABLE   : usize = 0x2000;
+pub const ENABLE_STRIDE : usize = 0x80;
+pub const CONTEXT  : usize = 0x200000;
+pub const CONTEXT_STRIDE: usize = 0x1000;
+pub const SIZE     : usize = 0x4000000;
+// Source 0 doesn't exist, so 1-31 are usable
+pub const SOURCES  : usize = 32;
+// M-mode and S-mode of hart 0, in that order like on QEMU's virt machine
+pub const CONTEXTS : usize = 2;
+const PRIORITY_MASK: u32 = 0x7;
+
+#[derive(Default)]
+pub struct Plic {
+    pub priority: [u32; SOURCES],
+    pub pending: u32,
+    pub enable: [u32; CONTEXTS],
+    pub threshold: [u32; CONTEXTS],
+    // Claimed but not completed yet, the gateway holds these back
+    claimed: u32,
+    // Current level of each interrupt line
+    level: u32,
+}
+impl Plic {
+    // Lines are level triggered, a high line becomes pending again after completion
+    pub fn set_line(&mut self, source: usize, high: bool) {
+        if source == 0 || source >= SOURCES { return; }
+        if high {
+            self.level |= 1 << source;
+        } else {
+            self.level &= !(1 << source);
+        }
+        self.pending |= self.level & !self.claimed;
+    }
+    // Highest priority interrupt that's pending, enabled and above the threshold, lowest id on ties
+    fn best(&self, ctx: usize) -> usize {
+        let mut best = (0, 0);
+        for source in 1..SOURCES {
+            let prio = self.priority[source];
+            if (self.pending & self.enable[ctx]) >> source & 1 != 0 && prio > self.threshold[ctx] && prio > best.1 {
+                best = (source, prio);
+            }
+        }
+        best.0
+    }
+    pub fn claim(&mut self, ctx: usize) -> u32 {
+        let source = self.best(ctx);
+        if source != 0 {
+            self.pending &= !(1 << source);
+            self.claimed |= 1 << source;
+        }
+        source as u32
+    }
+    pub fn complete(&mut self, ctx: usize, source: u32) {
+        let source = source as usize;
+        if source == 0 || source >= SOURCES || self.enable[ctx] >> source & 1 == 0 { return; }
+        self.claimed &= !(1 << source);
+        self.pending |= self.level & !self.claimed;
+    }
+    pub fn write_priority(&mut self, source: usize, v: u32) {
+        if source != 0 && source < SOURCES {
+            self.priority[source] = v & PRIORITY_MASK;
+        }
+    }
+    pub fn write_enable(&mut self, ctx: usize, v: u32) {
+        self.enable[ctx] = v & !1;
+    }
+    pub fn write_threshold(&mut self, ctx: usize, v: u32) {
+        self.threshold[ctx] = v & PRIORITY_MASK;
+    }
+    // Every register and line level, little endian
+    pub fn snapshot(&self) -> Vec<u8> {
+        let words = self.priority.iter().chain(&self.enable).chain(&self.threshold).chain([&self.pending, &self.claimed, &self.level]);
+        words.flat_map(|x| x.to_le_bytes()).collect()
+    }
+    // MEIP and SEIP as they should show up in mip
+    pub fn interrupts(&self) -> u32 {
+        let mut bits = 0;
+        if self.best(0) != 0 { bits |= irq::MEI; }
+        if self.best(1) != 0 { bits |= irq::SEI; }
+        bits
+    }
+}
@@ -0,0 +1,98 @@
+use crate::cache::Access;
+use crate::csr::Mode;
+
+// NOTE: Section 3.7 of the privileged manual
+pub mod cfg {
+    pub const R: u8 = 1 << 0;
+    pub const W: u8 = 1 << 1;
+    pub const X: u8 = 1 << 2;
+    pub const A_SHIFT: u8 = 3;
+    pub const A: u8 = 0b11 << A_SHIFT;
+    pub const L: u8 = 1 << 7;
+    // Address matching modes in A, zero turns the entry off
+    pub const TOR  : u8 = 1;
+    pub const NA4  : u8 = 2;
+    pub const NAPOT: u8 = 3;
+}
+pub const ENTRIES: usize = 16;
+
+#[derive(Default)]
+pub struct Pmp {
+    pub cfg: [u8; ENTRIES],
+    // Physical address bits 33:2
+    pub addr: [u32; ENTRIES],
+}
+impl Pmp {
+    #[inline]
+    fn locked(&self, i: usize) -> bool {
+        self.cfg[i] & cfg::L != 0
+    }
+    #[inline]
+    fn matching(&self, i: usize) -> u8 {
+        (self.cfg[i] & cfg::A) >> cfg::A_SHIFT
+    }
+    // Each pmpcfg register packs the configuration of four entries
+    pub fn read_cfg(&self, reg: usize) -> u32 {
+        u32::from_le_bytes(self.cfg[reg*4..reg*4+4].try_into().unwrap())
+    }
+    pub fn write_cfg(&mut self, reg: usize, v: u32) {
+        for (i, byte) in v.to_le_bytes().into_iter().enumerate() {
+            let i = reg*4 + i;
+            if self.locked(i) { continue; }
+            let mut byte = byte & (cfg::R | cfg::W | cfg::X | cfg::A | cfg::L);
+            // R=0 W=1 is reserved
+            if byte & (cfg::R | cfg::W) == cfg::W {
+                byte &= !cfg::W;
+            }
+            self.cfg[i] = byte;
+        }
+    }
+    pub fn write_addr(&mut self, i: usize, v: u32) {
+        // A locked TOR entry also locks the address below it
+        let top_locked = i+1 < ENTRIES && self.locked(i+1) && self.matching(i+1) == cfg::TOR;
+        if self.locked(i) || top_locked { return; }
+        self.addr[i] = v;
+    }
+    // What firmware leaves behind for the lower modes: a single NAPOT entry over everything, like OpenSBI
+    pub fn allow_all(&mut self) {
+        self.write_addr(0, u32::MAX);
+        self.write_cfg(0, (cfg::R | cfg::W | cfg::X | (cfg::NAPOT << cfg::A_SHIFT)) as u32);
+    }
+    fn range(&self, i: usize) -> Option<(u64, u64)> {
+        let addr = self.addr[i] as u64;
+        match self.matching(i) {
+            cfg::TOR => Some((if i == 0 { 0 } else { (self.addr[i-1] as u64) << 2 }, addr << 2)),
+            cfg::NA4 => Some((addr << 2, (addr << 2) + 4)),
+            cfg::NAPOT => {
+                let size = 8u64 << addr.trailing_ones();
+                let base = (addr << 2) & !(size-1);
+                Some((base, base + size))
+            }
+            _ => None,
+        }
+    }
+    // Whether `len` bytes at physical `addr` can be accessed from `mode`.
+    // The lowest numbered entry that overlaps the access decides.
+    pub fn check(&self, addr: u64, len: usize, access: Access, mode: Mode) -> bool {
+        let end = addr + len as u64;
+        for i in 0..ENTRIES {
+            let Some((lo, hi)) = self.range(i) else { continue; };
+            if lo >= hi || end <= lo || addr >= hi { continue; }
+            // Accesses only partially inside the entry fail no matter the permissions
+            if addr < lo || end > hi {
+                return false;
+            }
+            if mode == Mode::Machine && !self.locked(i) {
+                return true;
+            }
+            let bit = match access {
+                Access::Fetch => cfg::X,
+                Access::Read => cfg::R,
+                Access::Write => cfg::W,
+            };
+            return self.cfg[i] & bit != 0;
+        }
+        // Every entry is implemented, so S and U are denied anything left unmatched
+        mode == Mode::Machine
+    }
+}
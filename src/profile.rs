@@ -0,0 +1,98 @@
+use std::{collections::HashMap, io::{self, Write}};
+
+use crate::{elf::Symbols, inst::Inst32, ops};
+
+struct Frame {
+    parent: usize,
+    func: u32,
+    count: u64,
+    children: HashMap<u32, usize>,
+}
+// Counts retired instructions per pc and per call stack.
+// Call stacks are rebuilt from the JAL/JALR hints in Table 2.1 of the unprivileged spec:
+// a jump that writes a link register is a call, a JALR through one that doesn't is a return.
+// Stacks are kept as a tree of frames so each retired instruction is a single increment.
+pub struct Profiler {
+    pcs: HashMap<u32, u64>,
+    frames: Vec<Frame>,
+    current: usize,
+}
+impl Profiler {
+    pub fn new(entry: u32) -> Self {
+        Self {
+            pcs: HashMap::new(),
+            frames: vec![Frame { parent: 0, func: entry, count: 0, children: HashMap::new() }],
+            current: 0,
+        }
+    }
+    fn call(&mut self, func: u32) {
+        let next = self.frames.len();
+        let current = self.current;
+        let child = *self.frames[current].children.entry(func).or_insert(next);
+        if child == next {
+            self.frames.push(Frame { parent: current, func, count: 0, children: HashMap::new() });
+        }
+        self.current = child;
+    }
+    fn ret(&mut self) {
+        self.current = self.frames[self.current].parent;
+    }
+    pub fn retire(&mut self, pc: u32, inst: Inst32, next: u32) {
+        *self.pcs.entry(pc).or_insert(0) += 1;
+        self.frames[self.current].count += 1;
+        match inst.opcode() {
+            ops::JUMP_OP if ops::is_link(inst.rd()) => self.call(next),
+            ops::JUMP_REG_OP => {
+                match (ops::is_link(inst.rd()), ops::is_link(inst.r1())) {
+                    (false, false) => {}
+                    (false, true) => self.ret(),
+                    (true, false) => self.call(next),
+                    (true, true) => {
+                        // Coroutine swap
+                        if inst.rd() != inst.r1() { self.ret(); }
+                        self.call(next);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    fn stack(&self, mut frame: usize, symbols: &Symbols) -> String {
+        let mut names = Vec::new();
+        loop {
+            names.push(symbols.name(self.frames[frame].func));
+            if frame == 0 { break; }
+            frame = self.frames[frame].parent;
+        }
+        names.reverse();
+        names.join(";")
+    }
+    // Folded stacks, as consumed by inferno and flamegraph.pl
+    pub fn write_folded(&self, out: &mut impl Write, symbols: &Symbols) -> io::Result<()> {
+        let mut stacks: HashMap<String, u64> = HashMap::new();
+        for (i, frame) in self.frames.iter().enumerate() {
+            if frame.count == 0 { continue; }
+            *stacks.entry(self.stack(i, symbols)).or_insert(0) += frame.count;
+        }
+        let mut stacks: Vec<_> = stacks.into_iter().collect();
+        stacks.sort();
+        for (stack, count) in stacks {
+            writeln!(out, "{} {}", stack, count)?;
+        }
+        Ok(())
+    }
+    pub fn write_flat(&self, out: &mut impl Write, symbols: &Symbols) -> io::Result<()> {
+        let mut funcs: HashMap<String, u64> = HashMap::new();
+        for (&pc, &count) in self.pcs.iter() {
+            *funcs.entry(symbols.name(pc)).or_insert(0) += count;
+        }
+        let total: u64 = funcs.values().sum();
+        let mut funcs: Vec<_> = funcs.into_iter().collect();
+        funcs.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        writeln!(out, "{:>12} {:>7}  symbol", "instructions", "%")?;
+        for (name, count) in funcs {
+            writeln!(out, "{:>12} {:>6.2}%  {}", count, count as f64 * 100.0 / total.max(1) as f64, name)?;
+        }
+        Ok(())
+    }
+}
@@ -1,11 +1,13 @@
-use std::process::exit;
-
-use crate::vm::VM;
+use crate::{clint::{self, Clint, Timebase}, csr::irq, plic::{self, Plic}, uart::Uart, vm::{Halt, VM}};
 
 pub struct RegionList(pub Box<[Region]>);
 impl RegionList {
     pub fn find_region(&self, addr: usize) -> Option<&Region> {
-        Some(&self.0[self.0.binary_search_by(|x| {
+        Some(&self.0[self.find(addr)?])
+    }
+    // Index of the region `addr` falls in
+    pub fn find(&self, addr: usize) -> Option<usize> {
+        self.0.binary_search_by(|x| {
             if addr < x.addr {
                 std::cmp::Ordering::Greater // Search in the left half
             } else if addr >= x.addr + x.size {
@@ -13,75 +15,334 @@ impl RegionList {
             } else {
                 std::cmp::Ordering::Equal // Found the region
             }
-        }).ok()?])
+        }).ok()
+    }
+    // The first region with a device of `kind` behind it
+    pub fn find_kind(&self, kind: Kind) -> Option<&Region> {
+        self.0.iter().find(|x| x.device.kind() == kind)
+    }
+    // Bytes of memory mapped in all
+    pub fn ram_size(&self) -> usize {
+        self.0.iter().filter(|x| x.device.kind() == Kind::Memory).map(|x| x.size).sum()
     }
 }
 
-pub struct RegionMeta {
-    pub write: fn (region: &Region, vm: &mut VM, off: usize, bytes: &    [u8]) -> Result<(), ()>,
-    pub read : fn (region: &Region, vm: &mut VM, off: usize, bytes: &mut [u8]) -> Result<(), ()>,
-}
-pub struct MemoryMeta;
-impl MemoryMeta {
-    #[inline]
-    pub const fn new() -> RegionMeta {
-        RegionMeta { write: Self::write, read: Self::read }
-    }
-    fn write(region: &Region, vm: &mut VM, off: usize, bytes: &    [u8]) -> Result<(), ()> {
-        vm.ram[region.addr+off..region.addr+off+bytes.len()].copy_from_slice(bytes);
+// What sits behind a region, so the machine can be described to the guest
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+    Memory,
+    Serial,
+    Exit,
+    Clint,
+    Plic,
+    Uart,
+    Finisher,
+    // Left out of the device tree
+    Other,
+}
+/// A device model behind a [`Region`], with whatever state it needs kept in `self`.
+///
+/// Accesses come in at offsets from the start of the region and are `bytes.len()` wide:
+/// 1, 2 or 4 bytes for the guest's loads and stores, anything for the host's own copies.
+/// Every method gets the whole VM, to raise an interrupt through [`VM::set_line`] or stop it through `vm.halt`.
+/// The device is lifted out of its region while it runs, so it can't access that region through the VM.
+#[allow(clippy::result_unit_err)]
+pub trait Device {
+    fn kind(&self) -> Kind { Kind::Other }
+    fn read (&mut self, vm: &mut VM, off: usize, bytes: &mut [u8]) -> Result<(), ()>;
+    fn write(&mut self, vm: &mut VM, off: usize, bytes: &    [u8]) -> Result<(), ()>;
+    /// Called every few hundred instructions, `vm.instret` and `vm.cycles` tell how far the hart got.
+    fn tick(&mut self, _vm: &mut VM) {}
+    /// Back to the state it powered on in.
+    fn reset(&mut self, _vm: &mut VM) {}
+    /// The device's state as bytes, to save or compare it. Empty when there's nothing to keep.
+    fn snapshot(&self, _vm: &VM) -> Vec<u8> { Vec::new() }
+    /// Interrupt line `line` into the device changed level, see [`VM::set_line`]. Only controllers care.
+    fn set_line(&mut self, _vm: &mut VM, _line: usize, _high: bool) {}
+    /// Blocks until the device raises one of the hart's interrupts in `wake` (mip bits),
+    /// false if it never will. A WFI with nothing else to wait for ends up here.
+    fn wait(&mut self, _vm: &mut VM, _wake: u32) -> bool { false }
+    /// The bytes behind a RAM-like device, so loaders and host calls can reach them directly.
+    fn memory(&mut self) -> Option<&mut [u8]> { None }
+}
+// Stands in for a device while it's out of its region
+pub(crate) struct Busy;
+impl Device for Busy {
+    fn read (&mut self, _: &mut VM, _: usize, _: &mut [u8]) -> Result<(), ()> { Err(()) }
+    fn write(&mut self, _: &mut VM, _: usize, _: &    [u8]) -> Result<(), ()> { Err(()) }
+}
+// RAM, owning its bytes. A machine mapping one image around a hole splits it between two of these.
+pub struct Memory {
+    bytes: Vec<u8>,
+}
+impl Memory {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self { bytes }
+    }
+}
+impl Device for Memory {
+    fn kind(&self) -> Kind { Kind::Memory }
+    fn write(&mut self, _: &mut VM, off: usize, bytes: &    [u8]) -> Result<(), ()> {
+        self.bytes[off..off+bytes.len()].copy_from_slice(bytes);
         Ok(())
     }
-    fn read (region: &Region, vm: &mut VM, off: usize, bytes: &mut [u8]) -> Result<(), ()> {
-        bytes.copy_from_slice(&vm.ram[region.addr+off..region.addr+off+bytes.len()]);
+    fn read (&mut self, _: &mut VM, off: usize, bytes: &mut [u8]) -> Result<(), ()> {
+        bytes.copy_from_slice(&self.bytes[off..off+bytes.len()]);
         Ok(())
     }
+    fn memory(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.bytes)
+    }
 }
-pub struct SerialMeta;
-impl SerialMeta {
-    #[inline]
-    pub const fn new() -> RegionMeta {
-        RegionMeta { write: Self::write, read: MemoryMeta::read }
+// Write-only ports, they read as zero
+pub struct Serial;
+impl Device for Serial {
+    fn kind(&self) -> Kind { Kind::Serial }
+    fn read(&mut self, _: &mut VM, _: usize, bytes: &mut [u8]) -> Result<(), ()> {
+        bytes.fill(0);
+        Ok(())
     }
-    fn write(_: &Region, _: &mut VM, _: usize, bytes: &[u8]) -> Result<(), ()> {
+    fn write(&mut self, _: &mut VM, _: usize, bytes: &[u8]) -> Result<(), ()> {
         print!("{}", bytes[0] as char);
         Ok(())
     }
 }
-pub struct ExitMeta;
-impl ExitMeta {
-    #[inline]
-    pub const fn new() -> RegionMeta {
-        RegionMeta { write: Self::write, read: MemoryMeta::read }
+pub struct Exit;
+impl Device for Exit {
+    fn kind(&self) -> Kind { Kind::Exit }
+    fn read(&mut self, _: &mut VM, _: usize, bytes: &mut [u8]) -> Result<(), ()> {
+        bytes.fill(0);
+        Ok(())
     }
-    fn write(_: &Region, _: &mut VM, _: usize, bytes: &[u8]) -> Result<(), ()> {
-        exit(bytes[0] as i32)
+    fn write(&mut self, vm: &mut VM, _: usize, bytes: &[u8]) -> Result<(), ()> {
+        vm.halt = Some(Halt::Exit(bytes[0]));
+        Ok(())
     }
 }
-pub struct Region {
-    pub meta: RegionMeta,
-    pub addr: usize,
-    pub size: usize
-}
-impl Region {
-    pub fn write(&self, vm: &mut VM, off: usize, bytes: &[u8]) -> Result<(), ()> {
-        (self.meta.write)(self, vm, off, bytes)
-        /*
-        match self.addr {
-            SERIAL_OUT => {
-                print!("{}", bytes[0] as char);
-            }
-            EXIT => {
-                eprintln!("Exiting with {}", bytes[0] as i32);
-                exit(bytes[0] as i32)
+// Timer and software interrupts, mtime is the timebase the hart's time CSR reads too.
+// MTIP follows mtime on every access and tick, so it can go up a few hundred instructions late.
+#[derive(Default)]
+pub struct ClintMmio(Clint);
+impl ClintMmio {
+    fn update(&self, vm: &mut VM) {
+        vm.set_irq(irq::MSI | irq::MTI, self.0.pending(&vm.time));
+    }
+}
+impl Device for ClintMmio {
+    fn kind(&self) -> Kind { Kind::Clint }
+    // Goes byte by byte, so any access width lines up with the 32 and 64 bit registers
+    fn read(&mut self, vm: &mut VM, off: usize, bytes: &mut [u8]) -> Result<(), ()> {
+        let mtimecmp = self.0.mtimecmp.to_le_bytes();
+        let mtime = vm.time.mtime().to_le_bytes();
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = match off+i {
+                clint::MSIP => self.0.msip as u8,
+                o @ clint::MTIMECMP..=0x4007 => mtimecmp[o-clint::MTIMECMP],
+                o @ clint::MTIME..=0xBFFF => mtime[o-clint::MTIME],
+                _ => 0,
+            };
+        }
+        self.update(vm);
+        Ok(())
+    }
+    fn write(&mut self, vm: &mut VM, off: usize, bytes: &[u8]) -> Result<(), ()> {
+        let mut mtimecmp = self.0.mtimecmp.to_le_bytes();
+        let mut mtime = vm.time.mtime().to_le_bytes();
+        let mut mtime_written = false;
+        for (i, byte) in bytes.iter().copied().enumerate() {
+            match off+i {
+                clint::MSIP => self.0.msip = byte & 1 != 0,
+                o @ clint::MTIMECMP..=0x4007 => mtimecmp[o-clint::MTIMECMP] = byte,
+                o @ clint::MTIME..=0xBFFF => {
+                    mtime[o-clint::MTIME] = byte;
+                    mtime_written = true;
+                }
+                _ => {}
             }
-            _ => {
-                vm.ram[self.addr+off..self.addr+off+bytes.len()].copy_from_slice(bytes);
+        }
+        self.0.mtimecmp = u64::from_le_bytes(mtimecmp);
+        if mtime_written {
+            vm.time.set_mtime(u64::from_le_bytes(mtime));
+        }
+        self.update(vm);
+        Ok(())
+    }
+    fn tick(&mut self, vm: &mut VM) {
+        self.update(vm);
+    }
+    fn reset(&mut self, vm: &mut VM) {
+        self.0 = Clint::default();
+        vm.time = Timebase::new(vm.time.clock);
+        self.update(vm);
+    }
+    fn snapshot(&self, vm: &VM) -> Vec<u8> {
+        let mut bytes = vec![self.0.msip as u8];
+        bytes.extend(self.0.mtimecmp.to_le_bytes());
+        bytes.extend(vm.time.mtime().to_le_bytes());
+        bytes
+    }
+    fn wait(&mut self, vm: &mut VM, wake: u32) -> bool {
+        if wake & irq::MTI == 0 || !self.0.wait(&mut vm.time) { return false; }
+        self.update(vm);
+        true
+    }
+}
+// External interrupt controller. Context 0 drives the hart's MEIP and context 1 its SEIP.
+#[derive(Default)]
+pub struct PlicMmio(Plic);
+impl PlicMmio {
+    // Splits an offset into the context it belongs to and the offset inside of it
+    fn context(off: usize, base: usize, stride: usize) -> Option<(usize, usize)> {
+        let ctx = off.checked_sub(base)? / stride;
+        if ctx >= plic::CONTEXTS { return None; }
+        Some((ctx, off - base - ctx*stride))
+    }
+    fn read_word(&mut self, off: usize) -> u32 {
+        if off < plic::PENDING {
+            return self.0.priority.get((off-plic::PRIORITY)/4).copied().unwrap_or(0);
+        }
+        if off == plic::PENDING {
+            return self.0.pending;
+        }
+        if let Some((ctx, 0)) = Self::context(off, plic::ENABLE, plic::ENABLE_STRIDE) {
+            return self.0.enable[ctx];
+        }
+        match Self::context(off, plic::CONTEXT, plic::CONTEXT_STRIDE) {
+            Some((ctx, 0)) => self.0.threshold[ctx],
+            Some((ctx, 4)) => self.0.claim(ctx),
+            _ => 0,
+        }
+    }
+    fn write_word(&mut self, off: usize, v: u32) {
+        if off < plic::PENDING {
+            self.0.write_priority((off-plic::PRIORITY)/4, v);
+        } else if let Some((ctx, 0)) = Self::context(off, plic::ENABLE, plic::ENABLE_STRIDE) {
+            self.0.write_enable(ctx, v);
+        } else {
+            match Self::context(off, plic::CONTEXT, plic::CONTEXT_STRIDE) {
+                Some((ctx, 0)) => self.0.write_threshold(ctx, v),
+                Some((ctx, 4)) => self.0.complete(ctx, v),
+                _ => {}
             }
         }
+    }
+    fn update(&self, vm: &mut VM) {
+        vm.set_irq(irq::MEI | irq::SEI, self.0.interrupts());
+    }
+}
+impl Device for PlicMmio {
+    fn kind(&self) -> Kind { Kind::Plic }
+    // All registers are 32 bits wide, narrower reads see part of the word
+    // and narrower writes are dropped
+    fn read(&mut self, vm: &mut VM, off: usize, bytes: &mut [u8]) -> Result<(), ()> {
+        let word = self.read_word(off & !3).to_le_bytes();
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = word.get((off & 3) + i).copied().unwrap_or(0);
+        }
+        // Claims change what's pending
+        self.update(vm);
+        Ok(())
+    }
+    fn write(&mut self, vm: &mut VM, off: usize, bytes: &[u8]) -> Result<(), ()> {
+        if let (0, Ok(word)) = (off & 3, bytes.try_into()) {
+            self.write_word(off, u32::from_le_bytes(word));
+        }
+        self.update(vm);
+        Ok(())
+    }
+    fn reset(&mut self, vm: &mut VM) {
+        self.0 = Plic::default();
+        self.update(vm);
+    }
+    fn snapshot(&self, _: &VM) -> Vec<u8> {
+        self.0.snapshot()
+    }
+    fn set_line(&mut self, vm: &mut VM, line: usize, high: bool) {
+        self.0.set_line(line, high);
+        self.update(vm);
+    }
+}
+// NS16550A, its interrupt output goes to line `line` of the controller mapped at `plic`
+pub struct UartMmio {
+    uart: Uart,
+    plic: usize,
+    line: usize,
+}
+impl UartMmio {
+    pub fn new(uart: Uart, plic: usize, line: usize) -> Self {
+        Self { uart, plic, line }
+    }
+    fn update(&self, vm: &mut VM) {
+        vm.set_line(self.plic, self.line, self.uart.interrupting());
+    }
+}
+impl Device for UartMmio {
+    fn kind(&self) -> Kind { Kind::Uart }
+    fn read(&mut self, vm: &mut VM, off: usize, bytes: &mut [u8]) -> Result<(), ()> {
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.uart.read(off+i);
+        }
+        self.update(vm);
+        Ok(())
+    }
+    fn write(&mut self, vm: &mut VM, off: usize, bytes: &[u8]) -> Result<(), ()> {
+        for (i, byte) in bytes.iter().copied().enumerate() {
+            self.uart.write(off+i, byte);
+        }
+        self.update(vm);
+        Ok(())
+    }
+    // Picks up host input
+    fn tick(&mut self, vm: &mut VM) {
+        self.uart.poll();
+        self.update(vm);
+        if self.uart.quit {
+            vm.halt = Some(Halt::Exit(0));
+        }
+    }
+    fn reset(&mut self, vm: &mut VM) {
+        self.uart.reset();
+        self.update(vm);
+    }
+    fn snapshot(&self, _: &VM) -> Vec<u8> {
+        self.uart.snapshot()
+    }
+    fn wait(&mut self, vm: &mut VM, wake: u32) -> bool {
+        if wake & (irq::MEI | irq::SEI) == 0 || !self.uart.can_interrupt() || !self.uart.wait() { return false; }
+        self.update(vm);
+        true
+    }
+}
+// SiFive test device, the guest powers off by writing a status word
+pub struct Finisher;
+impl Finisher {
+    const FAIL : u32 = 0x3333;
+    const PASS : u32 = 0x5555;
+    const RESET: u32 = 0x7777;
+}
+impl Device for Finisher {
+    fn kind(&self) -> Kind { Kind::Finisher }
+    fn read(&mut self, _: &mut VM, _: usize, bytes: &mut [u8]) -> Result<(), ()> {
+        bytes.fill(0);
         Ok(())
-        */
     }
-    pub fn read(&self, vm: &mut VM, off: usize, bytes: &mut [u8]) -> Result<(), ()> {
-        (self.meta.read)(self, vm, off, bytes)
+    fn write(&mut self, vm: &mut VM, off: usize, bytes: &[u8]) -> Result<(), ()> {
+        let (0, Ok(word)) = (off, <[u8; 4]>::try_from(bytes)) else { return Ok(()); };
+        let word = u32::from_le_bytes(word);
+        match word & 0xFFFF {
+            Self::FAIL => vm.halt = Some(Halt::Exit((word >> 16) as u8)),
+            // NOTE: There's no way to reset the machine, so a reset stops it like a pass
+            Self::PASS | Self::RESET => vm.halt = Some(Halt::Exit(0)),
+            _ => {}
+        }
+        Ok(())
     }
 }
+/// A device mapped at physical addresses `addr..addr+size`.
+pub struct Region {
+    pub device: Box<dyn Device>,
+    pub addr: usize,
+    pub size: usize,
+    // Extra cycles per access, used by the timing model
+    pub latency: u32,
+}
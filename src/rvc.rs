@@ -0,0 +1,135 @@
+use crate::{inst::Inst32, ops::{self, branch, imm_math, jump_reg, load, reg_math, store, system}};
+
+// NOTE: Chapter 16 of the unprivileged manual for the C extension.
+// Every compressed instruction is a shorter encoding of a 32-bit one,
+// so they are expanded here and executed like any other instruction.
+// The floating point loads and stores are illegal since there is no F or D.
+const X0: i32 = 0;
+const RA: i32 = 1;
+const SP: i32 = 2;
+
+fn r_type(opcode: i32, (funct3, funct7): (i32, i32), rd: i32, r1: i32, r2: i32) -> Inst32 {
+    Inst32::new((funct7 << 25 | r2 << 20 | r1 << 15 | funct3 << 12 | rd << 7 | opcode) as u32)
+}
+fn i_type(opcode: i32, funct3: i32, rd: i32, r1: i32, imm: i32) -> Inst32 {
+    Inst32::new(((imm & 0xFFF) << 20 | r1 << 15 | funct3 << 12 | rd << 7 | opcode) as u32)
+}
+fn s_type(funct3: i32, r1: i32, r2: i32, imm: i32) -> Inst32 {
+    Inst32::new(((imm >> 5 & 0x7F) << 25 | r2 << 20 | r1 << 15 | funct3 << 12 | (imm & 0x1F) << 7 | ops::STORE_OP) as u32)
+}
+fn b_type(funct3: i32, r1: i32, r2: i32, imm: i32) -> Inst32 {
+    let hi = (imm >> 12 & 1) << 6 | (imm >> 5 & 0x3F);
+    let lo = (imm >> 1 & 0xF) << 1 | (imm >> 11 & 1);
+    Inst32::new((hi << 25 | r2 << 20 | r1 << 15 | funct3 << 12 | lo << 7 | ops::BRANCH_OP) as u32)
+}
+fn j_type(rd: i32, imm: i32) -> Inst32 {
+    let imm = (imm >> 20 & 1) << 19 | (imm >> 1 & 0x3FF) << 9 | (imm >> 11 & 1) << 8 | (imm >> 12 & 0xFF);
+    Inst32::new((imm << 12 | rd << 7 | ops::JUMP_OP) as u32)
+}
+
+// Picks bit `from` of the instruction and moves it to bit `to` of an immediate
+fn bits(inst: i32, map: &[(i32, i32)]) -> i32 {
+    map.iter().fold(0, |imm, &(from, to)| imm | (inst >> from & 1) << to)
+}
+// The 6 bit sign extended immediate of CI-format instructions
+fn imm_ci(inst: i32) -> i32 {
+    ((inst >> 2 & 0x1F) | (inst >> 12 & 1) << 5) << 26 >> 26
+}
+
+// The 32-bit equivalent of a 16-bit instruction, None if it's reserved or illegal
+pub fn expand(half: u16) -> Option<Inst32> {
+    let c = half as i32;
+    let rd = c >> 7 & 0x1F;
+    let r2 = c >> 2 & 0x1F;
+    // The 3 bit register fields only reach x8-x15
+    let rd_ = (c >> 2 & 0b111) + 8;
+    let r1_ = (c >> 7 & 0b111) + 8;
+    let funct3 = c >> 13 & 0b111;
+    Some(match (c & 0b11, funct3) {
+        // C.ADDI4SPN, all zeroes is defined to be illegal
+        (0b00, 0b000) => {
+            let imm = bits(c, &[(6, 2), (5, 3), (11, 4), (12, 5), (7, 6), (8, 7), (9, 8), (10, 9)]);
+            if imm == 0 { return None; }
+            i_type(ops::IMM_MATH_OP, imm_math::ADDI, rd_, SP, imm)
+        }
+        // C.LW and C.SW
+        (0b00, 0b010 | 0b110) => {
+            let imm = bits(c, &[(6, 2), (10, 3), (11, 4), (12, 5), (5, 6)]);
+            if funct3 == 0b010 {
+                i_type(ops::LOAD_OP, load::LW, rd_, r1_, imm)
+            } else {
+                s_type(store::SW, r1_, rd_, imm)
+            }
+        }
+        // C.NOP and C.ADDI
+        (0b01, 0b000) => i_type(ops::IMM_MATH_OP, imm_math::ADDI, rd, rd, imm_ci(c)),
+        // C.JAL and C.J
+        (0b01, 0b001 | 0b101) => {
+            let imm = bits(c, &[(3, 1), (4, 2), (5, 3), (11, 4), (2, 5), (7, 6), (6, 7), (9, 8), (10, 9), (8, 10), (12, 11)]) << 20 >> 20;
+            j_type(if funct3 == 0b001 { RA } else { X0 }, imm)
+        }
+        // C.LI
+        (0b01, 0b010) => i_type(ops::IMM_MATH_OP, imm_math::ADDI, rd, X0, imm_ci(c)),
+        // C.ADDI16SP
+        (0b01, 0b011) if rd == SP => {
+            let imm = bits(c, &[(6, 4), (2, 5), (5, 6), (3, 7), (4, 8), (12, 9)]) << 22 >> 22;
+            if imm == 0 { return None; }
+            i_type(ops::IMM_MATH_OP, imm_math::ADDI, SP, SP, imm)
+        }
+        // C.LUI
+        (0b01, 0b011) => {
+            let imm = imm_ci(c);
+            if imm == 0 { return None; }
+            Inst32::new(((imm << 12) | rd << 7 | ops::LUI_OP) as u32)
+        }
+        (0b01, 0b100) => {
+            let shamt = c >> 2 & 0x1F;
+            match c >> 10 & 0b11 {
+                // shamt[5] set is only valid on RV64
+                0b00 if c >> 12 & 1 == 0 => i_type(ops::IMM_MATH_OP, imm_math::SRLI, r1_, r1_, shamt),
+                0b01 if c >> 12 & 1 == 0 => i_type(ops::IMM_MATH_OP, imm_math::SRLI, r1_, r1_, imm_math::ARITH_SHIFT << 5 | shamt),
+                0b10 => i_type(ops::IMM_MATH_OP, imm_math::ANDI, r1_, r1_, imm_ci(c)),
+                0b11 if c >> 12 & 1 == 0 => {
+                    let op = match c >> 5 & 0b11 {
+                        0b00 => reg_math::SUB,
+                        0b01 => reg_math::XOR,
+                        0b10 => reg_math::OR,
+                        _ => reg_math::AND,
+                    };
+                    r_type(ops::REG_MATH_OP, op, r1_, r1_, rd_)
+                }
+                _ => return None,
+            }
+        }
+        // C.BEQZ and C.BNEZ
+        (0b01, 0b110 | 0b111) => {
+            let imm = bits(c, &[(3, 1), (4, 2), (10, 3), (11, 4), (2, 5), (5, 6), (6, 7), (12, 8)]) << 23 >> 23;
+            b_type(if funct3 == 0b110 { branch::BEQ } else { branch::BNE }, r1_, X0, imm)
+        }
+        // C.SLLI
+        (0b10, 0b000) if c >> 12 & 1 == 0 => i_type(ops::IMM_MATH_OP, imm_math::SLLI, rd, rd, r2),
+        // C.LWSP
+        (0b10, 0b010) if rd != 0 => {
+            let imm = bits(c, &[(4, 2), (5, 3), (6, 4), (12, 5), (2, 6), (3, 7)]);
+            i_type(ops::LOAD_OP, load::LW, rd, SP, imm)
+        }
+        (0b10, 0b100) => match (c >> 12 & 1, rd, r2) {
+            // C.JR
+            (0, 0, _) => return None,
+            (0, _, 0) => i_type(ops::JUMP_REG_OP, jump_reg::JALR, X0, rd, 0),
+            // C.MV
+            (0, _, _) => r_type(ops::REG_MATH_OP, reg_math::ADD, rd, X0, r2),
+            (_, 0, 0) => i_type(ops::SYSTEM_OP, system::PRIV, 0, 0, system::EBREAK),
+            // C.JALR
+            (_, _, 0) => i_type(ops::JUMP_REG_OP, jump_reg::JALR, RA, rd, 0),
+            // C.ADD
+            _ => r_type(ops::REG_MATH_OP, reg_math::ADD, rd, rd, r2),
+        },
+        // C.SWSP
+        (0b10, 0b110) => {
+            let imm = bits(c, &[(9, 2), (10, 3), (11, 4), (12, 5), (7, 6), (8, 7)]);
+            s_type(store::SW, SP, r2, imm)
+        }
+        _ => return None,
+    })
+}
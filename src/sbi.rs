@@ -0,0 +1,210 @@
+use crate::{clint, csr::{irq, mstatus, Mode}, mmu, region::Kind, uart, vm::{Halt, VM}};
+
+// NOTE: RISC-V SBI specification v2.0
+pub const SPEC_VERSION: u32 = 2 << 24;
+// Not a registered implementation ID
+const IMPL_ID: u32 = 0x5256;
+const IMPL_VERSION: u32 = 1;
+
+mod eid {
+    pub const LEGACY_SET_TIMER : u32 = 0x00;
+    pub const LEGACY_PUTCHAR   : u32 = 0x01;
+    pub const LEGACY_GETCHAR   : u32 = 0x02;
+    pub const LEGACY_CLEAR_IPI : u32 = 0x03;
+    pub const LEGACY_SEND_IPI  : u32 = 0x04;
+    pub const LEGACY_FENCE_I   : u32 = 0x05;
+    pub const LEGACY_SFENCE_VMA: u32 = 0x06;
+    pub const LEGACY_SFENCE_VMA_ASID: u32 = 0x07;
+    pub const LEGACY_SHUTDOWN  : u32 = 0x08;
+    pub const BASE : u32 = 0x10;
+    pub const TIME : u32 = 0x54494D45;
+    pub const IPI  : u32 = 0x735049;
+    pub const RFENCE: u32 = 0x52464E43;
+    pub const HSM  : u32 = 0x48534D;
+    pub const SRST : u32 = 0x53525354;
+}
+const EXTENSIONS: [u32; 15] = [
+    eid::LEGACY_SET_TIMER, eid::LEGACY_PUTCHAR, eid::LEGACY_GETCHAR, eid::LEGACY_CLEAR_IPI, eid::LEGACY_SEND_IPI,
+    eid::LEGACY_FENCE_I, eid::LEGACY_SFENCE_VMA, eid::LEGACY_SFENCE_VMA_ASID, eid::LEGACY_SHUTDOWN,
+    eid::BASE, eid::TIME, eid::IPI, eid::RFENCE, eid::HSM, eid::SRST,
+];
+
+const SUCCESS: i32 = 0;
+const ERR_NOT_SUPPORTED: i32 = -2;
+const ERR_INVALID_PARAM: i32 = -3;
+const ERR_INVALID_ADDRESS: i32 = -5;
+const ERR_ALREADY_AVAILABLE: i32 = -6;
+
+const HSM_STARTED: u32 = 0;
+const HSM_SUSPEND_NON_RETENTIVE: u32 = 0x80000000;
+const SRST_REASON_FAILURE: u32 = 1;
+
+// Machine state the firmware would leave behind before jumping to the kernel in S-mode
+pub fn boot(vm: &mut VM) {
+    vm.sbi = true;
+    vm.mode = Mode::Supervisor;
+    vm.pmp.allow_all();
+    // Misaligned fetches, breakpoints, U-mode ecalls and page faults
+    vm.csr.medeleg = 0xB109;
+    vm.csr.mideleg = irq::S_MASK;
+    vm.csr.mcounteren = 0b111;
+}
+
+// Whether hart 0, the only one, is in the mask
+fn selects_hart(mask: u32, base: u32) -> bool {
+    base == u32::MAX || (base == 0 && mask & 1 != 0)
+}
+fn flush(vm: &mut VM, start: u32, size: u32) {
+    // Anything bigger than a handful of pages is cheaper to flush wholesale
+    if size == 0 || size > 16 * mmu::PAGE_SIZE {
+        vm.tlb.flush(None);
+    } else {
+        for page in (start..start.saturating_add(size)).step_by(mmu::PAGE_SIZE as usize) {
+            vm.tlb.flush(Some(page));
+        }
+    }
+}
+
+// Handles an ECALL made from S-mode on the host.
+// Returns where the hart continues when it isn't the next instruction.
+// The timer and console go through the machine's CLINT and UART like firmware's drivers would
+fn set_timer(vm: &mut VM, mtimecmp: u64) {
+    if let Some(addr) = vm.regions.find_kind(Kind::Clint).map(|x| x.addr) {
+        vm.write(addr + clint::MTIMECMP, &mtimecmp.to_le_bytes()).ok();
+    }
+}
+fn getchar(vm: &mut VM) -> Option<u8> {
+    let addr = vm.regions.find_kind(Kind::Uart)?.addr;
+    let mut byte = [0];
+    vm.read(addr + uart::LSR, &mut byte).ok()?;
+    if byte[0] & uart::bits::LSR_DR == 0 { return None; }
+    vm.read(addr + uart::RBR, &mut byte).ok()?;
+    Some(byte[0])
+}
+pub fn call(vm: &mut VM) -> Option<u32> {
+    let reg = |vm: &VM, i: usize| vm.regs[i] as u32;
+    let (eid, fid) = (reg(vm, 17), reg(vm, 16));
+    let (a0, a1, a2) = (reg(vm, 10), reg(vm, 11), reg(vm, 12));
+    // Legacy calls only return a single value in a0
+    let legacy = |vm: &mut VM, v: i32| {
+        vm.set_reg(10, v);
+        None
+    };
+    let (error, value) = match eid {
+        eid::LEGACY_SET_TIMER => {
+            set_timer(vm, (a1 as u64) << 32 | a0 as u64);
+            return legacy(vm, SUCCESS);
+        }
+        eid::LEGACY_PUTCHAR => {
+            uart::putchar(a0 as u8);
+            return legacy(vm, SUCCESS);
+        }
+        eid::LEGACY_GETCHAR => {
+            let c = getchar(vm).map_or(-1, |x| x as i32);
+            return legacy(vm, c);
+        }
+        eid::LEGACY_CLEAR_IPI => {
+            vm.csr.mip &= !irq::SSI;
+            return legacy(vm, SUCCESS);
+        }
+        // These take a pointer to the hart mask instead of the mask itself
+        eid::LEGACY_SEND_IPI | eid::LEGACY_FENCE_I | eid::LEGACY_SFENCE_VMA | eid::LEGACY_SFENCE_VMA_ASID => {
+            let mut mask = [0; 4];
+            let selected = a0 == 0 || (vm.load(a0, &mut mask).is_ok() && u32::from_le_bytes(mask) & 1 != 0);
+            if selected {
+                match eid {
+                    eid::LEGACY_SEND_IPI => vm.csr.mip |= irq::SSI,
+                    eid::LEGACY_SFENCE_VMA | eid::LEGACY_SFENCE_VMA_ASID => flush(vm, a1, a2),
+                    _ => {}
+                }
+            }
+            return legacy(vm, SUCCESS);
+        }
+        eid::LEGACY_SHUTDOWN => {
+            vm.halt = Some(Halt::Exit(0));
+            return None;
+        }
+        eid::BASE => match fid {
+            0 => (SUCCESS, SPEC_VERSION),
+            1 => (SUCCESS, IMPL_ID),
+            2 => (SUCCESS, IMPL_VERSION),
+            3 => (SUCCESS, EXTENSIONS.contains(&a0) as u32),
+            // mvendorid, marchid and mimpid are all zero
+            4..=6 => (SUCCESS, 0),
+            _ => (ERR_NOT_SUPPORTED, 0),
+        },
+        eid::TIME => match fid {
+            0 => {
+                set_timer(vm, (a1 as u64) << 32 | a0 as u64);
+                (SUCCESS, 0)
+            }
+            _ => (ERR_NOT_SUPPORTED, 0),
+        },
+        eid::IPI => match fid {
+            0 => {
+                if selects_hart(a0, a1) {
+                    vm.csr.mip |= irq::SSI;
+                }
+                (SUCCESS, 0)
+            }
+            _ => (ERR_NOT_SUPPORTED, 0),
+        },
+        eid::RFENCE => match fid {
+            // There's no instruction cache to flush
+            0 => (SUCCESS, 0),
+            // ASIDs aren't implemented, so both flush by address alone
+            1 | 2 => {
+                if selects_hart(a0, a1) {
+                    flush(vm, a2, reg(vm, 13));
+                }
+                (SUCCESS, 0)
+            }
+            // The hypervisor fences
+            _ => (ERR_NOT_SUPPORTED, 0),
+        },
+        eid::HSM => match fid {
+            0 if a0 == 0 => (ERR_ALREADY_AVAILABLE, 0),
+            // Stopping the only hart leaves nothing running
+            1 => {
+                vm.halt = Some(Halt::Idle);
+                return None;
+            }
+            2 if a0 == 0 => (SUCCESS, HSM_STARTED),
+            0 | 2 => (ERR_INVALID_PARAM, 0),
+            3 => match a0 {
+                0 => {
+                    vm.idle(vm.csr.mie);
+                    (SUCCESS, 0)
+                }
+                HSM_SUSPEND_NON_RETENTIVE => {
+                    if a1 & 0b11 != 0 {
+                        (ERR_INVALID_ADDRESS, 0)
+                    } else {
+                        vm.idle(vm.csr.mie);
+                        // Resumes like a fresh start, with translation and interrupts off
+                        vm.csr.satp = 0;
+                        vm.csr.mstatus &= !mstatus::SIE;
+                        vm.set_reg(10, 0);
+                        vm.set_reg(11, a2 as i32);
+                        return Some(a1);
+                    }
+                }
+                _ => (ERR_INVALID_PARAM, 0),
+            },
+            _ => (ERR_NOT_SUPPORTED, 0),
+        },
+        eid::SRST => match fid {
+            0 if a0 <= 2 => {
+                // Shutdown and both reboots stop the machine, there's no way to reset it
+                vm.halt = Some(Halt::Exit((a1 == SRST_REASON_FAILURE) as u8));
+                return None;
+            }
+            0 => (ERR_INVALID_PARAM, 0),
+            _ => (ERR_NOT_SUPPORTED, 0),
+        },
+        _ => (ERR_NOT_SUPPORTED, 0),
+    };
+    vm.set_reg(10, error);
+    vm.set_reg(11, value as i32);
+    None
+}
@@ -0,0 +1,233 @@
+use std::{fs::{self, File, OpenOptions}, io::{self, IsTerminal, Read, Seek, SeekFrom, Write}, time::{Instant, SystemTime, UNIX_EPOCH}};
+use crate::{mmu, vm::{Halt, VM}};
+
+// NOTE: RISC-V semihosting, which is the Arm semihosting interface behind a magic sequence:
+//   slli x0, x0, 0x1f
+//   ebreak
+//   srai x0, x0, 7
+// Operation in a0, a pointer to the parameter block (or the parameter itself) in a1, result in a0.
+const SLLI_X0: u32 = 0x01F01013;
+const SRAI_X0: u32 = 0x40705013;
+mod op {
+    pub const OPEN       : u32 = 0x01;
+    pub const CLOSE      : u32 = 0x02;
+    pub const WRITEC     : u32 = 0x03;
+    pub const WRITE0     : u32 = 0x04;
+    pub const WRITE      : u32 = 0x05;
+    pub const READ       : u32 = 0x06;
+    pub const READC      : u32 = 0x07;
+    pub const ISTTY      : u32 = 0x09;
+    pub const SEEK       : u32 = 0x0A;
+    pub const FLEN       : u32 = 0x0C;
+    pub const REMOVE     : u32 = 0x0E;
+    pub const CLOCK      : u32 = 0x10;
+    pub const TIME       : u32 = 0x11;
+    pub const ERRNO      : u32 = 0x13;
+    pub const GET_CMDLINE: u32 = 0x15;
+    pub const EXIT       : u32 = 0x18;
+    pub const EXIT_EXTENDED: u32 = 0x20;
+}
+// ADP_Stopped_ApplicationExit, any other reason is a failure
+const APPLICATION_EXIT: u32 = 0x20026;
+const EBADF: i32 = 9;
+const EINVAL: i32 = 22;
+
+enum Handle {
+    Stdin,
+    Stdout,
+    Stderr,
+    File(File),
+}
+
+pub struct Semihost {
+    handles: Vec<Option<Handle>>,
+    errno: i32,
+    cmdline: String,
+    start: Instant,
+}
+impl Semihost {
+    pub fn new(cmdline: String) -> Self {
+        Self { handles: Vec::new(), errno: 0, cmdline, start: Instant::now() }
+    }
+    fn handle(&mut self, h: u32) -> Result<&mut Handle, i32> {
+        self.handles.get_mut(h as usize).and_then(Option::as_mut).ok_or(EBADF)
+    }
+}
+
+// Whether the EBREAK at ip is wrapped in the sequence that makes it a semihosting call
+pub fn is_call(vm: &mut VM) -> bool {
+    let ip = vm.ip as u32;
+    // Both halves have to be on the same page as the EBREAK
+    if ip & (mmu::PAGE_SIZE - 1) == 0 || ip.wrapping_add(4) & (mmu::PAGE_SIZE - 1) == 0 { return false; }
+    vm.peek_inst(ip.wrapping_sub(4)) == Some(SLLI_X0) && vm.peek_inst(ip.wrapping_add(4)) == Some(SRAI_X0)
+}
+
+fn load(vm: &mut VM, addr: u32, len: usize) -> Result<Vec<u8>, i32> {
+    if len > vm.regions.ram_size() { return Err(EINVAL); }
+    let mut bytes = vec![0; len];
+    vm.copy_from_guest(addr, &mut bytes).map_err(|_| EINVAL)?;
+    Ok(bytes)
+}
+fn store(vm: &mut VM, addr: u32, bytes: &[u8]) -> Result<(), i32> {
+    vm.copy_to_guest(addr, bytes).map_err(|_| EINVAL)
+}
+fn args<const N: usize>(vm: &mut VM, block: u32) -> Result<[u32; N], i32> {
+    let bytes = load(vm, block, N * 4)?;
+    Ok(std::array::from_fn(|i| u32::from_le_bytes(bytes[i*4..i*4+4].try_into().unwrap())))
+}
+fn host(e: io::Error) -> i32 {
+    e.raw_os_error().unwrap_or(EINVAL)
+}
+
+// Handles the semihosting call at ip, the guest carries on with the SRAI after it
+pub fn call(vm: &mut VM) {
+    let Some(mut sh) = vm.semihost.take() else { return; };
+    let (op, param) = (vm.regs[10] as u32, vm.regs[11] as u32);
+    let ret = match dispatch(vm, &mut sh, op, param) {
+        Ok(v) => v,
+        Err(e) => {
+            sh.errno = e;
+            -1
+        }
+    };
+    vm.semihost = Some(sh);
+    if vm.halt.is_none() {
+        vm.set_reg(10, ret);
+    }
+}
+fn dispatch(vm: &mut VM, sh: &mut Semihost, op: u32, param: u32) -> Result<i32, i32> {
+    Ok(match op {
+        op::OPEN => {
+            let [name, mode, len] = args(vm, param)?;
+            let name = load(vm, name, len as usize)?;
+            if mode > 11 { return Err(EINVAL); }
+            // The console is the special file ":tt", its mode picks the stream
+            let handle = if name == b":tt" {
+                match mode {
+                    0..=3 => Handle::Stdin,
+                    4..=7 => Handle::Stdout,
+                    _ => Handle::Stderr,
+                }
+            } else {
+                // fopen() modes r, r+, w, w+, a, a+, each with a binary twin
+                let path = String::from_utf8_lossy(&name).into_owned();
+                let plus = mode & 0b10 != 0;
+                let file = match mode >> 2 {
+                    0 => OpenOptions::new().read(true).write(plus).open(&path),
+                    1 => OpenOptions::new().read(plus).write(true).create(true).truncate(true).open(&path),
+                    _ => OpenOptions::new().read(plus).append(true).create(true).open(&path),
+                };
+                Handle::File(file.map_err(host)?)
+            };
+            let h = sh.handles.iter().position(Option::is_none).unwrap_or(sh.handles.len());
+            if h == sh.handles.len() { sh.handles.push(None); }
+            sh.handles[h] = Some(handle);
+            h as i32
+        }
+        op::CLOSE => {
+            let [h] = args(vm, param)?;
+            sh.handle(h)?;
+            sh.handles[h as usize] = None;
+            0
+        }
+        op::WRITEC => {
+            let c = load(vm, param, 1)?;
+            io::stdout().write_all(&c).and_then(|_| io::stdout().flush()).map_err(host)?;
+            0
+        }
+        op::WRITE0 => {
+            let mut s = Vec::new();
+            loop {
+                let c = load(vm, param.wrapping_add(s.len() as u32), 1)?[0];
+                if c == 0 { break; }
+                s.push(c);
+            }
+            io::stdout().write_all(&s).and_then(|_| io::stdout().flush()).map_err(host)?;
+            0
+        }
+        // Both return how many bytes were left over
+        op::WRITE => {
+            let [h, buf, len] = args(vm, param)?;
+            let bytes = load(vm, buf, len as usize)?;
+            let res = match sh.handle(h)? {
+                Handle::Stdout => io::stdout().write_all(&bytes).and_then(|_| io::stdout().flush()),
+                Handle::Stderr => io::stderr().write_all(&bytes),
+                Handle::File(file) => file.write_all(&bytes),
+                Handle::Stdin => return Err(EBADF),
+            };
+            res.map_err(host)?;
+            0
+        }
+        op::READ => {
+            let [h, buf, len] = args(vm, param)?;
+            if len as usize > vm.regions.ram_size() { return Err(EINVAL); }
+            let mut bytes = vec![0; len as usize];
+            let n = match sh.handle(h)? {
+                Handle::Stdin => io::stdin().read(&mut bytes),
+                Handle::File(file) => file.read(&mut bytes),
+                _ => return Err(EBADF),
+            }.map_err(host)?;
+            store(vm, buf, &bytes[..n])?;
+            (len as usize - n) as i32
+        }
+        op::READC => {
+            let mut c = [0];
+            io::stdin().read_exact(&mut c).map_err(host)?;
+            c[0] as i32
+        }
+        op::ISTTY => {
+            let [h] = args(vm, param)?;
+            let tty = match sh.handle(h)? {
+                Handle::Stdin => io::stdin().is_terminal(),
+                Handle::Stdout => io::stdout().is_terminal(),
+                Handle::Stderr => io::stderr().is_terminal(),
+                Handle::File(_) => false,
+            };
+            tty as i32
+        }
+        op::SEEK => {
+            let [h, pos] = args(vm, param)?;
+            match sh.handle(h)? {
+                Handle::File(file) => file.seek(SeekFrom::Start(pos as u64)).map_err(host)?,
+                _ => return Err(EINVAL),
+            };
+            0
+        }
+        op::FLEN => {
+            let [h] = args(vm, param)?;
+            match sh.handle(h)? {
+                Handle::File(file) => i32::try_from(file.metadata().map_err(host)?.len()).map_err(|_| EINVAL)?,
+                _ => return Err(EINVAL),
+            }
+        }
+        op::REMOVE => {
+            let [name, len] = args(vm, param)?;
+            let name = load(vm, name, len as usize)?;
+            fs::remove_file(String::from_utf8_lossy(&name).as_ref()).map_err(host)?;
+            0
+        }
+        // Centiseconds since the program started
+        op::CLOCK => (sh.start.elapsed().as_millis() / 10) as i32,
+        op::TIME => SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i32,
+        op::ERRNO => sh.errno,
+        op::GET_CMDLINE => {
+            let [buf, len] = args(vm, param)?;
+            let cmdline = [sh.cmdline.as_bytes(), &[0]].concat();
+            if cmdline.len() > len as usize { return Err(EINVAL); }
+            store(vm, buf, &cmdline)?;
+            store(vm, param.wrapping_add(4), &(cmdline.len() as u32 - 1).to_le_bytes())?;
+            0
+        }
+        // On 32-bit targets the reason is passed directly, without a block
+        op::EXIT => {
+            vm.halt = Some(Halt::Exit((param != APPLICATION_EXIT) as u8));
+            0
+        }
+        op::EXIT_EXTENDED => {
+            let [reason, code] = args(vm, param)?;
+            vm.halt = Some(Halt::Exit(if reason == APPLICATION_EXIT { code as u8 } else { 1 }));
+            0
+        }
+        _ => return Err(EINVAL),
+    })
+}
@@ -1,5 +1,16 @@
 use crate::region::RegionList;
 pub struct Setup {
     pub sp: usize,
-    pub layout: RegionList
+    pub layout: RegionList,
+    // Physical address just past the end of RAM
+    pub ram_end: usize,
+}
+// Pads the image out to `size` bytes of RAM.
+// A fresh vec![0; n] comes zeroed from the allocator, resize() would write every byte,
+// which takes most of a second for a few hundred MiB in debug builds.
+pub fn grow(ram: &mut Vec<u8>, size: usize) {
+    if ram.len() >= size { return; }
+    let mut big = vec![0; size];
+    big[..ram.len()].copy_from_slice(ram);
+    *ram = big;
 }
@@ -1,33 +1,43 @@
-use crate::{region::{ExitMeta, MemoryMeta, Region, RegionList, SerialMeta}, Setup};
+use crate::{region::{Exit, Memory, Region, RegionList, Serial}, setup::{self, Setup}};
 
 const SERIAL_OUT: usize = 0x6969;
 const EXIT: usize = 0x7000;
-const RAM_SIZE: usize = 4096 * 4096;
+pub const RAM_SIZE: usize = 4096 * 4096;
 const STACK_BASE: usize = RAM_SIZE - 0x1000;
-pub fn setup(ram: &mut Vec<u8>) -> Setup {
-    ram.resize(RAM_SIZE.max(ram.len()), 0);
+const MMIO_LATENCY: u32 = 4;
+pub fn setup(mut ram: Vec<u8>) -> Setup {
+    setup::grow(&mut ram, RAM_SIZE);
+    let end = ram.len();
+    // Serial and Exit sit in a hole, RAM carries on after it
+    let high = ram.split_off(EXIT+1);
+    ram.truncate(SERIAL_OUT);
+    ram.shrink_to_fit();
     let layout = RegionList(
         vec![
             Region {
-                meta: MemoryMeta::new(),
+                device: Box::new(Memory::new(ram)),
                 addr: 0,
-                size: SERIAL_OUT
+                size: SERIAL_OUT,
+                latency: 0,
             },
             Region {
-                meta: SerialMeta::new(),
+                device: Box::new(Serial),
                 addr: SERIAL_OUT,
-                size: 1
+                size: 1,
+                latency: MMIO_LATENCY,
             },
             Region {
-                meta: ExitMeta::new(),
+                device: Box::new(Exit),
                 addr: EXIT,
-                size: 1
+                size: 1,
+                latency: MMIO_LATENCY,
             },
             Region {
-                meta: MemoryMeta::new(),
+                size: high.len(),
+                device: Box::new(Memory::new(high)),
                 addr: EXIT+1,
-                size: ram.len()-EXIT+1,
+                latency: 0,
             }
         ].into_boxed_slice());
-    Setup { sp: STACK_BASE, layout }
+    Setup { sp: STACK_BASE, layout, ram_end: end }
 }
@@ -0,0 +1,105 @@
+use crate::{cache::Access, csr::{mstatus, Mode}, mmu::{self, pte}, region::{Memory, Region, RegionList}, trap, vm::VM};
+
+// NOTE: Sv32 walks over a page table built by hand in RAM.
+// The root table is at ROOT and maps the first 4 MiB through the second level table at LEAVES,
+// the next 4 MiB with an aligned megapage and the 4 MiB after that with a misaligned one.
+const RAM_SIZE: usize = 0xC0_0000;
+const ROOT: usize = 0x1000;
+const LEAVES: usize = 0x2000;
+const RWAD: u32 = pte::R | pte::W | pte::A | pte::D;
+
+fn leaf(vm: &mut VM, vaddr: u32, paddr: usize, flags: u32) {
+    let entry = ((paddr >> mmu::PAGE_SHIFT) as u32) << 10 | flags | pte::V;
+    vm.write(LEAVES + (vaddr as usize >> mmu::PAGE_SHIFT & 0x3FF) * 4, &entry.to_le_bytes()).unwrap();
+}
+fn setup() -> VM {
+    let regions = RegionList(vec![Region { device: Box::new(Memory::new(vec![0; RAM_SIZE])), addr: 0, size: RAM_SIZE, latency: 0 }].into_boxed_slice());
+    let mut vm = VM::new(regions);
+    vm.pmp.allow_all();
+    let root = [
+        ((LEAVES >> mmu::PAGE_SHIFT) as u32) << 10 | pte::V,
+        0x400 << 10 | RWAD | pte::V,
+        0x401 << 10 | RWAD | pte::V,
+    ];
+    for (i, entry) in root.iter().enumerate() {
+        vm.write(ROOT + i*4, &entry.to_le_bytes()).unwrap();
+    }
+    leaf(&mut vm, 0x5000, 0x9000, RWAD | pte::X);
+    leaf(&mut vm, 0x6000, 0x7000, RWAD);
+    leaf(&mut vm, 0x7000, 0xA000, RWAD | pte::X | pte::U);
+    leaf(&mut vm, 0x8000, 0xB000, pte::X | pte::A);
+    leaf(&mut vm, 0x9000, 0xC000, pte::R | pte::W);
+    leaf(&mut vm, 0xA000, 0xD000, pte::R | pte::W | pte::A);
+    vm.csr.satp = mmu::SATP_MODE | (ROOT >> mmu::PAGE_SHIFT) as u32;
+    vm.mode = Mode::Supervisor;
+    vm
+}
+fn fault(access: Access, vaddr: u32) -> Result<usize, trap::Trap> {
+    Err(mmu::page_fault(access, vaddr))
+}
+
+#[test]
+fn translation() {
+    let mut vm = setup();
+    assert_eq!(vm.translate(0x5123, Access::Read), Ok(0x9123));
+    assert_eq!(vm.translate(0x6FFF, Access::Write), Ok(0x7FFF));
+    // The low VPN of a megapage comes from the virtual address
+    assert_eq!(vm.translate(0x41_2345, Access::Read), Ok(0x41_2345));
+    assert_eq!(vm.translate(0x80_0000, Access::Read), fault(Access::Read, 0x80_0000));
+    assert_eq!(vm.translate(0x4000, Access::Read), fault(Access::Read, 0x4000));
+    assert_eq!(vm.translate(0xC0_0000, Access::Fetch), fault(Access::Fetch, 0xC0_0000));
+    // M-mode ignores satp
+    vm.mode = Mode::Machine;
+    assert_eq!(vm.translate(0x80_0000, Access::Read), Ok(0x80_0000));
+}
+
+#[test]
+fn accessed_and_dirty() {
+    let mut vm = setup();
+    assert_eq!(vm.translate(0x9000, Access::Read), fault(Access::Read, 0x9000));
+    assert_eq!(vm.translate(0xA000, Access::Read), Ok(0xD000));
+    assert_eq!(vm.translate(0xA000, Access::Write), fault(Access::Write, 0xA000));
+}
+
+#[test]
+fn permissions() {
+    let mut vm = setup();
+    // S-mode only touches U pages with SUM, and never executes them
+    assert_eq!(vm.translate(0x7000, Access::Read), fault(Access::Read, 0x7000));
+    vm.csr.mstatus |= mstatus::SUM;
+    assert_eq!(vm.translate(0x7000, Access::Read), Ok(0xA000));
+    assert_eq!(vm.translate(0x7000, Access::Write), Ok(0xA000));
+    assert_eq!(vm.translate(0x7000, Access::Fetch), fault(Access::Fetch, 0x7000));
+    // Execute-only pages are readable with MXR
+    assert_eq!(vm.translate(0x8000, Access::Fetch), Ok(0xB000));
+    assert_eq!(vm.translate(0x8000, Access::Read), fault(Access::Read, 0x8000));
+    vm.csr.mstatus |= mstatus::MXR;
+    assert_eq!(vm.translate(0x8000, Access::Read), Ok(0xB000));
+    assert_eq!(vm.translate(0x8000, Access::Write), fault(Access::Write, 0x8000));
+    // U-mode only touches U pages, SUM or not
+    vm.mode = Mode::User;
+    assert_eq!(vm.translate(0x7000, Access::Fetch), Ok(0xA000));
+    assert_eq!(vm.translate(0x5000, Access::Read), fault(Access::Read, 0x5000));
+    assert_eq!(vm.translate(0x41_0000, Access::Read), fault(Access::Read, 0x41_0000));
+    // MPRV makes M-mode loads and stores go through the table as MPP, but not fetches
+    vm.mode = Mode::Machine;
+    vm.csr.mstatus |= mstatus::MPRV;
+    assert_eq!(vm.translate(0x5000, Access::Read), fault(Access::Read, 0x5000));
+    assert_eq!(vm.translate(0x5000, Access::Fetch), Ok(0x5000));
+}
+
+#[test]
+fn page_crossing() {
+    let mut vm = setup();
+    vm.write(0x9FFE, &[1, 2]).unwrap();
+    vm.write(0x7000, &[3, 4]).unwrap();
+    let mut bytes = [0; 4];
+    assert_eq!(vm.load(0x5FFE, &mut bytes), Ok(()));
+    assert_eq!(bytes, [1, 2, 3, 4]);
+    vm.copy_to_guest(0x5FFF, &[5, 6]).unwrap();
+    assert_eq!(vm.read_u16(0x9FFF).unwrap(), 5);
+    assert_eq!(vm.read_u16(0x7000).unwrap() & 0xFF, 6);
+    // The fault is reported at the first byte of the page that isn't mapped
+    leaf(&mut vm, 0xB000, 0xE000, RWAD);
+    assert_eq!(vm.load(0xBFFE, &mut bytes), Err(mmu::page_fault(Access::Read, 0xC000)));
+}
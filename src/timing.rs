@@ -0,0 +1,176 @@
+use std::io::{self, Write};
+
+use crate::{inst::Inst32, ops::{self, system}};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    Alu,
+    Mul,
+    Div,
+    Load,
+    Store,
+    Branch,
+    Jump,
+    System,
+}
+const CLASS_COUNT: usize = 8;
+const CLASS_NAMES: [&str; CLASS_COUNT] = ["alu", "mul", "div", "load", "store", "branch", "jump", "system"];
+impl Class {
+    pub const fn of(inst: Inst32) -> Self {
+        match inst.opcode() {
+            ops::REG_MATH_OP if inst.funct7() == 1 => if inst.funct3() < 4 { Class::Mul } else { Class::Div },
+            ops::LOAD_OP => Class::Load,
+            ops::STORE_OP => Class::Store,
+            ops::BRANCH_OP => Class::Branch,
+            ops::JUMP_OP | ops::JUMP_REG_OP => Class::Jump,
+            ops::SYSTEM_OP => Class::System,
+            _ => Class::Alu,
+        }
+    }
+}
+// Registers an instruction reads, so a load feeding it can be detected
+fn sources(inst: Inst32) -> [i32; 2] {
+    match inst.opcode() {
+        ops::REG_MATH_OP | ops::BRANCH_OP | ops::STORE_OP => [inst.r1(), inst.r2()],
+        ops::IMM_MATH_OP | ops::LOAD_OP | ops::JUMP_REG_OP => [inst.r1(), 0],
+        ops::SYSTEM_OP if matches!(inst.funct3(), system::CSRRW | system::CSRRS | system::CSRRC) => [inst.r1(), 0],
+        _ => [0, 0],
+    }
+}
+
+pub struct TimingConfig {
+    // Base cost of each instruction class, in cycles
+    pub latency: [u32; CLASS_COUNT],
+    // Bubble when an instruction consumes the result of the load right before it
+    pub load_use: u32,
+    pub mispredict: u32,
+    // Per region access latencies, by region start address
+    pub regions: Vec<(usize, u32)>,
+}
+impl Default for TimingConfig {
+    // Roughly a classic 5 stage in-order pipeline with an iterative divider
+    fn default() -> Self {
+        Self {
+            latency: [1, 3, 34, 1, 1, 1, 1, 1],
+            load_use: 1,
+            mispredict: 2,
+            regions: Vec::new(),
+        }
+    }
+}
+impl TimingConfig {
+    // One `key = value` per line, `#` starts a comment:
+    //  alu mul div load store branch jump system - class latencies
+    //  load_use mispredict                      - penalties
+    //  region.<addr>                            - extra cycles per access to the region at addr
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let mut cfg = Self::default();
+        for (i, line) in src.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() { continue; }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(format!("line {}: Expected `key = value`", i+1));
+            };
+            let (key, value) = (key.trim(), value.trim());
+            let value: u32 = value.parse().map_err(|e| format!("line {}: Invalid cycle count `{}`: {}", i+1, value, e))?;
+            if let Some(class) = CLASS_NAMES.iter().position(|x| *x == key) {
+                cfg.latency[class] = value;
+            } else if let Some(addr) = key.strip_prefix("region.") {
+                let addr = match addr.strip_prefix("0x") {
+                    Some(hex) => usize::from_str_radix(hex, 16),
+                    None => addr.parse(),
+                }.map_err(|e| format!("line {}: Invalid region address `{}`: {}", i+1, addr, e))?;
+                cfg.regions.push((addr, value));
+            } else {
+                match key {
+                    "load_use" => cfg.load_use = value,
+                    "mispredict" => cfg.mispredict = value,
+                    _ => return Err(format!("line {}: Unknown key `{}`", i+1, key)),
+                }
+            }
+        }
+        Ok(cfg)
+    }
+}
+
+pub struct Timing {
+    pub cfg: TimingConfig,
+    pub cycles: u64,
+    retired: [u64; CLASS_COUNT],
+    class_cycles: [u64; CLASS_COUNT],
+    load_use_stalls: u64,
+    mispredicts: u64,
+    mem_stalls: u64,
+    // Memory latency picked up by the instruction in flight
+    pending: u64,
+    last_load: Option<i32>,
+}
+impl Timing {
+    pub fn new(cfg: TimingConfig) -> Self {
+        Self {
+            cfg,
+            cycles: 0,
+            retired: [0; CLASS_COUNT],
+            class_cycles: [0; CLASS_COUNT],
+            load_use_stalls: 0,
+            mispredicts: 0,
+            mem_stalls: 0,
+            pending: 0,
+            last_load: None,
+        }
+    }
+    pub fn access(&mut self, latency: u32) {
+        self.pending += latency as u64;
+    }
+    // Static backward-taken/forward-not-taken prediction for branches.
+    // JALR targets are never known ahead of time, so they always pay the penalty.
+    fn mispredicted(pc: u32, inst: Inst32, next: u32) -> bool {
+        match inst.opcode() {
+            ops::BRANCH_OP => (inst.imm_B() < 0) != (next == pc.wrapping_add(inst.imm_B() as u32)),
+            ops::JUMP_REG_OP => true,
+            _ => false,
+        }
+    }
+    // Returns the cycles taken by the instruction.
+    // `mispredicted` comes from the branch predictor simulation when one is attached.
+    pub fn retire(&mut self, pc: u32, inst: Inst32, next: u32, mispredicted: Option<bool>) -> u64 {
+        let class = Class::of(inst);
+        let mut cost = self.cfg.latency[class as usize] as u64;
+        if let Some(rd) = self.last_load.take() {
+            if sources(inst).contains(&rd) {
+                self.load_use_stalls += 1;
+                cost += self.cfg.load_use as u64;
+            }
+        }
+        if class == Class::Load && inst.rd() != 0 {
+            self.last_load = Some(inst.rd());
+        }
+        if mispredicted.unwrap_or_else(|| Self::mispredicted(pc, inst, next)) {
+            self.mispredicts += 1;
+            cost += self.cfg.mispredict as u64;
+        }
+        self.mem_stalls += self.pending;
+        cost += self.pending;
+        self.pending = 0;
+        self.retired[class as usize] += 1;
+        self.class_cycles[class as usize] += cost;
+        self.cycles += cost;
+        cost
+    }
+    pub fn report(&self, out: &mut impl Write) -> io::Result<()> {
+        let instret: u64 = self.retired.iter().sum();
+        writeln!(out, "Timing report:")?;
+        writeln!(out, "  cycles             {}", self.cycles)?;
+        writeln!(out, "  instructions       {}", instret)?;
+        writeln!(out, "  CPI                {:.3}", self.cycles as f64 / instret.max(1) as f64)?;
+        writeln!(out, "  load-use stalls    {} ({} cycles)", self.load_use_stalls, self.load_use_stalls * self.cfg.load_use as u64)?;
+        writeln!(out, "  mispredicts        {} ({} cycles)", self.mispredicts, self.mispredicts * self.cfg.mispredict as u64)?;
+        writeln!(out, "  memory stalls      {} cycles", self.mem_stalls)?;
+        writeln!(out, "  {:<8} {:>12} {:>12}", "class", "retired", "cycles")?;
+        for (i, name) in CLASS_NAMES.iter().enumerate() {
+            if self.retired[i] == 0 { continue; }
+            writeln!(out, "  {:<8} {:>12} {:>12}", name, self.retired[i], self.class_cycles[i])?;
+        }
+        Ok(())
+    }
+}
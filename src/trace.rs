@@ -0,0 +1,79 @@
+use std::{fs::File, io::{self, BufWriter, Write}};
+
+use crate::{csr::Mode, disasm::Disasm32, inst::Inst32};
+
+struct MemAccess {
+    addr: u32,
+    size: usize,
+    value: u32,
+    write: bool,
+}
+// Writes one Spike `-l --log-commits` style record per retired instruction:
+//  core   0: 0x00000000 (0x06900513) addi x10, x0, 105
+//  core   0: 3 0x00000000 (0x06900513) x10 0x00000069
+// The first line carries the disassembly, the second the register writeback
+// and memory accesses, so commit lines can be diffed against Spike directly.
+// Loads show the value read as well, which Spike leaves out, so strip it to compare those:
+//  core   0: 3 0x00000004 (0x0002a583) x11 0x12345678 mem 0x40000000 0x12345678
+pub struct Trace {
+    out: BufWriter<File>,
+    pc: u32,
+    raw: u32,
+    inst: Inst32,
+    mode: Mode,
+    reg: Option<(usize, i32)>,
+    mem: Vec<MemAccess>,
+}
+impl Trace {
+    pub fn create(path: &str) -> io::Result<Self> {
+        Ok(Self {
+            out: BufWriter::new(File::create(path)?),
+            pc: 0,
+            raw: 0,
+            inst: Inst32::new(0),
+            mode: Mode::Machine,
+            reg: None,
+            mem: Vec::new(),
+        })
+    }
+    // `raw` is the instruction as fetched, `inst` is what it expands to if it's compressed
+    pub fn fetch(&mut self, pc: u32, raw: u32, inst: Inst32, mode: Mode) {
+        self.pc = pc;
+        self.raw = raw;
+        self.inst = inst;
+        self.mode = mode;
+        self.reg = None;
+        self.mem.clear();
+    }
+    pub fn reg(&mut self, reg: usize, value: i32) {
+        self.reg = Some((reg, value));
+    }
+    pub fn load(&mut self, addr: u32, bytes: &[u8]) {
+        self.mem.push(MemAccess { addr, size: bytes.len(), value: Self::value(bytes), write: false });
+    }
+    pub fn store(&mut self, addr: u32, bytes: &[u8]) {
+        self.mem.push(MemAccess { addr, size: bytes.len(), value: Self::value(bytes), write: true });
+    }
+    fn value(bytes: &[u8]) -> u32 {
+        let mut buf = [0; 4];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        u32::from_le_bytes(buf)
+    }
+    pub fn retire(&mut self) -> io::Result<()> {
+        self.write_record()
+    }
+    fn write_record(&mut self) -> io::Result<()> {
+        // Spike prints compressed instructions as 4 hex digits
+        let raw = if self.raw & 0b11 == 0b11 { format!("0x{:08x}", self.raw) } else { format!("0x{:04x}", self.raw) };
+        writeln!(self.out, "core   0: 0x{:08x} ({}) {}", self.pc, raw, Disasm32(self.inst))?;
+        write!(self.out, "core   0: {} 0x{:08x} ({})", self.mode as u8, self.pc, raw)?;
+        if let Some((reg, value)) = self.reg {
+            write!(self.out, " x{:<2} 0x{:08x}", reg, value as u32)?;
+        }
+        // Loads before stores like Spike, the value's width gives the size
+        for access in self.mem.iter().filter(|x| !x.write).chain(self.mem.iter().filter(|x| x.write)) {
+            write!(self.out, " mem 0x{:08x} 0x{:0width$x}", access.addr, access.value, width = access.size * 2)?;
+        }
+        writeln!(self.out)
+    }
+}
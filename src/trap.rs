@@ -0,0 +1,40 @@
+// NOTE: Table 14 of the privileged manual for exception codes
+pub const INST_MISALIGNED   : u32 = 0;
+pub const INST_ACCESS_FAULT : u32 = 1;
+pub const ILLEGAL_INST      : u32 = 2;
+pub const BREAKPOINT        : u32 = 3;
+pub const LOAD_MISALIGNED   : u32 = 4;
+pub const LOAD_ACCESS_FAULT : u32 = 5;
+pub const STORE_MISALIGNED  : u32 = 6;
+pub const STORE_ACCESS_FAULT: u32 = 7;
+// ECALL from S-mode and M-mode are 9 and 11, following the mode encoding
+pub const ECALL_U           : u32 = 8;
+pub const INST_PAGE_FAULT   : u32 = 12;
+pub const LOAD_PAGE_FAULT   : u32 = 13;
+pub const STORE_PAGE_FAULT  : u32 = 15;
+// Set in mcause/scause when the trap is an interrupt, the rest is the mip bit
+pub const INTERRUPT         : u32 = 1 << 31;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Trap {
+    pub cause: u32,
+    pub tval: u32,
+}
+impl Trap {
+    #[inline]
+    pub const fn new(cause: u32, tval: u32) -> Self {
+        Self { cause, tval }
+    }
+    #[inline]
+    pub const fn illegal(inst: u32) -> Self {
+        Self::new(ILLEGAL_INST, inst)
+    }
+    #[inline]
+    pub const fn interrupt(code: u32) -> Self {
+        Self::new(INTERRUPT | code, 0)
+    }
+    #[inline]
+    pub const fn is_interrupt(&self) -> bool {
+        self.cause & INTERRUPT != 0
+    }
+}
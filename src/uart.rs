@@ -0,0 +1,248 @@
+use std::{collections::VecDeque, io::{self, IsTerminal, Read, Write}, mem, panic, sync::mpsc::{self, Receiver, TryRecvError}, thread};
+
+// NS16550A registers, one byte apart
+pub const RBR: usize = 0;
+pub const IER: usize = 1;
+pub const IIR: usize = 2;
+pub const LCR: usize = 3;
+pub const MCR: usize = 4;
+pub const LSR: usize = 5;
+pub const MSR: usize = 6;
+pub const SCR: usize = 7;
+// Aliases that depend on direction and DLAB
+pub const THR: usize = RBR;
+pub const DLL: usize = RBR;
+pub const DLM: usize = IER;
+pub const FCR: usize = IIR;
+pub const SIZE: usize = 8;
+
+pub mod bits {
+    pub const IER_RDI : u8 = 0x01;
+    pub const IER_THRI: u8 = 0x02;
+    pub const IER_MASK: u8 = 0x0F;
+    pub const IIR_NONE: u8 = 0x01;
+    pub const IIR_THRI: u8 = 0x02;
+    pub const IIR_RDI : u8 = 0x04;
+    pub const IIR_FIFO: u8 = 0xC0;
+    pub const FCR_ENABLE  : u8 = 0x01;
+    pub const FCR_CLEAR_RX: u8 = 0x02;
+    pub const FCR_TRIGGER : u8 = 0xC0;
+    pub const LCR_DLAB: u8 = 0x80;
+    pub const MCR_LOOP: u8 = 0x10;
+    pub const MCR_MASK: u8 = 0x1F;
+    pub const LSR_DR  : u8 = 0x01;
+    pub const LSR_THRE: u8 = 0x20;
+    pub const LSR_TEMT: u8 = 0x40;
+    // DCD, DSR and CTS, a terminal that's always there
+    pub const MSR_IDLE: u8 = 0xB0;
+}
+use bits::*;
+const FIFO_SIZE: usize = 16;
+const CTRL_A: u8 = 0x01;
+
+#[derive(Default)]
+pub struct Uart {
+    rx: VecDeque<u8>,
+    input: Option<Receiver<u8>>,
+    // Stdin is only read once the guest looks for input, until then it's left to the host
+    stdin: bool,
+    ier: u8,
+    fcr: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
+    divisor: u16,
+    // Transmission is instant, so this only tracks whether the guest has been told
+    thre_pending: bool,
+    // Ctrl-A escapes are only handled on a raw terminal, which is put back when the UART goes
+    raw: Option<RawTerminal>,
+    escape: bool,
+    // Set by Ctrl-A x
+    pub quit: bool,
+}
+// Console output that bypasses the registers, for firmware running on the host
+pub fn putchar(byte: u8) {
+    let mut out = io::stdout();
+    let _ = out.write_all(&[byte]).and_then(|_| out.flush());
+}
+
+impl Uart {
+    // Bytes are read on a separate thread, so the guest never blocks on the host
+    pub fn connect_stdin(&mut self, raw: bool) {
+        self.stdin = true;
+        self.raw = if raw { RawTerminal::enter() } else { None };
+    }
+    // Starts the reader thread the first time the guest reads RBR or LSR or asks for RX interrupts
+    fn listen(&mut self) {
+        if !mem::take(&mut self.stdin) { return; }
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for byte in io::stdin().lock().bytes().map_while(Result::ok) {
+                if tx.send(byte).is_err() { break; }
+            }
+        });
+        self.input = Some(rx);
+    }
+    // Registers and RX FIFO back to power on, still wired to the same input
+    pub fn reset(&mut self) {
+        *self = Self { input: self.input.take(), stdin: self.stdin, raw: self.raw.take(), ..Default::default() };
+    }
+    // The registers, then whatever is waiting in the RX FIFO
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut bytes = vec![self.ier, self.fcr, self.lcr, self.mcr, self.scr, self.thre_pending as u8];
+        bytes.extend(self.divisor.to_le_bytes());
+        bytes.extend(&self.rx);
+        bytes
+    }
+    fn capacity(&self) -> usize {
+        if self.fcr & FCR_ENABLE != 0 { FIFO_SIZE } else { 1 }
+    }
+    fn receive(&mut self, byte: u8) {
+        if self.escape {
+            self.escape = false;
+            match byte {
+                b'x' | b'X' => { self.quit = true; return; }
+                CTRL_A => {}
+                _ => return,
+            }
+        } else if self.raw.is_some() && byte == CTRL_A {
+            self.escape = true;
+            return;
+        }
+        self.rx.push_back(byte);
+    }
+    // Moves host input into the RX FIFO as long as there's room for it
+    pub fn poll(&mut self) {
+        while self.rx.len() < self.capacity() && !self.quit {
+            let Some(input) = self.input.as_ref() else { return; };
+            match input.try_recv() {
+                Ok(byte) => self.receive(byte),
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => self.input = None,
+            }
+        }
+    }
+    // Blocks until input arrives, false if none ever will
+    pub fn wait(&mut self) -> bool {
+        self.listen();
+        while self.rx.is_empty() && !self.quit {
+            let Some(input) = self.input.as_ref() else { return false; };
+            match input.recv() {
+                Ok(byte) => self.receive(byte),
+                Err(_) => self.input = None,
+            }
+        }
+        true
+    }
+    // Whether input could ever raise the interrupt
+    pub fn can_interrupt(&self) -> bool {
+        self.ier & IER_RDI != 0 && (self.input.is_some() || !self.rx.is_empty())
+    }
+    pub fn interrupting(&self) -> bool {
+        (self.ier & IER_RDI != 0 && !self.rx.is_empty()) || (self.ier & IER_THRI != 0 && self.thre_pending)
+    }
+    fn dlab(&self) -> bool {
+        self.lcr & LCR_DLAB != 0
+    }
+    pub fn read(&mut self, off: usize) -> u8 {
+        match off {
+            DLL if self.dlab() => self.divisor as u8,
+            DLM if self.dlab() => (self.divisor >> 8) as u8,
+            RBR => {
+                self.listen();
+                let byte = self.rx.pop_front().unwrap_or(0);
+                self.poll();
+                byte
+            }
+            IER => self.ier,
+            IIR => {
+                let fifo = if self.fcr & FCR_ENABLE != 0 { IIR_FIFO } else { 0 };
+                fifo | if self.ier & IER_RDI != 0 && !self.rx.is_empty() {
+                    IIR_RDI
+                } else if self.ier & IER_THRI != 0 && self.thre_pending {
+                    // Reading the THRE cause acknowledges it
+                    self.thre_pending = false;
+                    IIR_THRI
+                } else {
+                    IIR_NONE
+                }
+            }
+            LCR => self.lcr,
+            MCR => self.mcr,
+            LSR => {
+                self.listen();
+                self.poll();
+                LSR_THRE | LSR_TEMT | if self.rx.is_empty() { 0 } else { LSR_DR }
+            }
+            // Loopback feeds DTR, RTS, OUT1 and OUT2 back as DSR, CTS, RI and DCD
+            MSR if self.mcr & MCR_LOOP != 0 => {
+                let m = self.mcr;
+                ((m & 0x1) << 5) | ((m & 0x2) << 3) | ((m & 0x4) << 4) | ((m & 0x8) << 4)
+            }
+            MSR => MSR_IDLE,
+            SCR => self.scr,
+            _ => 0,
+        }
+    }
+    pub fn write(&mut self, off: usize, v: u8) {
+        match off {
+            DLL if self.dlab() => self.divisor = (self.divisor & 0xFF00) | v as u16,
+            DLM if self.dlab() => self.divisor = (self.divisor & 0x00FF) | ((v as u16) << 8),
+            THR => {
+                if self.mcr & MCR_LOOP != 0 {
+                    if self.rx.len() < self.capacity() { self.rx.push_back(v); }
+                } else {
+                    putchar(v);
+                }
+                self.thre_pending = true;
+            }
+            IER => {
+                // Enabling THRE interrupts with an empty THR raises one right away
+                if v & IER_THRI != 0 && self.ier & IER_THRI == 0 {
+                    self.thre_pending = true;
+                }
+                if v & IER_RDI != 0 { self.listen(); }
+                self.ier = v & IER_MASK;
+            }
+            FCR => {
+                if v & FCR_CLEAR_RX != 0 || (v ^ self.fcr) & FCR_ENABLE != 0 {
+                    self.rx.clear();
+                }
+                self.fcr = v & (FCR_ENABLE | FCR_TRIGGER);
+                self.poll();
+            }
+            LCR => self.lcr = v,
+            MCR => self.mcr = v & MCR_MASK,
+            SCR => self.scr = v,
+            _ => {}
+        }
+    }
+}
+
+// Puts the host terminal in raw mode until dropped, so keys reach the guest as they're typed.
+// A panic puts it back before the message gets printed.
+pub struct RawTerminal(libc::termios);
+impl RawTerminal {
+    fn set(attrs: &libc::termios) -> bool {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, attrs) == 0 }
+    }
+    pub fn enter() -> Option<Self> {
+        if !io::stdin().is_terminal() { return None; }
+        let mut saved: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } != 0 { return None; }
+        let mut raw = saved;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        if !Self::set(&raw) { return None; }
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            Self::set(&saved);
+            hook(info);
+        }));
+        Some(Self(saved))
+    }
+}
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        Self::set(&self.0);
+    }
+}
@@ -0,0 +1,105 @@
+use std::{collections::hash_map::RandomState, hash::{BuildHasher, Hasher}};
+use crate::{csr::{self, Mode}, elf::Elf, linux::Process, mmu, region::{Memory, Region, RegionList}, setup::{self, Setup}, vm::VM};
+
+// NOTE: A single Linux process and nothing else, the way qemu-user runs one.
+// One flat address space with the first page left out so null pointers fault,
+// the heap grows up from the image and mmap hands out memory down from the stack.
+pub const SIZE: usize = 256 * 1024 * 1024;
+pub const STACK_SIZE: usize = 8 * 1024 * 1024;
+pub fn setup(mut ram: Vec<u8>) -> Setup {
+    setup::grow(&mut ram, SIZE);
+    let null = mmu::PAGE_SIZE as usize;
+    let end = ram.len();
+    let ram = ram.split_off(null);
+    let layout = RegionList(
+        vec![
+            Region {
+                size: ram.len(),
+                device: Box::new(Memory::new(ram)),
+                addr: null,
+                latency: 0,
+            }
+        ].into_boxed_slice());
+    Setup { sp: end, layout, ram_end: end }
+}
+
+// Auxiliary vector types, include/uapi/linux/auxvec.h
+mod at {
+    pub const NULL  : u32 = 0;
+    pub const PHDR  : u32 = 3;
+    pub const PHENT : u32 = 4;
+    pub const PHNUM : u32 = 5;
+    pub const PAGESZ: u32 = 6;
+    pub const ENTRY : u32 = 9;
+    pub const UID   : u32 = 11;
+    pub const EUID  : u32 = 12;
+    pub const GID   : u32 = 13;
+    pub const EGID  : u32 = 14;
+    pub const HWCAP : u32 = 16;
+    pub const CLKTCK: u32 = 17;
+    pub const SECURE: u32 = 23;
+    pub const RANDOM: u32 = 25;
+    pub const EXECFN: u32 = 31;
+}
+// What the kernel tells the program about itself and the machine
+pub fn auxv(elf: &Elf) -> Vec<(u32, u32)> {
+    let mut aux = vec![
+        (at::PHENT, elf.phentsize as u32),
+        (at::PHNUM, elf.phnum as u32),
+        (at::PAGESZ, mmu::PAGE_SIZE),
+        (at::ENTRY, elf.entry),
+        (at::UID, 0), (at::EUID, 0), (at::GID, 0), (at::EGID, 0),
+        // One bit per single letter extension, same as misa
+        (at::HWCAP, csr::MISA_VALUE & ((1 << 26) - 1)),
+        (at::CLKTCK, 100),
+        (at::SECURE, 0),
+    ];
+    if let Some(phdr) = elf.phdr {
+        aux.push((at::PHDR, phdr));
+    }
+    aux
+}
+
+// Lays out the initial stack below `top` like execve does:
+// argc, the argv and envp pointer arrays, then the auxiliary vector, with the strings above.
+// Returns the stack pointer the program starts with, or where the stack ran out of memory.
+pub fn stack(vm: &mut VM, top: usize, args: &[String], env: &[String], mut aux: Vec<(u32, u32)>) -> Result<usize, usize> {
+    let mut sp = top;
+    let mut push = |bytes: &[u8]| -> Result<u32, usize> {
+        sp = sp.saturating_sub(bytes.len());
+        vm.write(sp, bytes)?;
+        Ok(sp as u32)
+    };
+    let mut random = [0; 16];
+    for chunk in random.chunks_mut(8) {
+        chunk.copy_from_slice(&RandomState::new().build_hasher().finish().to_le_bytes());
+    }
+    aux.push((at::RANDOM, push(&random)?));
+    let mut string = |s: &String| push(&[s.as_bytes(), &[0]].concat());
+    aux.push((at::EXECFN, string(&args[0])?));
+    let argv = args.iter().map(&mut string).collect::<Result<Vec<u32>, _>>()?;
+    let envp = env.iter().map(&mut string).collect::<Result<Vec<u32>, _>>()?;
+    aux.push((at::NULL, 0));
+
+    let mut words = vec![args.len() as u32];
+    words.extend(argv);
+    words.push(0);
+    words.extend(envp);
+    words.push(0);
+    words.extend(aux.into_iter().flat_map(|(k, v)| [k, v]));
+    let sp = sp.saturating_sub(words.len()*4) & !0xF;
+    for (i, word) in words.into_iter().enumerate() {
+        vm.write(sp+i*4, &word.to_le_bytes())?;
+    }
+    Ok(sp)
+}
+
+// Drops the hart into U-mode with the process behind every ECALL
+pub fn boot(vm: &mut VM, process: Process) {
+    vm.process = Some(process);
+    vm.mode = Mode::User;
+    vm.pmp.allow_all();
+    // rdcycle, rdtime and rdinstret work from user space
+    vm.csr.mcounteren = 0b111;
+    vm.csr.scounteren = 0b111;
+}
@@ -0,0 +1,56 @@
+use crate::{clint, plic, uart::{self, Uart}, region::{ClintMmio, Finisher, Memory, PlicMmio, Region, RegionList, UartMmio}, setup::{self, Setup}};
+
+// NOTE: Memory map of QEMU's virt board, hw/riscv/virt.c
+const TEST: usize = 0x100000;
+const TEST_SIZE: usize = 0x1000;
+const CLINT: usize = 0x2000000;
+const PLIC: usize = 0xC000000;
+const UART: usize = 0x10000000;
+pub const UART_IRQ: usize = 10;
+pub const DRAM: usize = 0x80000000;
+// QEMU's default -m 128M
+pub const DRAM_SIZE: usize = 128 * 1024 * 1024;
+const MMIO_LATENCY: u32 = 4;
+// `stdin` hooks the UART up to the host's input, raw or not
+pub fn setup(mut ram: Vec<u8>, stdin: Option<bool>) -> Setup {
+    setup::grow(&mut ram, DRAM_SIZE);
+    let mut uart = Uart::default();
+    if let Some(raw) = stdin {
+        uart.connect_stdin(raw);
+    }
+    let end = DRAM + ram.len();
+    let layout = RegionList(
+        vec![
+            Region {
+                device: Box::new(Finisher),
+                addr: TEST,
+                size: TEST_SIZE,
+                latency: MMIO_LATENCY,
+            },
+            Region {
+                device: Box::new(ClintMmio::default()),
+                addr: CLINT,
+                size: clint::SIZE,
+                latency: MMIO_LATENCY,
+            },
+            Region {
+                device: Box::new(PlicMmio::default()),
+                addr: PLIC,
+                size: plic::SIZE,
+                latency: MMIO_LATENCY,
+            },
+            Region {
+                device: Box::new(UartMmio::new(uart, PLIC, UART_IRQ)),
+                addr: UART,
+                size: uart::SIZE,
+                latency: MMIO_LATENCY,
+            },
+            Region {
+                size: ram.len(),
+                device: Box::new(Memory::new(ram)),
+                addr: DRAM,
+                latency: 0,
+            }
+        ].into_boxed_slice());
+    Setup { sp: end, layout, ram_end: end }
+}
@@ -1,202 +1,886 @@
-use crate::ops::{self, branch, imm_math, load, reg_math, store};
-use crate::region::RegionList;
+use crate::ops::{self, amo, branch, imm_math, jump_reg, load, misc_mem, reg_math, store, system};
+use crate::region::{Busy, Device, RegionList};
 use crate::inst::{inst_len, Inst32};
-use crate::disasm::Disasm32;
+use crate::rvc;
+use crate::trace::Trace;
+use crate::profile::Profiler;
+use crate::coverage::Coverage;
+use crate::timing::Timing;
+use crate::csr::{self, irq, mstatus, Csrs, Mode};
+use crate::trap::{self, Trap};
+use crate::cache::{Access, CacheSim};
+use crate::bpred::BranchSim;
+use crate::mmu::{self, pte, Tlb, TlbEntry};
+use crate::pmp::Pmp;
+use crate::clint::{Clock, Timebase};
+use crate::sbi;
+use crate::linux::{self, Abi, Process};
+use crate::semihost::{self, Semihost};
+use crate::region::Kind;
+use crate::htif::{self, Htif};
+use crate::elf::Symbols;
 
-pub struct VM<'a, 'rlist> {
-    pub regions: &'rlist RegionList,
-    pub ram: &'a mut [u8],
+/// Why the hart stopped.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Halt {
+    /// The guest exited with a status, through an exit port, the test finisher, HTIF or a system call.
+    Exit(u8),
+    /// The hart can no longer make progress (self-loop or WFI).
+    Idle,
+}
+/// A single RV32IMAC hart with its memory map and devices, usually put together by a [`Builder`](crate::Builder).
+/// Registers and CSRs are public to look at or poke between steps, RAM through [`VM::memory`] or [`VM::write`].
+pub struct VM {
+    pub regions: RegionList,
     pub regs: [i32; 32],
-    pub ip: i32
+    pub ip: i32,
+    pub halt: Option<Halt>,
+    pub trace: Option<Trace>,
+    pub profile: Option<Profiler>,
+    pub coverage: Option<Coverage>,
+    pub timing: Option<Timing>,
+    pub cache: Option<CacheSim>,
+    pub bpred: Option<BranchSim>,
+    pub cycles: u64,
+    pub instret: u64,
+    pub mode: Mode,
+    pub csr: Csrs,
+    pub tlb: Tlb,
+    pub pmp: Pmp,
+    // mtime and the time CSR
+    pub time: Timebase,
+    // Interrupt inputs as the devices last drove them, see set_irq
+    irq: u32,
+    // ECALLs from S-mode go to the built-in SBI instead of M-mode
+    pub sbi: bool,
+    // Host side of the program's system calls, see -muser and -newlib
+    pub process: Option<Process>,
+    // EBREAKs in the semihosting sequence go to the host, see -semihost
+    pub semihost: Option<Semihost>,
+    // tohost and fromhost, see htif.rs
+    pub htif: Option<Htif>,
+    // Address reserved by the last LR.W
+    pub reservation: Option<u32>,
+    /// Function symbols from the ELF, what the reports name code by.
+    pub symbols: Symbols,
+    /// `begin_signature..end_signature` from the ELF, the words RISCOF compares.
+    pub signature: Option<(u32, u32)>,
 }
 
-impl <'a, 'rlist> VM <'a, 'rlist> {
+impl VM {
     #[inline]
     pub fn set_rsp(&mut self, rsp: usize)  {
         assert!(rsp < u32::MAX as usize);
         self.regs[2] = rsp as i32;
     }
-    pub fn new(regions: &'rlist RegionList, ram: &'a mut [u8]) -> Self {
-        Self { ram, regions, ip: 0, regs: [0; 32] }
+    /// A hart in M-mode at address 0, with RAM and devices all behind `regions`.
+    pub fn new(regions: RegionList) -> Self {
+        Self { regions, ip: 0, regs: [0; 32], halt: None, trace: None, profile: None, coverage: None, timing: None, cache: None, bpred: None, cycles: 0, instret: 0, mode: Mode::Machine, csr: Csrs::default(), tlb: Tlb::default(), pmp: Pmp::default(), time: Timebase::new(Clock::Instret(1)), irq: 0, sbi: false, process: None, semihost: None, htif: None, reservation: None, symbols: Symbols::default(), signature: None }
     }
     pub fn ip(&self) -> usize {
         self.ip as u32 as usize
     }
-    pub fn write(&mut self, mut addr: usize, mut bytes: &[u8]) {
+    // The device gets the whole VM to work with, so it's out of the list while it runs
+    fn with_device<T>(&mut self, i: usize, f: impl FnOnce(&mut dyn Device, &mut VM) -> T) -> T {
+        let mut device = std::mem::replace(&mut self.regions.0[i].device, Box::new(Busy));
+        let v = f(device.as_mut(), self);
+        self.regions.0[i].device = device;
+        v
+    }
+    /// Drives the hart's interrupt inputs in `mask` (mip bits) to `bits`, for the devices wired straight to it.
+    pub fn set_irq(&mut self, mask: u32, bits: u32) {
+        self.irq = self.irq & !mask | bits & mask;
+    }
+    /// Drives line `line` of the interrupt controller mapped at `controller`, does nothing if there's none.
+    pub fn set_line(&mut self, controller: usize, line: usize, high: bool) {
+        if let Some(i) = self.regions.find(controller) {
+            self.with_device(i, |device, vm| device.set_line(vm, line, high));
+        }
+    }
+    /// `len` bytes of RAM at physical address `addr`, None unless a single memory region holds them all.
+    pub fn memory(&mut self, addr: usize, len: usize) -> Option<&mut [u8]> {
+        let region = &mut self.regions.0[self.regions.find(addr)?];
+        let off = addr - region.addr;
+        if len > region.size - off { return None; }
+        region.device.memory().map(|x| &mut x[off..off+len])
+    }
+    /// Writes physical memory, devices included, without any checks.
+    /// Fails with the address of the first byte nothing is mapped at or a device refused.
+    pub fn write(&mut self, mut addr: usize, mut bytes: &[u8]) -> Result<(), usize> {
         while !bytes.is_empty() {
-            let region = match self.regions.find_region(addr) {
-                Some(v) => v,
-                None => panic!("Exception: Out of bounds write at {:08X} (ip=0x{:08X})", addr, self.ip),
-            };
-            let to_write = bytes.len().min(region.size);
+            let i = self.regions.find(addr).ok_or(addr)?;
+            let region = &self.regions.0[i];
+            // Whatever doesn't fit goes to the next region
+            let to_write = bytes.len().min(region.addr+region.size-addr);
             let (bytes_to_write, left) = bytes.split_at(to_write);
             bytes = left;
             let off = addr-region.addr;
             addr += to_write;
-            region.write(self, off, bytes_to_write).expect("Failed to write");
+            self.with_device(i, |device, vm| device.write(vm, off, bytes_to_write)).map_err(|_| addr-to_write)?;
         }
+        Ok(())
     }
 
-    pub fn read(&mut self, mut addr: usize, mut bytes: &mut [u8]) {
+    /// Reads physical memory, devices included, without any checks. Fails like `write`.
+    pub fn read(&mut self, mut addr: usize, mut bytes: &mut [u8]) -> Result<(), usize> {
         while !bytes.is_empty() {
-            let region = match self.regions.find_region(addr) {
-                Some(v) => v,
-                None => panic!("Exception: Out of bounds read at {:08X} (ip=0x{:08X})", addr, self.ip),
-            };
-            let to_read = bytes.len().min(region.size);
+            let i = self.regions.find(addr).ok_or(addr)?;
+            let region = &self.regions.0[i];
+            let to_read = bytes.len().min(region.addr+region.size-addr);
             let (bytes_to_read, left) = bytes.split_at_mut(to_read);
             bytes = left;
             let off = addr-region.addr;
             addr += to_read;
-            region.read(self, off, bytes_to_read).expect("Failed to read");
+            self.with_device(i, |device, vm| device.read(vm, off, bytes_to_read)).map_err(|_| addr-to_read)?;
+        }
+        Ok(())
+    }
+    /// Puts every device back the way it powered on, the hart is left alone.
+    pub fn reset_devices(&mut self) {
+        for i in 0..self.regions.0.len() {
+            self.with_device(i, |device, vm| device.reset(vm));
+        }
+    }
+    /// Each region's device state, in address order.
+    pub fn snapshot_devices(&self) -> Vec<Vec<u8>> {
+        self.regions.0.iter().map(|x| x.device.snapshot(self)).collect()
+    }
+    // Feeds an access made by the guest to the cache simulator and timing model
+    fn observe(&mut self, addr: usize, kind: Access) {
+        if self.timing.is_none() && self.cache.is_none() { return; }
+        let Some(region) = self.regions.find_region(addr) else { return; };
+        let mut latency = region.latency;
+        if let Some(cache) = self.cache.as_mut() {
+            latency += cache.access(addr as u32, kind, region.addr, self.ip as u32);
+        }
+        if let Some(timing) = self.timing.as_mut() {
+            timing.access(latency);
+        }
+    }
+    // Raises an access fault unless memory backs the whole range and PMP lets `mode` at it.
+    // `vaddr` is what the fault reports.
+    fn check_phys(&self, addr: usize, len: usize, vaddr: u32, access: Access, mode: Mode) -> Result<(), Trap> {
+        let backed = self.regions.find_region(addr).is_some() && self.regions.find_region(addr+len-1).is_some();
+        if !backed || !self.pmp.check(addr as u64, len, access, mode) {
+            return Err(mmu::access_fault(access, vaddr));
+        }
+        Ok(())
+    }
+    // Page table reads are implicit S-mode accesses as far as PMP is concerned
+    fn read_pte(&mut self, addr: usize, vaddr: u32, access: Access) -> Result<u32, Trap> {
+        self.check_phys(addr, 4, vaddr, access, Mode::Supervisor)?;
+        self.read_u32(addr).map_err(|_| mmu::access_fault(access, vaddr))
+    }
+    // Sv32 two level walk, every PTE visited is recorded in `steps` for the debugger.
+    // Permissions are left to the caller since the TLB caches the leaf flags.
+    pub fn walk(&mut self, vaddr: u32, access: Access, steps: &mut [Option<(usize, u32)>; 2]) -> Result<TlbEntry, Trap> {
+        let fault = mmu::page_fault(access, vaddr);
+        let mut table = ((self.csr.satp & mmu::SATP_PPN) as usize) << mmu::PAGE_SHIFT;
+        for level in (0..2).rev() {
+            let addr = table + ((vaddr >> (mmu::PAGE_SHIFT + 10*level)) & 0x3FF) as usize * 4;
+            let entry = self.read_pte(addr, vaddr, access)?;
+            steps[1-level as usize] = Some((addr, entry));
+            if entry & pte::V == 0 || (entry & pte::R == 0 && entry & pte::W != 0) {
+                return Err(fault);
+            }
+            let ppn = entry >> 10;
+            if entry & (pte::R | pte::X) != 0 {
+                // Megapages have to be aligned to 4 MiB
+                if level == 1 && ppn & 0x3FF != 0 {
+                    return Err(fault);
+                }
+                let vpn = vaddr >> mmu::PAGE_SHIFT;
+                let ppn = if level == 1 { ppn | (vpn & 0x3FF) } else { ppn };
+                return Ok(TlbEntry { vpn, ppn, flags: entry & 0xFF, mega: level == 1 });
+            }
+            table = (ppn as usize) << mmu::PAGE_SHIFT;
+        }
+        // Pointer to a third level that Sv32 doesn't have
+        Err(fault)
+    }
+    // Privilege loads and stores are checked at, MPRV makes M-mode borrow the one in MPP
+    fn data_mode(&self) -> Mode {
+        if self.mode == Mode::Machine && self.csr.mstatus & mstatus::MPRV != 0 {
+            Mode::from_bits(self.csr.mstatus >> mstatus::MPP_SHIFT)
+        } else {
+            self.mode
+        }
+    }
+    pub fn translate(&mut self, vaddr: u32, access: Access) -> Result<usize, Trap> {
+        let mode = if access == Access::Fetch { self.mode } else { self.data_mode() };
+        if mode == Mode::Machine || self.csr.satp & mmu::SATP_MODE == 0 {
+            return Ok(vaddr as usize);
+        }
+        let entry = match self.tlb.lookup(vaddr >> mmu::PAGE_SHIFT) {
+            Some(v) => v,
+            None => {
+                let entry = self.walk(vaddr, access, &mut [None; 2])?;
+                self.tlb.insert(entry);
+                entry
+            }
+        };
+        if !mmu::allowed(entry.flags, access, mode, self.csr.mstatus) {
+            return Err(mmu::page_fault(access, vaddr));
+        }
+        Ok(((entry.ppn as usize) << mmu::PAGE_SHIFT) | (vaddr & (mmu::PAGE_SIZE-1)) as usize)
+    }
+    // Translates and checks an access that may straddle two pages.
+    // Returns where the first part goes, its length and where the rest goes.
+    fn translate_span(&mut self, vaddr: u32, len: usize, access: Access) -> Result<(usize, usize, usize), Trap> {
+        let mode = self.data_mode();
+        let first = self.translate(vaddr, access)?;
+        let split = len.min((mmu::PAGE_SIZE - (vaddr & (mmu::PAGE_SIZE-1))) as usize);
+        if split == len {
+            self.check_phys(first, len, vaddr, access, mode)?;
+            return Ok((first, len, 0));
+        }
+        let second = self.translate(vaddr.wrapping_add(split as u32), access)?;
+        self.check_phys(first, split, vaddr, access, mode)?;
+        self.check_phys(second, len-split, vaddr, access, mode)?;
+        Ok((first, split, second))
+    }
+    // Data accesses made by the guest, as opposed to fetches and debugger peeks
+    pub fn load(&mut self, vaddr: u32, bytes: &mut [u8]) -> Result<(), Trap> {
+        let (first, split, second) = self.translate_span(vaddr, bytes.len(), Access::Read)?;
+        let (a, b) = bytes.split_at_mut(split);
+        // A device turning the access down faults like an unmapped address would
+        let fault = mmu::access_fault(Access::Read, vaddr);
+        self.observe(first, Access::Read);
+        self.read(first, a).map_err(|_| fault)?;
+        if !b.is_empty() {
+            self.observe(second, Access::Read);
+            self.read(second, b).map_err(|_| fault)?;
+        }
+        if let Some(trace) = self.trace.as_mut() {
+            trace.load(vaddr, bytes);
+        }
+        Ok(())
+    }
+    fn store(&mut self, vaddr: u32, bytes: &[u8]) -> Result<(), Trap> {
+        let (first, split, second) = self.translate_span(vaddr, bytes.len(), Access::Write)?;
+        let (a, b) = bytes.split_at(split);
+        let fault = mmu::access_fault(Access::Write, vaddr);
+        self.observe(first, Access::Write);
+        self.write(first, a).map_err(|_| fault)?;
+        if !b.is_empty() {
+            self.observe(second, Access::Write);
+            self.write(second, b).map_err(|_| fault)?;
+        }
+        if let Some(trace) = self.trace.as_mut() {
+            trace.store(vaddr, bytes);
+        }
+        let watched = |x: &Htif| x.watches(first, a.len()) || (!b.is_empty() && x.watches(second, b.len()));
+        if self.htif.as_ref().is_some_and(watched) {
+            htif::poll(self);
         }
+        Ok(())
+    }
+    // Guest memory as the host sees it when servicing a call from the guest: through the guest's
+    // translation and PMP, a page at a time, but invisible to the trace and the memory models.
+    pub fn copy_from_guest(&mut self, vaddr: u32, bytes: &mut [u8]) -> Result<(), Trap> {
+        let mode = self.data_mode();
+        let mut off = 0;
+        while off < bytes.len() {
+            let at = vaddr.wrapping_add(off as u32);
+            let n = (bytes.len() - off).min((mmu::PAGE_SIZE - (at & (mmu::PAGE_SIZE-1))) as usize);
+            let addr = self.translate(at, Access::Read)?;
+            self.check_phys(addr, n, at, Access::Read, mode)?;
+            self.read(addr, &mut bytes[off..off+n]).map_err(|_| mmu::access_fault(Access::Read, at))?;
+            off += n;
+        }
+        Ok(())
+    }
+    pub fn copy_to_guest(&mut self, vaddr: u32, bytes: &[u8]) -> Result<(), Trap> {
+        let mode = self.data_mode();
+        let mut off = 0;
+        while off < bytes.len() {
+            let at = vaddr.wrapping_add(off as u32);
+            let n = (bytes.len() - off).min((mmu::PAGE_SIZE - (at & (mmu::PAGE_SIZE-1))) as usize);
+            let addr = self.translate(at, Access::Write)?;
+            self.check_phys(addr, n, at, Access::Write, mode)?;
+            self.write(addr, &bytes[off..off+n]).map_err(|_| mmu::access_fault(Access::Write, at))?;
+            off += n;
+        }
+        Ok(())
     }
     #[inline]
-    pub fn read_u16(&mut self, addr: usize) -> u16 {
+    pub fn read_u16(&mut self, addr: usize) -> Result<u16, usize> {
         let mut tag_bytes: [u8; 2] = [0; 2];
-        self.read(addr, &mut tag_bytes);
-        u16::from_le_bytes(tag_bytes)
+        self.read(addr, &mut tag_bytes)?;
+        Ok(u16::from_le_bytes(tag_bytes))
     }
 
     #[inline]
-    pub fn read_u32(&mut self, addr: usize) -> u32 {
+    pub fn read_u32(&mut self, addr: usize) -> Result<u32, usize> {
         let mut tag_bytes: [u8; 4] = [0; 4];
-        self.read(addr, &mut tag_bytes);
-        u32::from_le_bytes(tag_bytes)
+        self.read(addr, &mut tag_bytes)?;
+        Ok(u32::from_le_bytes(tag_bytes))
     }
 
-    pub fn disasm(&mut self, addr: usize) -> usize {
-        let tag = self.read_u16(addr);
-        let len = inst_len(tag);
-        match len {
-            2 => {
-                let inst = Inst32::new(self.read_u32(addr));
-                println!("{}",Disasm32(inst));
-            }
-            _ => panic!("Unsupported {} bit Instruction",len*16),
+    // Decodes the instruction at physical `addr`, a compressed one expanded, and its length in bytes
+    pub fn disasm(&mut self, addr: usize) -> Result<(Inst32, usize), String> {
+        let unreadable = |x: usize| format!("Nothing readable at 0x{:08X}", x);
+        let tag = self.read_u16(addr).map_err(unreadable)?;
+        match inst_len(tag) {
+            1 => rvc::expand(tag).map(|x| (x, 2)).ok_or_else(|| format!("Illegal compressed instruction 0x{:04X}", tag)),
+            2 => Ok((Inst32::new(self.read_u32(addr).map_err(unreadable)?), 4)),
+            _ => Err(format!("Unsupported instruction length, first parcel 0x{:04X}", tag)),
         }
-        len
     }
     #[inline]
     pub fn set_reg(&mut self, reg: usize, v: i32) {
         if reg == 0 { return; }
         self.regs[reg] = v;
+        if let Some(trace) = self.trace.as_mut() {
+            trace.reg(reg, v);
+        }
     }
 
+    /// Integer register `reg`, x0 reads as 0.
     #[inline]
     pub fn get_reg(&self, reg: usize) -> i32 {
         if reg == 0 { return 0; }
         self.regs[reg]
     }
-    pub fn run(&mut self) {
-        let tag = self.read_u16(self.ip());
-        let len = inst_len(tag);
-        match len {
+    // Returns the instruction, expanded if it's compressed, and its raw bits.
+    // A 32-bit instruction can straddle two pages, each half is fetched on its own.
+    fn fetch(&mut self) -> Result<(Inst32, u32), Trap> {
+        let pc = self.ip as u32;
+        let addr = self.translate(pc, Access::Fetch)?;
+        self.check_phys(addr, 2, pc, Access::Fetch, self.mode)?;
+        let tag = self.read_u16(addr).map_err(|_| mmu::access_fault(Access::Fetch, pc))?;
+        self.observe(addr, Access::Fetch);
+        match inst_len(tag) {
+            1 => rvc::expand(tag).map(|x| (x, tag as u32)).ok_or(Trap::illegal(tag as u32)),
             2 => {
-                let inst = Inst32::new(self.read_u32(self.ip()));
-                match inst.opcode() {
-                    ops::LUI_OP      => self.set_reg(inst.rd() as usize, inst.imm_U() << 12),
-                    ops::AUIPC_OP    => self.set_reg(inst.rd() as usize, self.ip + inst.imm_U() << 12),
-                    ops::IMM_MATH_OP => {
-                         match inst.funct3() {
-                             imm_math::ADDI => {
-                                 self.set_reg(inst.rd() as usize, self.get_reg(inst.r1() as usize) + inst.imm_I());
-                             }
-                             funct3 => {
-                                 todo!("Immediate math op 0x{:01X}",funct3)
-                             }
-                         }
+                let next = pc.wrapping_add(2);
+                let addr = self.translate(next, Access::Fetch)?;
+                self.check_phys(addr, 2, next, Access::Fetch, self.mode)?;
+                let high = self.read_u16(addr).map_err(|_| mmu::access_fault(Access::Fetch, next))?;
+                let raw = (high as u32) << 16 | tag as u32;
+                Ok((Inst32::new(raw), raw))
+            }
+            _ => Err(Trap::illegal(tag as u32)),
+        }
+    }
+    // The instruction word at `vaddr` without fetching it, if it's in memory
+    pub fn peek_inst(&mut self, vaddr: u32) -> Option<u32> {
+        let addr = self.translate(vaddr, Access::Fetch).ok()?;
+        self.check_phys(addr, 4, vaddr, Access::Fetch, self.mode).ok()?;
+        if self.regions.find_region(addr)?.device.kind() != Kind::Memory { return None; }
+        self.read_u32(addr).ok()
+    }
+    // Interrupts that would trap right now if they were pending
+    fn enabled_interrupts(&self) -> u32 {
+        let status = self.csr.mstatus;
+        let m = self.mode < Mode::Machine || status & mstatus::MIE != 0;
+        let s = self.mode < Mode::Supervisor || (self.mode == Mode::Supervisor && status & mstatus::SIE != 0);
+        let mut enabled = 0;
+        if m { enabled |= !self.csr.mideleg; }
+        if s { enabled |= self.csr.mideleg; }
+        self.csr.mie & enabled
+    }
+    // csr.mip only holds the bits software can write, the rest come straight from the devices
+    pub fn mip(&self) -> u32 {
+        let mut clint = self.irq & (irq::MSI | irq::MTI);
+        // The built-in SBI forwards MSIP and MTIP to SSIP and STIP, like firmware would
+        if self.sbi { clint >>= 2; }
+        self.csr.mip | clint | self.irq & !(irq::MSI | irq::MTI)
+    }
+    // Checked before every instruction
+    fn interrupt(&mut self) -> Option<Trap> {
+        let pending = self.mip() & self.enabled_interrupts();
+        if pending == 0 { return None; }
+        let bit = irq::PRIORITY.into_iter().find(|x| pending & x != 0)?;
+        Some(Trap::interrupt(bit.trailing_zeros()))
+    }
+    // Lets the devices catch up every so often, the UART picks up host input then
+    fn poll_devices(&mut self) {
+        for i in 0..self.regions.0.len() {
+            self.with_device(i, |device, vm| device.tick(vm));
+        }
+    }
+    /// Steps the hart: one instruction, or into a trap or interrupt handler.
+    pub fn run(&mut self) {
+        if self.instret & 0xFF == 0 {
+            self.poll_devices();
+            if self.halt.is_some() { return; }
+        }
+        if let Some(trap) = self.interrupt() {
+            return self.take_trap(trap);
+        }
+        let pc = self.ip as u32;
+        let (inst, raw) = match self.fetch() {
+            Ok(v) => v,
+            Err(trap) => return self.take_trap(trap),
+        };
+        if let Some(trace) = self.trace.as_mut() {
+            trace.fetch(pc, raw, inst, self.mode);
+        }
+        let len = if raw & 0b11 == 0b11 { 4 } else { 2 };
+        if let Err(trap) = self.execute(inst, len) {
+            return self.take_trap(trap);
+        }
+        self.instret += 1;
+        self.time.retire();
+        let mispredicted = self.bpred.as_mut().map(|x| x.resolve(pc, inst, len as u32, self.ip as u32));
+        self.cycles += match self.timing.as_mut() {
+            Some(timing) => timing.retire(pc, inst, self.ip as u32, mispredicted),
+            None => 1,
+        };
+        // A full disk or a closed pipe ends the trace, not the run
+        if let Some(Err(e)) = self.trace.as_mut().map(|x| x.retire()) {
+            eprintln!("WARN: Stopped tracing: {}", e);
+            self.trace = None;
+        }
+        if let Some(profile) = self.profile.as_mut() {
+            profile.retire(pc, inst, self.ip as u32);
+        }
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.retire(pc, inst, self.ip as u32);
+        }
+    }
+    #[inline]
+    fn jump(&mut self, target: i32) -> Result<(), Trap> {
+        // Compressed instructions make every even address a valid target
+        if target & 1 != 0 {
+            return Err(Trap::new(trap::INST_MISALIGNED, target as u32));
+        }
+        self.ip = target;
+        Ok(())
+    }
+    // `len` is 2 for instructions that were compressed, 4 otherwise
+    fn execute(&mut self, inst: Inst32, len: i32) -> Result<(), Trap> {
+        let illegal = Trap::illegal(inst.data as u32);
+        let rd = inst.rd() as usize;
+        let a = self.get_reg(inst.r1() as usize);
+        let b = self.get_reg(inst.r2() as usize);
+        match inst.opcode() {
+            ops::LUI_OP      => self.set_reg(rd, inst.imm_U() << 12),
+            ops::AUIPC_OP    => self.set_reg(rd, self.ip.wrapping_add(inst.imm_U() << 12)),
+            ops::IMM_MATH_OP => {
+                let imm = inst.imm_I();
+                let shamt = imm & 0b11111;
+                let v = match (inst.funct3(), inst.funct7()) {
+                    (imm_math::ADDI , _) => a.wrapping_add(imm),
+                    (imm_math::SLTI , _) => (a < imm) as i32,
+                    (imm_math::SLTIU, _) => ((a as u32) < (imm as u32)) as i32,
+                    (imm_math::XORI , _) => a ^ imm,
+                    (imm_math::ORI  , _) => a | imm,
+                    (imm_math::ANDI , _) => a & imm,
+                    (imm_math::SLLI , 0) => a << shamt,
+                    (imm_math::SRLI , 0) => ((a as u32) >> shamt) as i32,
+                    (imm_math::SRLI , imm_math::ARITH_SHIFT) => a >> shamt,
+                    _ => return Err(illegal),
+                };
+                self.set_reg(rd, v);
+            }
+            ops::REG_MATH_OP => {
+                let v = match (inst.funct3(), inst.funct7()) {
+                    reg_math::ADD  => a.wrapping_add(b),
+                    reg_math::SUB  => a.wrapping_sub(b),
+                    reg_math::SLL  => a << (b & 0b11111),
+                    reg_math::SLT  => (a < b) as i32,
+                    reg_math::SLTU => ((a as u32) < (b as u32)) as i32,
+                    reg_math::XOR  => a ^ b,
+                    reg_math::SRL  => ((a as u32) >> (b & 0b11111)) as i32,
+                    reg_math::SRA  => a >> (b & 0b11111),
+                    reg_math::OR   => a | b,
+                    reg_math::AND  => a & b,
+                    reg_math::MUL    => a.wrapping_mul(b),
+                    reg_math::MULH   => ((a as i64 * b as i64) >> 32) as i32,
+                    reg_math::MULHSU => ((a as i64 * b as u32 as i64) >> 32) as i32,
+                    reg_math::MULHU  => ((a as u32 as u64 * b as u32 as u64) >> 32) as i32,
+                    // Division by zero and overflow don't trap, see Table 13.1
+                    reg_math::DIV  => if b == 0 { -1 } else { a.wrapping_div(b) },
+                    reg_math::DIVU => if b == 0 { -1 } else { ((a as u32) / (b as u32)) as i32 },
+                    reg_math::REM  => if b == 0 { a } else { a.wrapping_rem(b) },
+                    reg_math::REMU => if b == 0 { a } else { ((a as u32) % (b as u32)) as i32 },
+                    _ => return Err(illegal),
+                };
+                self.set_reg(rd, v);
+            }
+            ops::STORE_OP => {
+                let addr = a.wrapping_add(inst.imm_S()) as u32;
+                match inst.funct3() {
+                    store::SB => self.store(addr, &[b as u8])?,
+                    store::SH => self.store(addr, &(b as u16).to_le_bytes())?,
+                    store::SW => self.store(addr, &b.to_le_bytes())?,
+                    _ => return Err(illegal),
+                }
+            }
+            ops::JUMP_OP => {
+                let link = self.ip.wrapping_add(len);
+                self.jump(self.ip.wrapping_add(inst.imm_J()))?;
+                self.set_reg(rd, link);
+                if inst.imm_J() == 0 {
+                    self.idle(self.enabled_interrupts());
+                }
+                return Ok(());
+            }
+            ops::JUMP_REG_OP => {
+                if inst.funct3() != jump_reg::JALR { return Err(illegal); }
+                let link = self.ip.wrapping_add(len);
+                // The lowest bit of the target is always cleared
+                self.jump(a.wrapping_add(inst.imm_I()) & !1)?;
+                self.set_reg(rd, link);
+                return Ok(());
+            }
+            ops::LOAD_OP => {
+                let addr = a.wrapping_add(inst.imm_I()) as u32;
+                let v = match inst.funct3() {
+                    load::LB => {
+                        let mut data = [0; 1];
+                        self.load(addr, &mut data)?;
+                        data[0] as i8 as i32
                     }
-                    ops::REG_MATH_OP => {
-                        match (inst.funct3(), inst.funct7()) {
-                            reg_math::ADD => {
-                                self.set_reg(inst.rd() as usize, self.get_reg(inst.r1() as usize) + self.get_reg(inst.r2() as usize));
-                            }
-                            (funct3, funct7) => {
-                                panic!("Register math op funct3=0x{:01X} funct7=0x{:02X}", funct3, funct7)
-                            }
-                        }
+                    load::LH => {
+                        let mut data = [0; 2];
+                        self.load(addr, &mut data)?;
+                        i16::from_le_bytes(data) as i32
                     }
-                    ops::STORE_OP => {
-                        match inst.funct3() {
-                            store::SB => {
-                                let v = (self.get_reg(inst.r2() as usize) & 0xFF) as u8;
-                                self.write((self.get_reg(inst.r1() as usize) + inst.imm_S()) as u32 as usize, &[v]);
-                            }
-                            store::SH => {
-                                let v = (self.get_reg(inst.r2() as usize) & 0xFFFF) as u16;
-                                self.write((self.get_reg(inst.r1() as usize) + inst.imm_S()) as u32 as usize, &v.to_le_bytes());
-                            }
-                            store::SW => {
-                                let v = self.get_reg(inst.r2() as usize);
-                                self.write((self.get_reg(inst.r1() as usize) + inst.imm_S()) as u32 as usize, &v.to_le_bytes());
-                            }
-                            funct3 => {
-                                todo!("Unhandled store op funct3=0x{:01X}",funct3);
-                            }
-                        }
+                    load::LW => {
+                        let mut data = [0; 4];
+                        self.load(addr, &mut data)?;
+                        i32::from_le_bytes(data)
                     }
-                    ops::JUMP_OP => {
-                        self.set_reg(inst.rd() as usize, self.ip + 4);
-                        self.ip += inst.imm_J();
-                        return;
+                    load::LBU => {
+                        let mut data = [0; 1];
+                        self.load(addr, &mut data)?;
+                        data[0] as i32
                     }
-                    ops::JUMP_REG_OP => {
-                        self.set_reg(inst.rd() as usize, self.ip + 4);
-                        self.ip = self.get_reg(inst.r1() as usize) + inst.imm_I();
-                        return;
+                    load::LHU => {
+                        let mut data = [0; 2];
+                        self.load(addr, &mut data)?;
+                        u16::from_le_bytes(data) as i32
                     }
-                    ops::LOAD_OP => {
-                        match inst.funct3() {
-                            load::LW => {
-                                let v = self.get_reg(inst.r1() as usize);
-                                let mut data = [0; 4];
-                                self.read((v+inst.imm_I()) as usize, &mut data);
-                                self.set_reg(inst.rd() as usize, i32::from_le_bytes(data));
-                            }
-                            load::LBU => {
-                                let v = self.get_reg(inst.r1() as usize);
-                                let mut data = [0; 4];
-                                self.read((v+inst.imm_I()) as usize, &mut data);
-                                self.set_reg(inst.rd() as usize, (u32::from_le_bytes(data) as u8) as i32);
-                            }
-                            funct3 => todo!("load op with funct3={:01X}", funct3)
+                    _ => return Err(illegal),
+                };
+                self.set_reg(rd, v);
+            }
+            ops::BRANCH_OP => {
+                let taken = match inst.funct3() {
+                    branch::BEQ  => a == b,
+                    branch::BNE  => a != b,
+                    branch::BLT  => a < b,
+                    branch::BGE  => a >= b,
+                    branch::BLTU => (a as u32) < (b as u32),
+                    branch::BGEU => (a as u32) >= (b as u32),
+                    _ => return Err(illegal),
+                };
+                if taken {
+                    return self.jump(self.ip.wrapping_add(inst.imm_B()));
+                }
+            }
+            ops::AMO_OP => {
+                if inst.funct3() != amo::W { return Err(illegal); }
+                let addr = a as u32;
+                // Unlike plain loads and stores, atomics have to be naturally aligned
+                let misaligned = |cause| Trap::new(cause, addr);
+                match inst.funct7() >> 2 {
+                    amo::LR => {
+                        if inst.r2() != 0 { return Err(illegal); }
+                        if addr & 0b11 != 0 { return Err(misaligned(trap::LOAD_MISALIGNED)); }
+                        let mut data = [0; 4];
+                        self.load(addr, &mut data)?;
+                        self.reservation = Some(addr);
+                        self.set_reg(rd, i32::from_le_bytes(data));
+                    }
+                    amo::SC => {
+                        if addr & 0b11 != 0 { return Err(misaligned(trap::STORE_MISALIGNED)); }
+                        // Any SC gives up the reservation, whether it succeeds or not
+                        let reserved = self.reservation.take() == Some(addr);
+                        if reserved {
+                            self.store(addr, &b.to_le_bytes())?;
                         }
+                        self.set_reg(rd, !reserved as i32);
                     }
-                    ops::BRANCH_OP => {
-                        match inst.funct3() {
-                            branch::BEQ => {
-                                if self.get_reg(inst.r1() as usize) == self.get_reg(inst.r2() as usize) {
-                                    self.ip += inst.imm_B();
-                                    return;
+                    funct5 => {
+                        if addr & 0b11 != 0 { return Err(misaligned(trap::STORE_MISALIGNED)); }
+                        // Faults are reported as store faults, so check for write access first
+                        self.translate_span(addr, 4, Access::Write)?;
+                        let mut data = [0; 4];
+                        self.load(addr, &mut data)?;
+                        let old = i32::from_le_bytes(data);
+                        let v = match funct5 {
+                            amo::SWAP => b,
+                            amo::ADD  => old.wrapping_add(b),
+                            amo::XOR  => old ^ b,
+                            amo::AND  => old & b,
+                            amo::OR   => old | b,
+                            amo::MIN  => old.min(b),
+                            amo::MAX  => old.max(b),
+                            amo::MINU => (old as u32).min(b as u32) as i32,
+                            amo::MAXU => (old as u32).max(b as u32) as i32,
+                            _ => return Err(illegal),
+                        };
+                        self.store(addr, &v.to_le_bytes())?;
+                        self.set_reg(rd, old);
+                    }
+                }
+            }
+            ops::MISC_MEM_OP => {
+                // There is a single hart and no caches that could go stale
+                match inst.funct3() {
+                    misc_mem::FENCE | misc_mem::FENCE_I => {}
+                    _ => return Err(illegal),
+                }
+            }
+            ops::SYSTEM_OP => {
+                match inst.funct3() {
+                    system::PRIV if inst.funct7() == system::SFENCE_VMA && inst.rd() == 0 => {
+                        if self.mode == Mode::User || (self.mode == Mode::Supervisor && self.csr.mstatus & mstatus::TVM != 0) {
+                            return Err(illegal);
+                        }
+                        // NOTE: ASIDs aren't implemented, so rs2 is ignored
+                        self.tlb.flush(if inst.r1() != 0 { Some(a as u32) } else { None });
+                    }
+                    system::PRIV => {
+                        if inst.rd() != 0 || inst.r1() != 0 { return Err(illegal); }
+                        match inst.funct12() {
+                            system::ECALL if self.sbi && self.mode == Mode::Supervisor => {
+                                if let Some(target) = sbi::call(self) {
+                                    self.ip = target as i32;
+                                    return Ok(());
                                 }
                             }
-                            branch::BNE => {
-                                if self.get_reg(inst.r1() as usize) != self.get_reg(inst.r2() as usize) {
-                                    self.ip += inst.imm_B();
-                                    return;
-                                }
+                            system::ECALL if self.process.is_some() => linux::syscall(self),
+                            system::ECALL => return Err(Trap::new(trap::ECALL_U + self.mode as u32, 0)),
+                            system::EBREAK if self.semihost.is_some() && semihost::is_call(self) => semihost::call(self),
+                            system::EBREAK => return Err(Trap::new(trap::BREAKPOINT, self.ip as u32)),
+                            system::MRET => {
+                                if self.mode != Mode::Machine { return Err(illegal); }
+                                let status = self.csr.mstatus;
+                                let mode = Mode::from_bits(status >> mstatus::MPP_SHIFT);
+                                let mut status = status & !(mstatus::MIE | mstatus::MPP);
+                                if status & mstatus::MPIE != 0 { status |= mstatus::MIE; }
+                                status |= mstatus::MPIE;
+                                if mode != Mode::Machine { status &= !mstatus::MPRV; }
+                                self.csr.mstatus = status;
+                                self.mode = mode;
+                                self.ip = self.csr.mepc as i32;
+                                return Ok(());
                             }
-                            branch::BGE => {
-                                if self.get_reg(inst.r1() as usize) >= self.get_reg(inst.r2() as usize) {
-                                    self.ip += inst.imm_B();
-                                    return;
+                            system::SRET => {
+                                if self.mode == Mode::User || (self.mode == Mode::Supervisor && self.csr.mstatus & mstatus::TSR != 0) {
+                                    return Err(illegal);
                                 }
+                                let status = self.csr.mstatus;
+                                let mode = if status & mstatus::SPP != 0 { Mode::Supervisor } else { Mode::User };
+                                let mut status = status & !(mstatus::SIE | mstatus::SPP | mstatus::MPRV);
+                                if status & mstatus::SPIE != 0 { status |= mstatus::SIE; }
+                                status |= mstatus::SPIE;
+                                self.csr.mstatus = status;
+                                self.mode = mode;
+                                self.ip = self.csr.sepc as i32;
+                                return Ok(());
                             }
-                            funct3 => {
-                                todo!("branch op funct3=0x{:01X}",funct3)
+                            system::WFI => {
+                                if self.mode == Mode::User || (self.mode == Mode::Supervisor && self.csr.mstatus & mstatus::TW != 0) {
+                                    return Err(illegal);
+                                }
+                                // Wakes on any locally enabled interrupt, even with MIE/SIE clear
+                                self.idle(self.csr.mie);
+                                if self.halt.is_some() { return Ok(()); }
                             }
+                            _ => return Err(illegal),
                         }
                     }
-                    op => todo!("op={:07b}",op)
+                    funct3 => self.csr_op(inst, funct3)?,
+                }
+            }
+            _ => return Err(illegal),
+        }
+        self.ip = self.ip.wrapping_add(len);
+        Ok(())
+    }
+    // Enters the trap handler, in S-mode if the trap is delegated and we're not in M-mode
+    fn take_trap(&mut self, trap: Trap) {
+        // There's no kernel to go to
+        if self.process.as_ref().is_some_and(|x| x.abi == Abi::Linux) {
+            return linux::fault(self, trap);
+        }
+        let pc = self.ip as u32;
+        let status = self.csr.mstatus;
+        let code = trap.cause & !trap::INTERRUPT;
+        let deleg = if trap.is_interrupt() { self.csr.mideleg } else { self.csr.medeleg };
+        // Vectored mode sends interrupts to base + 4*cause
+        let target = |tvec: u32| {
+            let base = tvec & !0b11;
+            if tvec & 1 != 0 && trap.is_interrupt() { base.wrapping_add(4*code) } else { base }
+        };
+        if self.mode <= Mode::Supervisor && (deleg >> code) & 1 != 0 {
+            self.csr.scause = trap.cause;
+            self.csr.sepc = pc;
+            self.csr.stval = trap.tval;
+            let mut status = status & !(mstatus::SPP | mstatus::SPIE | mstatus::SIE);
+            if self.mode == Mode::Supervisor { status |= mstatus::SPP; }
+            if self.csr.mstatus & mstatus::SIE != 0 { status |= mstatus::SPIE; }
+            self.csr.mstatus = status;
+            self.mode = Mode::Supervisor;
+            self.ip = target(self.csr.stvec) as i32;
+        } else {
+            self.csr.mcause = trap.cause;
+            self.csr.mepc = pc;
+            self.csr.mtval = trap.tval;
+            let mut status = status & !(mstatus::MPP | mstatus::MPIE | mstatus::MIE);
+            status |= (self.mode as u32) << mstatus::MPP_SHIFT;
+            if self.csr.mstatus & mstatus::MIE != 0 { status |= mstatus::MPIE; }
+            self.csr.mstatus = status;
+            self.mode = Mode::Machine;
+            self.ip = target(self.csr.mtvec) as i32;
+        }
+    }
+    fn read_csr(&self, csr: u32) -> Option<u32> {
+        let c = &self.csr;
+        Some(match csr {
+            csr::CYCLE | csr::MCYCLE => self.cycles as u32,
+            csr::CYCLEH | csr::MCYCLEH => (self.cycles >> 32) as u32,
+            csr::TIME => self.time.mtime() as u32,
+            csr::TIMEH => (self.time.mtime() >> 32) as u32,
+            csr::INSTRET | csr::MINSTRET => self.instret as u32,
+            csr::INSTRETH | csr::MINSTRETH => (self.instret >> 32) as u32,
+            csr::SSTATUS => c.mstatus & mstatus::SSTATUS_MASK,
+            csr::SIE => c.mie & c.mideleg,
+            csr::STVEC => c.stvec,
+            csr::SCOUNTEREN => c.scounteren,
+            csr::SSCRATCH => c.sscratch,
+            csr::SEPC => c.sepc,
+            csr::SCAUSE => c.scause,
+            csr::STVAL => c.stval,
+            csr::SIP => self.mip() & c.mideleg,
+            csr::SATP => c.satp,
+            csr::MVENDORID | csr::MARCHID | csr::MIMPID | csr::MHARTID => 0,
+            csr::MSTATUS => c.mstatus,
+            csr::MSTATUSH => 0,
+            csr::MISA => csr::MISA_VALUE,
+            csr::MEDELEG => c.medeleg,
+            csr::MIDELEG => c.mideleg,
+            csr::MIE => c.mie,
+            csr::MTVEC => c.mtvec,
+            csr::MCOUNTEREN => c.mcounteren,
+            csr::MSCRATCH => c.mscratch,
+            csr::MEPC => c.mepc,
+            csr::MCAUSE => c.mcause,
+            csr::MTVAL => c.mtval,
+            csr::MIP => self.mip(),
+            csr::PMPCFG0..=csr::PMPCFG3 => self.pmp.read_cfg((csr - csr::PMPCFG0) as usize),
+            csr::PMPADDR0..=csr::PMPADDR15 => self.pmp.addr[(csr - csr::PMPADDR0) as usize],
+            _ => return None,
+        })
+    }
+    // Only fields that are implemented are writable, the rest read back as zero
+    fn write_csr(&mut self, csr: u32, v: u32) -> Option<()> {
+        let c = &mut self.csr;
+        match csr {
+            csr::MCYCLE => self.cycles = (self.cycles & !0xFFFFFFFF) | v as u64,
+            csr::MCYCLEH => self.cycles = (self.cycles & 0xFFFFFFFF) | ((v as u64) << 32),
+            csr::MINSTRET => self.instret = (self.instret & !0xFFFFFFFF) | v as u64,
+            csr::MINSTRETH => self.instret = (self.instret & 0xFFFFFFFF) | ((v as u64) << 32),
+            csr::SSTATUS => c.mstatus = (c.mstatus & !mstatus::SSTATUS_MASK) | (v & mstatus::SSTATUS_MASK),
+            csr::SIE => c.mie = (c.mie & !c.mideleg) | (v & c.mideleg),
+            // Only direct (0) and vectored (1) modes exist
+            csr::STVEC => c.stvec = v & !0b10,
+            csr::SCOUNTEREN => c.scounteren = v & 0b111,
+            csr::SSCRATCH => c.sscratch = v,
+            csr::SEPC => c.sepc = v & !1,
+            csr::SCAUSE => c.scause = v,
+            csr::STVAL => c.stval = v,
+            csr::SIP => c.mip = (c.mip & !(irq::SSI & c.mideleg)) | (v & irq::SSI & c.mideleg),
+            csr::SATP => c.satp = v & mmu::SATP_MASK,
+            csr::MSTATUS => {
+                let mut v = v & mstatus::WRITE_MASK;
+                // MPP=2 is reserved, keep the previous mode
+                if (v & mstatus::MPP) >> mstatus::MPP_SHIFT == 2 {
+                    v = (v & !mstatus::MPP) | (c.mstatus & mstatus::MPP);
                 }
+                c.mstatus = v;
             }
-            _ => panic!("Unsupported {} bit Instruction",len*16),
+            // Little endian only, so all of mstatush is hardwired to zero
+            csr::MSTATUSH => {}
+            // None of the extensions can be turned off
+            csr::MISA => {}
+            csr::MEDELEG => c.medeleg = v & csr::MEDELEG_MASK,
+            csr::MIDELEG => c.mideleg = v & irq::S_MASK,
+            csr::MIE => c.mie = v & irq::ALL,
+            csr::MTVEC => c.mtvec = v & !0b10,
+            csr::MCOUNTEREN => c.mcounteren = v & 0b111,
+            csr::MSCRATCH => c.mscratch = v,
+            csr::MEPC => c.mepc = v & !1,
+            csr::MCAUSE => c.mcause = v,
+            csr::MTVAL => c.mtval = v,
+            // The M-level bits are driven by devices
+            csr::MIP => c.mip = (c.mip & !irq::S_MASK) | (v & irq::S_MASK),
+            csr::PMPCFG0..=csr::PMPCFG3 => self.pmp.write_cfg((csr - csr::PMPCFG0) as usize, v),
+            csr::PMPADDR0..=csr::PMPADDR15 => self.pmp.write_addr((csr - csr::PMPADDR0) as usize, v),
+            _ => return None,
+        }
+        Some(())
+    }
+    fn csr_allowed(&self, csr: u32, write: bool) -> bool {
+        if self.mode < csr::min_mode(csr) || (write && csr::is_read_only(csr)) {
+            return false;
+        }
+        if csr::is_user_counter(csr) {
+            let bit = 1 << (csr & 0b11111);
+            if self.mode < Mode::Machine && self.csr.mcounteren & bit == 0 { return false; }
+            if self.mode < Mode::Supervisor && self.csr.scounteren & bit == 0 { return false; }
+        }
+        !(csr == csr::SATP && self.mode == Mode::Supervisor && self.csr.mstatus & mstatus::TVM != 0)
+    }
+    fn csr_op(&mut self, inst: Inst32, funct3: i32) -> Result<(), Trap> {
+        let illegal = Trap::illegal(inst.data as u32);
+        let csr = inst.funct12() as u32;
+        // The immediate forms encode a 5 bit zero extended immediate in place of r1
+        let src = match funct3 {
+            system::CSRRWI | system::CSRRSI | system::CSRRCI => inst.r1() as u32,
+            system::CSRRW | system::CSRRS | system::CSRRC => self.get_reg(inst.r1() as usize) as u32,
+            _ => return Err(illegal),
+        };
+        let write = matches!(funct3, system::CSRRW | system::CSRRWI) || inst.r1() != 0;
+        if !self.csr_allowed(csr, write) {
+            return Err(illegal);
+        }
+        let old = self.read_csr(csr).ok_or(illegal)?;
+        if write {
+            // Set and clear on mip/sip work on the software writable bits, so a SEIP
+            // raised by the PLIC doesn't get latched
+            let old = if csr == csr::MIP || csr == csr::SIP { self.csr.mip } else { old };
+            let new = match funct3 {
+                system::CSRRW | system::CSRRWI => src,
+                system::CSRRS | system::CSRRSI => old | src,
+                _ => old & !src,
+            };
+            self.write_csr(csr, new).ok_or(illegal)?;
+        }
+        self.set_reg(inst.rd() as usize, old as i32);
+        Ok(())
+    }
+    // The hart is stuck until one of the `wake` interrupts comes in.
+    // The first device that can raise one gets to wait for it, the CLINT by skipping time ahead
+    // and the UART on host input, otherwise nothing ever will.
+    pub fn idle(&mut self, wake: u32) {
+        if self.mip() & wake != 0 { return; }
+        // What the devices see, before the SBI forwards MSIP and MTIP
+        let mut lines = wake;
+        if self.sbi { lines |= (wake & (irq::SSI | irq::STI)) << 2; }
+        for i in 0..self.regions.0.len() {
+            if self.with_device(i, |device, vm| device.wait(vm, lines)) { return; }
+        }
+        // A command may be waiting in tohost
+        htif::poll(self);
+        if self.halt.is_some() { return; }
+        self.halt = Some(Halt::Idle);
+    }
+    /// Runs until the guest halts or `steps` steps have gone by. Returns why it halted.
+    pub fn run_for(&mut self, steps: u64) -> Option<Halt> {
+        for _ in 0..steps {
+            if self.halt.is_some() { break; }
+            self.run();
         }
-        self.ip += (len * 2) as i32;
+        self.halt
     }
     pub fn next(&mut self) {
         self.run();
-        // self.ip += (self.disasm(self.ip())*2) as u32;
     }
 }
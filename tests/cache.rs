@@ -0,0 +1,55 @@
+use riscv_vm::{Builder, CacheSim, Halt, Machine, VM};
+
+// NOTE: The data cache model as a guest sees it. A single set of two 16 byte lines,
+// so the third line a program touches always has to push one out.
+// The programs end in a self-loop rather than the exit port, which would count as a store.
+const L1D: &str = "l1d=32/2/16";
+
+// hits, misses, evictions and writebacks of the whole L1D
+fn l1d(vm: &VM) -> [u64; 4] {
+    let mut out = Vec::new();
+    vm.cache.as_ref().unwrap().report(&mut out, &vm.symbols).unwrap();
+    let report = String::from_utf8(out).unwrap();
+    let row = report.lines().find(|x| x.trim_start().starts_with("l1d ")).unwrap();
+    let stats: Vec<u64> = row.split_whitespace().skip(1).take(4).map(|x| x.parse().unwrap()).collect();
+    stats.try_into().unwrap()
+}
+fn run(spec: &str, code: &[u32]) -> [u64; 4] {
+    let image: Vec<u8> = code.iter().flat_map(|x| x.to_le_bytes()).collect();
+    let mut vm = Builder::new(Machine::Simple)
+        .cache(CacheSim::parse(spec).unwrap())
+        .load(0, &image)
+        .build()
+        .unwrap();
+    assert_eq!(vm.run_for(100), Some(Halt::Idle));
+    l1d(&vm)
+}
+
+// lui t0, 0x1; lw a0 from A, B, A, C, A where A=0(t0), B=16(t0), C=32(t0); j .
+const REUSE: [u32; 7] = [0x000012b7, 0x0002a503, 0x0102a503, 0x0002a503, 0x0202a503, 0x0002a503, 0x0000006f];
+// lui t0, 0x1; sw zero to A; lw a0 from A, B, C; j .
+const STORE: [u32; 6] = [0x000012b7, 0x0002a023, 0x0002a503, 0x0102a503, 0x0202a503, 0x0000006f];
+
+#[test]
+fn lru() {
+    // C pushes out B, the line used longest ago, so A keeps hitting
+    assert_eq!(run(&format!("{}/lru", L1D), &REUSE), [2, 3, 1, 0]);
+}
+
+#[test]
+fn fifo() {
+    // C pushes out A, the line filled first, and A coming back pushes out B
+    assert_eq!(run(&format!("{}/fifo", L1D), &REUSE), [1, 4, 2, 0]);
+}
+
+#[test]
+fn write_back() {
+    // The store allocates A dirty, so the load hits and C pushing A out writes it back
+    assert_eq!(run(&format!("{}/lru/wb", L1D), &STORE), [1, 3, 1, 1]);
+}
+
+#[test]
+fn write_through() {
+    // The store goes past the cache without allocating, nothing is ever dirty
+    assert_eq!(run(&format!("{}/lru/wt", L1D), &STORE), [0, 4, 1, 0]);
+}
@@ -0,0 +1,38 @@
+use riscv_vm::{Builder, Halt, Machine};
+
+// NOTE: The CLINT's timer as a guest on the virt board uses it. mtime counts retired
+// instructions, so the interrupt has to wait until the guest has run past mtimecmp.
+// The handler reports mcause through the test finisher, mtime in a1.
+const MTIMECMP: u32 = 1000;
+const MTIP: u32 = 1 << 7;
+const TIMER_INTERRUPT: u32 = 0x8000_0007;
+
+//  la t0, handler; csrw mtvec, t0
+//  lui t0, 0x2004; li t1, 1000; sw t1, 0(t0); sw zero, 4(t0), mtimecmp
+//  li t1, 0x80; csrs mie, t1; csrsi mstatus, 8, MTIE and MIE
+//  loop: nop; j loop
+//  handler: csrr a0, mcause; lui t0, 0x200c; lw a1, -8(t0), mtime
+//  andi a2, a0, 0xff; slli a2, a2, 16; li t1, 0x3333; or t1, t1, a2; lui t2, 0x100; sw t1, 0(t2)
+const CODE: [u32; 22] = [
+    0x00000297, 0x03028293, 0x30529073,
+    0x020042b7, 0x3e800313, 0x0062a023, 0x0002a223,
+    0x08000313, 0x30432073, 0x30046073,
+    0x00000013, 0xffdff06f,
+    0x34202573, 0x0200c2b7, 0xff82a583,
+    0x0ff57613, 0x01061613, 0x00003337, 0x33330313, 0x00c36333, 0x001003b7, 0x0063a023,
+];
+
+#[test]
+fn mtimecmp() {
+    let image: Vec<u8> = CODE.iter().flat_map(|x| x.to_le_bytes()).collect();
+    let mut vm = Builder::new(Machine::Virt).load(0x8000_0000, &image).build().unwrap();
+    // Half way there nothing is pending yet
+    assert_eq!(vm.run_for(MTIMECMP as u64 / 2), None);
+    assert_eq!(vm.mip() & MTIP, 0);
+    assert_eq!(vm.get_reg(10), 0);
+
+    assert_eq!(vm.run_for(2 * MTIMECMP as u64), Some(Halt::Exit(7)));
+    assert_eq!(vm.get_reg(10) as u32, TIMER_INTERRUPT);
+    assert!(vm.get_reg(11) as u32 >= MTIMECMP);
+    assert_ne!(vm.mip() & MTIP, 0);
+}
@@ -0,0 +1,84 @@
+use riscv_vm::{Builder, Device, Halt, Kind, Machine, Region, VM};
+
+// NOTE: A peripheral model from outside the crate, the way an embedder would write one.
+// A word of scratch space at 0 and a count of the writes to it at 4.
+const MAILBOX: usize = 0x4000_0000;
+
+#[derive(Default)]
+struct Mailbox {
+    word: [u8; 4],
+    writes: u32,
+    ticks: u32,
+}
+impl Device for Mailbox {
+    fn read(&mut self, _: &mut VM, off: usize, bytes: &mut [u8]) -> Result<(), ()> {
+        let regs = [self.word, self.writes.to_le_bytes()].concat();
+        bytes.copy_from_slice(regs.get(off..off+bytes.len()).ok_or(())?);
+        Ok(())
+    }
+    fn write(&mut self, _: &mut VM, off: usize, bytes: &[u8]) -> Result<(), ()> {
+        self.word.get_mut(off..off+bytes.len()).ok_or(())?.copy_from_slice(bytes);
+        self.writes += 1;
+        Ok(())
+    }
+    fn tick(&mut self, _: &mut VM) {
+        self.ticks += 1;
+    }
+    fn reset(&mut self, _: &mut VM) {
+        *self = Self::default();
+    }
+    fn snapshot(&self, _: &VM) -> Vec<u8> {
+        [self.word, self.writes.to_le_bytes(), self.ticks.to_le_bytes()].concat()
+    }
+}
+
+fn mailbox(vm: &VM) -> Vec<u8> {
+    let i = vm.regions.0.iter().position(|x| x.addr == MAILBOX).unwrap();
+    vm.snapshot_devices().swap_remove(i)
+}
+
+#[test]
+fn custom_device() {
+    // lui t0, 0x40000; li a0, 0x12345678; sw a0, 0(t0) twice; lw a1, 0(t0); lw a2, 4(t0)
+    // then exits with the write count: lui t1, 0x7; sb a2, 0(t1)
+    let code = [0x400002b7u32, 0x12345537, 0x67850513, 0x00a2a023, 0x00a2a023, 0x0002a583, 0x0042a603, 0x00007337, 0x00c30023];
+    let image: Vec<u8> = code.iter().flat_map(|x| x.to_le_bytes()).collect();
+    let mut vm = Builder::new(Machine::Simple)
+        .region(Region { device: Box::new(Mailbox::default()), addr: MAILBOX, size: 8, latency: 0 })
+        .load(0, &image)
+        .build()
+        .unwrap();
+    assert_eq!(vm.run_for(100), Some(Halt::Exit(2)));
+    assert_eq!(vm.get_reg(11), 0x12345678);
+    assert!(vm.regions.find_region(MAILBOX).is_some_and(|x| x.device.kind() == Kind::Other));
+
+    // The state stays with the device, ticks included
+    let state = mailbox(&vm);
+    assert_eq!(state[..8], [0x78, 0x56, 0x34, 0x12, 2, 0, 0, 0]);
+    assert!(state[8] > 0);
+    vm.reset_devices();
+    assert_eq!(mailbox(&vm), [0; 12]);
+}
+
+#[test]
+fn overlapping_device() {
+    let region = Region { device: Box::new(Mailbox::default()), addr: 0x1000, size: 8, latency: 0 };
+    assert!(Builder::new(Machine::Simple).region(region).build().is_err());
+}
+
+#[test]
+fn device_error_traps() {
+    // The region is bigger than the mailbox so the load at 8 reaches it and it turns the access down.
+    // la t2, handler; csrw mtvec, t2; lui t0, 0x40000; lw a1, 8(t0)
+    // handler: csrr a0, mcause; csrr a1, mtval; lui t1, 0x7; sb a0, 0(t1)
+    let code = [0x00000397u32, 0x01438393, 0x30539073, 0x400002b7, 0x0082a583, 0x34202573, 0x343025f3, 0x00007337, 0x00a30023];
+    let image: Vec<u8> = code.iter().flat_map(|x| x.to_le_bytes()).collect();
+    let mut vm = Builder::new(Machine::Simple)
+        .region(Region { device: Box::new(Mailbox::default()), addr: MAILBOX, size: 16, latency: 0 })
+        .load(0, &image)
+        .build()
+        .unwrap();
+    // Load access fault with the address in mtval
+    assert_eq!(vm.run_for(100), Some(Halt::Exit(5)));
+    assert_eq!(vm.get_reg(11) as usize, MAILBOX + 8);
+}
@@ -0,0 +1,46 @@
+use std::{env, fs};
+use riscv_vm::{Builder, Halt, Machine};
+
+// NOTE: System calls proxied through HTIF the way riscv-pk's frontend takes them.
+// tohost is at 0x1000 and fromhost at 0x1008, the guest fills the eight 64-bit words of
+// its request at 0x1040 and points tohost at them. The file name goes at 0x1100.
+const TOHOST: u32 = 0x1000;
+const FROMHOST: u32 = 0x1008;
+const NAME: usize = 0x1100;
+
+//  lui s0, 0x1; addi s1, s0, 0x40
+//  request openat(AT_FDCWD, 0x1100, O_RDONLY): li t0, 56; sw t0, 0(s1); li t0, -100; sw t0, 8(s1); addi t0, s0, 0x100; sw t0, 16(s1)
+//  sw s1, 0(s0); sw zero, 4(s0); lw s2, 0(s1), the fd; lw s3, 8(s0), fromhost
+//  request read(s2, 0x1200, 4): li t0, 63; sw t0, 0(s1); sw s2, 8(s1); addi t0, s0, 0x200; sw t0, 16(s1); li t0, 4; sw t0, 24(s1)
+//  sw s1, 0(s0); sw zero, 4(s0); lw s4, 0(s1), the count; addi t0, s0, 0x200; lw s5, 0(t0)
+//  exit(3): li t0, 7; sw t0, 0(s0); sw zero, 4(s0)
+const CODE: [u32; 27] = [
+    0x00001437, 0x04040493, 0x03800293, 0x0054a023, 0xf9c00293, 0x0054a423, 0x10040293, 0x0054a823,
+    0x00942023, 0x00042223, 0x0004a903, 0x00842983, 0x03f00293, 0x0054a023, 0x0124a423, 0x20040293,
+    0x0054a823, 0x00400293, 0x0054ac23, 0x00942023, 0x00042223, 0x0004aa03, 0x20040293, 0x0002aa83,
+    0x00700293, 0x00542023, 0x00042223,
+];
+
+#[test]
+fn syscall_proxy() {
+    let path = env::temp_dir().join(format!("riscv_vm_htif_{}", std::process::id()));
+    fs::write(&path, b"RV32").unwrap();
+    let code: Vec<u8> = CODE.iter().flat_map(|x| x.to_le_bytes()).collect();
+    let mut vm = Builder::new(Machine::Simple)
+        .htif(TOHOST, Some(FROMHOST))
+        .load(0, &code)
+        .load(NAME, path.to_str().unwrap().as_bytes())
+        .build()
+        .unwrap();
+    let halt = vm.run_for(1000);
+    let _ = fs::remove_file(&path);
+    assert_eq!(halt, Some(Halt::Exit(3)));
+
+    assert!(vm.get_reg(18) >= 3);
+    // The frontend acknowledges each request with 1 in fromhost
+    assert_eq!(vm.get_reg(19), 1);
+    assert_eq!(vm.get_reg(20), 4);
+    assert_eq!(vm.get_reg(21).to_le_bytes(), *b"RV32");
+    // and takes tohost back once it has the command
+    assert_eq!(vm.memory(TOHOST as usize, 8).unwrap(), [0; 8]);
+}
@@ -0,0 +1,80 @@
+use std::{env, fs};
+use riscv_vm::{Builder, Halt, Machine, VM};
+
+// NOTE: A -muser process, which has to be an ELF, so the test wraps its code in the smallest
+// one that loads: the header, one PT_LOAD over the whole file and the code right after them.
+const BASE: u32 = 0x10000;
+const HEADERS: usize = 52 + 32;
+const ENOSYS: i32 = 38;
+const EINVAL: i32 = 22;
+
+fn elf(code: &[u32]) -> Vec<u8> {
+    let size = (HEADERS + code.len()*4) as u32;
+    let mut out = b"\x7fELF\x01\x01\x01".to_vec();
+    out.resize(16, 0);
+    // ET_EXEC, EM_RISCV, version, entry, phoff, shoff, flags
+    out.extend(2u16.to_le_bytes());
+    out.extend(243u16.to_le_bytes());
+    for word in [1, BASE + HEADERS as u32, 52, 0, 0] {
+        out.extend(word.to_le_bytes());
+    }
+    // ehsize, phentsize, phnum, shentsize, shnum, shstrndx
+    for half in [52u16, 32, 1, 40, 0, 0] {
+        out.extend(half.to_le_bytes());
+    }
+    // PT_LOAD, offset, vaddr, paddr, filesz, memsz, R|X, align
+    for word in [1, 0, BASE, BASE, size, size, 5, 0x1000] {
+        out.extend(word.to_le_bytes());
+    }
+    out.extend(code.iter().flat_map(|x| x.to_le_bytes()));
+    out
+}
+fn run(code: &[u32], args: &[&str]) -> (Option<Halt>, VM) {
+    let args: Vec<String> = args.iter().map(|x| x.to_string()).collect();
+    let mut vm = Builder::new(Machine::User)
+        .program(&args[0], args.clone())
+        .env(vec!["HOME=/".to_string()])
+        .image(&elf(code))
+        .unwrap()
+        .build()
+        .unwrap();
+    (vm.run_for(10_000), vm)
+}
+
+#[test]
+fn stack_and_syscalls() {
+    let path = env::temp_dir().join(format!("riscv_vm_linux_{}", std::process::id()));
+    fs::write(&path, b"RV32").unwrap();
+    // s0 = argc, s1 = argv[1], then past argv and envp to the auxv, s2 = AT_PAGESZ's value:
+    //  lw s0, 0(sp); lw s1, 8(sp); addi t0, s0, 2; slli t0, t0, 2; add t0, sp, t0
+    //  env: lw t1, 0(t0); addi t0, t0, 4; bnez t1, env
+    //  aux: lw t1, 0(t0); lw s2, 4(t0); addi t0, t0, 8; li t3, 6; bne t1, t3, aux
+    // s3 = openat(AT_FDCWD, argv[1], O_RDONLY, 0), s4 = read(s3, sp-16, 4), s5 = what it read, close(s3)
+    // s6 = syscall 999, s7 = rt_sigaction(SIGINT, 0, 0, 16), a sigsetsize Linux turns down, then exit_group(argc)
+    let code = [
+        0x00012403, 0x00812483, 0x00240293, 0x00229293, 0x005102b3,
+        0x0002a303, 0x00428293, 0xfe031ce3,
+        0x0002a303, 0x0042a903, 0x00828293, 0x00600e13, 0xffc318e3,
+        0xf9c00513, 0x00048593, 0x00000613, 0x00000693, 0x03800893, 0x00000073, 0x00050993,
+        0xff010113, 0x00010593, 0x00400613, 0x03f00893, 0x00000073, 0x00050a13, 0x00012a83,
+        0x00098513, 0x03900893, 0x00000073,
+        0x3e700893, 0x00000073, 0x00050b13,
+        0x00200513, 0x00000593, 0x00000613, 0x01000693, 0x08600893, 0x00000073, 0x00050b93,
+        0x00040513, 0x05e00893, 0x00000073,
+    ];
+    let (halt, mut vm) = run(&code, &["prog", path.to_str().unwrap()]);
+    let _ = fs::remove_file(&path);
+    assert_eq!(halt, Some(Halt::Exit(2)));
+
+    // argv[1] is the string the test passed
+    let argv1 = vm.get_reg(9) as usize;
+    let len = path.to_str().unwrap().len();
+    assert_eq!(vm.memory(argv1, len + 1).unwrap(), [path.to_str().unwrap().as_bytes(), &[0]].concat());
+    assert_eq!(vm.get_reg(18), 4096);
+
+    assert!(vm.get_reg(19) >= 3);
+    assert_eq!(vm.get_reg(20), 4);
+    assert_eq!(vm.get_reg(21).to_le_bytes(), *b"RV32");
+    assert_eq!(vm.get_reg(22), -ENOSYS);
+    assert_eq!(vm.get_reg(23), -EINVAL);
+}
@@ -0,0 +1,145 @@
+use riscv_vm::{Builder, Device, Machine, Region, VM};
+
+// NOTE: The UART's line into the PLIC, driven through MMIO the way a driver would.
+// The UART is in loopback, so a byte written to THR lands in its own RX FIFO and the
+// receive interrupt stays high until RBR is read, no host stdin involved.
+const PLIC: usize = 0xC000000;
+const UART: usize = 0x10000000;
+const LINE: usize = 10;
+const IER_RDI: u8 = 0x01;
+const MCR_LOOP: u8 = 0x10;
+const M: usize = 0;
+const S: usize = 1;
+// The SiFive PLIC's register map, as on QEMU's virt board
+mod plic {
+    pub const PRIORITY: usize = 0x0;
+    pub const PENDING: usize = 0x1000;
+    pub const ENABLE: usize = 0x2000;
+    pub const ENABLE_STRIDE: usize = 0x80;
+    pub const CONTEXT: usize = 0x200000;
+    pub const CONTEXT_STRIDE: usize = 0x1000;
+}
+// 16550 registers
+mod uart {
+    pub const RBR: usize = 0;
+    pub const THR: usize = 0;
+    pub const IER: usize = 1;
+    pub const MCR: usize = 4;
+}
+// mip bits
+mod irq {
+    pub const SEI: u32 = 1 << 9;
+    pub const MEI: u32 = 1 << 11;
+}
+
+fn read(vm: &mut VM, addr: usize) -> u32 {
+    let mut bytes = [0; 4];
+    vm.read(addr, &mut bytes).unwrap();
+    u32::from_le_bytes(bytes)
+}
+fn write(vm: &mut VM, addr: usize, v: u32) {
+    vm.write(addr, &v.to_le_bytes()).unwrap();
+}
+fn threshold(ctx: usize) -> usize {
+    PLIC + plic::CONTEXT + ctx*plic::CONTEXT_STRIDE
+}
+fn claim(ctx: usize) -> usize {
+    threshold(ctx) + 4
+}
+// Lines are sampled when devices tick, which is every few hundred instructions
+fn settle(vm: &mut VM) {
+    assert_eq!(vm.run_for(1024), None);
+}
+fn setup() -> VM {
+    // nop; j -4, a loop that doesn't count as idle
+    let image: Vec<u8> = [0x00000013u32, 0xffdff06f].iter().flat_map(|x| x.to_le_bytes()).collect();
+    let mut vm = Builder::new(Machine::Virt).load(0x8000_0000, &image).build().unwrap();
+    vm.write(UART + uart::MCR, &[MCR_LOOP]).unwrap();
+    vm.write(UART + uart::IER, &[IER_RDI]).unwrap();
+    vm.write(UART + uart::THR, b"x").unwrap();
+    settle(&mut vm);
+    vm
+}
+
+#[test]
+fn masking() {
+    let mut vm = setup();
+    // Raised but nothing routes it anywhere yet
+    assert_eq!(read(&mut vm, PLIC + plic::PENDING), 1 << LINE);
+    assert_eq!(vm.mip() & (irq::MEI | irq::SEI), 0);
+    write(&mut vm, PLIC + plic::ENABLE + M*plic::ENABLE_STRIDE, 1 << LINE);
+    // Priority 0 never interrupts
+    assert_eq!(vm.mip() & irq::MEI, 0);
+    assert_eq!(read(&mut vm, claim(M)), 0);
+    write(&mut vm, PLIC + plic::PRIORITY + LINE*4, 3);
+    assert_eq!(vm.mip() & (irq::MEI | irq::SEI), irq::MEI);
+    // Only a priority above the threshold gets through
+    write(&mut vm, threshold(M), 3);
+    assert_eq!(vm.mip() & irq::MEI, 0);
+    assert_eq!(read(&mut vm, claim(M)), 0);
+    write(&mut vm, threshold(M), 2);
+    assert_eq!(vm.mip() & irq::MEI, irq::MEI);
+    // Each context has its own enables
+    write(&mut vm, PLIC + plic::ENABLE + S*plic::ENABLE_STRIDE, 1 << LINE);
+    assert_eq!(vm.mip() & (irq::MEI | irq::SEI), irq::MEI | irq::SEI);
+}
+
+#[test]
+fn claim_and_complete() {
+    let mut vm = setup();
+    write(&mut vm, PLIC + plic::PRIORITY + LINE*4, 1);
+    write(&mut vm, PLIC + plic::ENABLE + M*plic::ENABLE_STRIDE, 1 << LINE);
+    assert_eq!(read(&mut vm, claim(M)), LINE as u32);
+    // Claiming clears pending and the gateway holds the line back until completion
+    assert_eq!(read(&mut vm, PLIC + plic::PENDING), 0);
+    assert_eq!(vm.mip() & irq::MEI, 0);
+    settle(&mut vm);
+    assert_eq!(read(&mut vm, PLIC + plic::PENDING), 0);
+    assert_eq!(read(&mut vm, claim(M)), 0);
+    // The byte is still there, so completing re-arms the level triggered line
+    write(&mut vm, claim(M), LINE as u32);
+    assert_eq!(read(&mut vm, PLIC + plic::PENDING), 1 << LINE);
+    assert_eq!(read(&mut vm, claim(M)), LINE as u32);
+    // Once the FIFO is drained the line drops and completing leaves it idle
+    let mut byte = [0];
+    vm.read(UART + uart::RBR, &mut byte).unwrap();
+    assert_eq!(&byte, b"x");
+    settle(&mut vm);
+    write(&mut vm, claim(M), LINE as u32);
+    assert_eq!(read(&mut vm, PLIC + plic::PENDING), 0);
+    assert_eq!(vm.mip() & irq::MEI, 0);
+}
+
+// A peripheral from outside the crate with its output on line DOORBELL_LINE, high while its register is 1
+const DOORBELL: usize = 0x4000_0000;
+const DOORBELL_LINE: usize = 5;
+struct Doorbell;
+impl Device for Doorbell {
+    fn read(&mut self, _: &mut VM, _: usize, bytes: &mut [u8]) -> Result<(), ()> {
+        bytes.fill(0);
+        Ok(())
+    }
+    fn write(&mut self, vm: &mut VM, _: usize, bytes: &[u8]) -> Result<(), ()> {
+        vm.set_line(PLIC, DOORBELL_LINE, bytes[0] & 1 != 0);
+        Ok(())
+    }
+}
+
+#[test]
+fn external_line() {
+    let mut vm = Builder::new(Machine::Virt)
+        .region(Region { device: Box::new(Doorbell), addr: DOORBELL, size: 4, latency: 0 })
+        .build()
+        .unwrap();
+    write(&mut vm, PLIC + plic::PRIORITY + DOORBELL_LINE*4, 1);
+    write(&mut vm, PLIC + plic::ENABLE + M*plic::ENABLE_STRIDE, 1 << DOORBELL_LINE);
+    assert_eq!(vm.mip() & irq::MEI, 0);
+    vm.write(DOORBELL, &[1]).unwrap();
+    assert_eq!(read(&mut vm, PLIC + plic::PENDING), 1 << DOORBELL_LINE);
+    assert_eq!(vm.mip() & irq::MEI, irq::MEI);
+    assert_eq!(read(&mut vm, claim(M)), DOORBELL_LINE as u32);
+    vm.write(DOORBELL, &[0]).unwrap();
+    write(&mut vm, claim(M), DOORBELL_LINE as u32);
+    assert_eq!(read(&mut vm, PLIC + plic::PENDING), 0);
+    assert_eq!(vm.mip() & irq::MEI, 0);
+}
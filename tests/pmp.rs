@@ -0,0 +1,92 @@
+use riscv_vm::{Builder, Halt, Machine, VM};
+
+// NOTE: PMP as a guest programs it. M-mode code at 0 sets the entries up and drops to
+// U-mode at 0x200, where the code under test runs and ECALLs once it's done.
+// Every trap lands in the handler at 0x100, which exits with mcause, mtval left in a1.
+const HANDLER: usize = 0x100;
+const USER: usize = 0x200;
+const ECALL: u8 = 8;
+const FETCH_FAULT: u8 = 1;
+const LOAD_FAULT: u8 = 5;
+const STORE_FAULT: u8 = 7;
+
+// li t0, 0x100; csrw mtvec, t0
+const PROLOGUE: [u32; 2] = [0x10000293, 0x30529073];
+// li t0, 0x1800; csrc mstatus, t0; li t0, 0x200; csrw mepc, t0; mret
+const TO_USER: [u32; 6] = [0x000022b7, 0x80028293, 0x3002b073, 0x20000293, 0x34129073, 0x30200073];
+// csrr a0, mcause; csrr a1, mtval; lui t1, 0x7; sb a0, 0(t1)
+const TRAP: [u32; 4] = [0x34202573, 0x343025f3, 0x00007337, 0x00a30023];
+
+// Entry 0 is TOR over [0, 0x1000) with R and X, the code included
+// li t0, 0x400; csrw pmpaddr0, t0; li t0, 0x0d; csrw pmpcfg0, t0
+const TOR: [u32; 4] = [0x40000293, 0x3b029073, 0x00d00293, 0x3a029073];
+// The same plus entry 1, NAPOT over [0x2000, 0x3000) with R and W
+// li t0, 0x400; csrw pmpaddr0, t0; li t0, 0x9ff; csrw pmpaddr1, t0; li t0, 0x1b0d; csrw pmpcfg0, t0
+const NAPOT: [u32; 8] = [0x40000293, 0x3b029073, 0x000012b7, 0x9ff28293, 0x3b129073, 0x000022b7, 0xb0d28293, 0x3a029073];
+
+fn words(code: &[u32]) -> Vec<u8> {
+    code.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+fn run(machine: &[u32], user: &[u32]) -> (Option<Halt>, VM) {
+    let machine = [&PROLOGUE[..], machine, &TO_USER].concat();
+    let user = [user, &[0x00000073]].concat();
+    let mut vm = Builder::new(Machine::Simple)
+        .load(0, &words(&machine))
+        .load(HANDLER, &words(&TRAP))
+        .load(USER, &words(&user))
+        .build()
+        .unwrap();
+    (vm.run_for(200), vm)
+}
+fn mtval(vm: &VM) -> u32 {
+    vm.get_reg(11) as u32
+}
+
+#[test]
+fn tor() {
+    // lw a2, 0x7fc(zero)
+    let (halt, _) = run(&TOR, &[0x7fc02603]);
+    assert_eq!(halt, Some(Halt::Exit(ECALL)));
+    // sw zero, 0x7fc(zero), the entry has no W
+    let (halt, vm) = run(&TOR, &[0x7e002e23]);
+    assert_eq!(halt, Some(Halt::Exit(STORE_FAULT)));
+    assert_eq!(mtval(&vm), 0x7fc);
+    // lui t0, 0x1; lw a2, 0(t0), right at the top, which nothing matches
+    let (halt, vm) = run(&TOR, &[0x000012b7, 0x0002a603]);
+    assert_eq!(halt, Some(Halt::Exit(LOAD_FAULT)));
+    assert_eq!(mtval(&vm), 0x1000);
+}
+
+#[test]
+fn napot() {
+    // lui t0, 0x3; sw zero, -4(t0), the last word in the range
+    let (halt, _) = run(&NAPOT, &[0x000032b7, 0xfe02ae23]);
+    assert_eq!(halt, Some(Halt::Exit(ECALL)));
+    // lui t0, 0x3; lw a2, 0(t0), one past it
+    let (halt, vm) = run(&NAPOT, &[0x000032b7, 0x0002a603]);
+    assert_eq!(halt, Some(Halt::Exit(LOAD_FAULT)));
+    assert_eq!(mtval(&vm), 0x3000);
+    // lui t0, 0x2; jalr t0, the range isn't executable
+    let (halt, vm) = run(&NAPOT, &[0x000022b7, 0x000280e7]);
+    assert_eq!(halt, Some(Halt::Exit(FETCH_FAULT)));
+    assert_eq!(mtval(&vm), 0x2000);
+}
+
+#[test]
+fn lock() {
+    // Entry 0 locked NAPOT over [0x2000, 0x3000) with only R, which holds for M-mode too.
+    // Neither the W nor the new address written afterwards sticks, and the store never gets to U-mode.
+    //  li t0, 0x9ff; csrw pmpaddr0, t0; li t0, 0x99; csrw pmpcfg0, t0
+    //  li t0, 0x9b; csrw pmpcfg0, t0; li t0, 0x7ff; csrw pmpaddr0, t0
+    //  csrr a3, pmpcfg0; csrr a4, pmpaddr0; lui t0, 0x2; lw a2, 0(t0); sw zero, 0(t0)
+    let code = [
+        0x000012b7, 0x9ff28293, 0x3b029073, 0x09900293, 0x3a029073,
+        0x09b00293, 0x3a029073, 0x7ff00293, 0x3b029073,
+        0x3a0026f3, 0x3b002773, 0x000022b7, 0x0002a603, 0x0002a023,
+    ];
+    let (halt, vm) = run(&code, &[]);
+    assert_eq!(halt, Some(Halt::Exit(STORE_FAULT)));
+    assert_eq!(mtval(&vm), 0x2000);
+    assert_eq!(vm.get_reg(13), 0x99);
+    assert_eq!(vm.get_reg(14), 0x9ff);
+}
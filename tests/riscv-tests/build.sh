@@ -0,0 +1,24 @@
+#!/bin/sh
+# Rebuilds the test binaries in isa/ from their sources, e.g. isa/rv32ui/add.S
+# becomes isa/rv32ui-p-add. Needs a C preprocessor, llvm-mc and python3.
+# For the official binaries see upstream.sh.
+set -e
+cd "$(dirname "$0")"
+MC=${MC:-llvm-mc}
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+for src in isa/rv32*/*.S; do
+    suite=$(basename "$(dirname "$src")")
+    test=$(basename "$src" .S)
+    # Only the extensions a suite is about, so nothing else sneaks in
+    case $suite in
+        rv32ui) attr=-c ;;
+        rv32um) attr=+m,-c ;;
+        rv32ua) attr=+a,-c ;;
+        rv32uc) attr=+c ;;
+        rv32mi) attr=+a,-c ;;
+    esac
+    cpp -x assembler-with-cpp -P -nostdinc -Ienv -Iisa/macros/scalar "$src" > "$tmp/$test.s"
+    $MC -triple=riscv32 -mattr=$attr,-relax -filetype=obj "$tmp/$test.s" -o "$tmp/$test.o"
+    python3 link.py "$tmp/$test.o" "isa/$suite-p-$test"
+done
@@ -0,0 +1,38 @@
+// The subset of riscv-opcodes' encoding.h the tests use
+#ifndef RISCV_CSR_ENCODING_H
+#define RISCV_CSR_ENCODING_H
+
+#define MSTATUS_SIE         0x00000002
+#define MSTATUS_MIE         0x00000008
+#define MSTATUS_SPIE        0x00000020
+#define MSTATUS_MPIE        0x00000080
+#define MSTATUS_SPP         0x00000100
+#define MSTATUS_MPP         0x00001800
+#define MSTATUS_MPRV        0x00020000
+
+#define SSTATUS_SPP         MSTATUS_SPP
+
+#define SIP_SSIP            0x00000002
+#define SIP_STIP            0x00000020
+
+#define PMP_R               0x01
+#define PMP_W               0x02
+#define PMP_X               0x04
+#define PMP_NAPOT           0x18
+
+#define CAUSE_MISALIGNED_FETCH    0x0
+#define CAUSE_FETCH_ACCESS        0x1
+#define CAUSE_ILLEGAL_INSTRUCTION 0x2
+#define CAUSE_BREAKPOINT          0x3
+#define CAUSE_MISALIGNED_LOAD     0x4
+#define CAUSE_LOAD_ACCESS         0x5
+#define CAUSE_MISALIGNED_STORE    0x6
+#define CAUSE_STORE_ACCESS        0x7
+#define CAUSE_USER_ECALL          0x8
+#define CAUSE_SUPERVISOR_ECALL    0x9
+#define CAUSE_MACHINE_ECALL       0xb
+#define CAUSE_FETCH_PAGE_FAULT    0xc
+#define CAUSE_LOAD_PAGE_FAULT     0xd
+#define CAUSE_STORE_PAGE_FAULT    0xf
+
+#endif
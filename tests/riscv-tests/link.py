@@ -0,0 +1,127 @@
+#!/usr/bin/env python3
+# Links a single relocatable RV32 object into an executable, for when no
+# RISC-V ld is around. Allocated sections are laid out in order from the base
+# address with the relocations the assembler emits applied, and everything else
+# (the symbol table in particular) is carried over so symbols like tohost and
+# begin_signature can still be looked up.
+#
+#   link.py <in.o> <out> [base]
+import struct, sys
+
+src, dst = sys.argv[1], sys.argv[2]
+base = int(sys.argv[3], 0) if len(sys.argv) > 3 else 0x80000000
+d = bytearray(open(src, 'rb').read())
+shoff, = struct.unpack_from('<I', d, 32)
+shnum, shstrndx = struct.unpack_from('<HH', d, 48)
+# name, type, flags, addr, offset, size, link, info, align, entsize
+secs = [list(struct.unpack_from('<10I', d, shoff + i*40)) for i in range(shnum)]
+def name(off):
+    s = secs[shstrndx][4] + off
+    return d[s:d.index(b'\0', s)].decode()
+SHF_ALLOC, SHT_NOBITS, SHT_SYMTAB, SHT_RELA = 2, 8, 2, 4
+
+# Code first so the entry point is at the base
+order = sorted((i for i, s in enumerate(secs) if s[2] & SHF_ALLOC),
+               key=lambda i: (not secs[i][2] & 4, not name(secs[i][0]).startswith('.text.init')))
+addr = base
+for i in order:
+    align = max(secs[i][8], 1)
+    addr = (addr + align - 1) // align * align
+    secs[i][3] = addr
+    addr += secs[i][5]
+image = bytearray(addr - base)
+for i in order:
+    if secs[i][1] != SHT_NOBITS:
+        image[secs[i][3]-base:secs[i][3]-base+secs[i][5]] = d[secs[i][4]:secs[i][4]+secs[i][5]]
+
+symtab = next(s for s in secs if s[1] == SHT_SYMTAB)
+def sym(n):
+    value, size, info, other, shndx = struct.unpack_from('<IIBBH', d, symtab[4] + n*16 + 4)
+    # Undefined weak symbols, like a handler the test doesn't have, are zero
+    if shndx == 0 and info >> 4 != 2:
+        sys.exit('undefined symbol %d' % n)
+    return value + (secs[shndx][3] if 0 < shndx < 0xff00 else 0)
+
+def get(at, n=4):
+    return int.from_bytes(image[at-base:at-base+n], 'little')
+def put(at, v, n=4):
+    image[at-base:at-base+n] = (v & ((1 << 8*n) - 1)).to_bytes(n, 'little')
+def bits(v, hi, lo):
+    return (v >> lo) & ((1 << (hi - lo + 1)) - 1)
+def itype(at, v):
+    put(at, get(at) & 0x000FFFFF | (v & 0xFFF) << 20)
+def stype(at, v):
+    put(at, get(at) & 0x01FFF07F | bits(v, 11, 5) << 25 | bits(v, 4, 0) << 7)
+def utype(at, v):
+    put(at, get(at) & 0xFFF | ((v + 0x800) >> 12 << 12))
+
+relocs = []
+for s in secs:
+    if s[1] == SHT_RELA and secs[s[7]][2] & SHF_ALLOC:
+        for o in range(s[4], s[4] + s[5], 12):
+            off, info, addend = struct.unpack_from('<IIi', d, o)
+            relocs.append((secs[s[7]][3] + off, info & 0xFF, info >> 8, addend))
+# %pcrel_lo points at the AUIPC, whose %pcrel_hi has the real target
+hi20 = {at: sym(n) + a - at for at, t, n, a in relocs if t == 23}
+for at, t, n, a in relocs:
+    s = sym(n) + a
+    if t == 1:
+        put(at, s)
+    elif t == 16:
+        v = s - at
+        put(at, get(at) & 0x01FFF07F | bits(v, 12, 12) << 31 | bits(v, 10, 5) << 25 | bits(v, 4, 1) << 8 | bits(v, 11, 11) << 7)
+    elif t == 17:
+        v = s - at
+        put(at, get(at) & 0xFFF | bits(v, 20, 20) << 31 | bits(v, 10, 1) << 21 | bits(v, 11, 11) << 20 | bits(v, 19, 12) << 12)
+    elif t in (18, 19):
+        utype(at, s - at)
+        itype(at + 4, s - at)
+    elif t == 23:
+        utype(at, s - at)
+    elif t in (24, 25):
+        (itype if t == 24 else stype)(at, hi20[sym(n)])
+    elif t == 26:
+        utype(at, s)
+    elif t == 27:
+        itype(at, s)
+    elif t == 28:
+        stype(at, s)
+    elif t == 44:
+        v = s - at
+        put(at, get(at, 2) & 0xE383 | bits(v, 8, 8) << 12 | bits(v, 4, 3) << 10 | bits(v, 7, 6) << 5 | bits(v, 2, 1) << 3 | bits(v, 5, 5) << 2, 2)
+    elif t == 45:
+        v = s - at
+        put(at, get(at, 2) & 0xE003 | bits(v, 11, 11) << 12 | bits(v, 4, 4) << 11 | bits(v, 9, 8) << 9 | bits(v, 10, 10) << 8
+            | bits(v, 6, 6) << 7 | bits(v, 7, 7) << 6 | bits(v, 3, 1) << 3 | bits(v, 5, 5) << 2, 2)
+    elif t != 51:
+        sys.exit('unhandled relocation type %d' % t)
+
+# Symbols become absolute addresses
+for o in range(symtab[4], symtab[4] + symtab[5], 16):
+    value, size, info, other, shndx = struct.unpack_from('<IIBBH', d, o + 4)
+    if 0 < shndx < 0xff00 and secs[shndx][2] & SHF_ALLOC:
+        struct.pack_into('<I', d, o + 4, value + secs[shndx][3])
+
+out = bytearray(52 + 32)
+text_off = len(out)
+out += image
+for i, s in enumerate(secs):
+    if i == 0:
+        continue
+    if s[2] & SHF_ALLOC:
+        s[4] = text_off + s[3] - base
+    elif s[1] != SHT_RELA:
+        off = len(out)
+        out += d[s[4]:s[4]+s[5]]
+        s[4] = off
+while len(out) % 4:
+    out.append(0)
+sh = len(out)
+for s in secs:
+    if s[1] == SHT_RELA:
+        s = [s[0]] + [0] * 9
+    out += struct.pack('<10I', *s)
+struct.pack_into('<4sBBBB8sHHIIIIIHHHHHH', out, 0, b'\x7fELF', 1, 1, 1, 0, b'\0'*8, 2, 243, 1,
+                 base, 52, sh, 0, 52, 32, 1, 40, len(secs), shstrndx)
+struct.pack_into('<8I', out, 52, 1, text_off, base, base, len(image), len(image), 7, 0x1000)
+open(dst, 'wb').write(out)
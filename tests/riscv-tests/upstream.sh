@@ -0,0 +1,24 @@
+#!/bin/sh
+# Builds the official riscv-tests at <commit> with their own makefile and env, and vendors the
+# rv32 "p" binaries into upstream/ next to this script with the commit they came from in
+# upstream/COMMIT. The harness runs those by default once they're there:
+#     ./upstream.sh <commit> [checkout dir]
+# Needs git, autoconf and a riscv64-unknown-elf toolchain on the PATH.
+set -e
+[ -n "$1" ] || { echo "usage: $0 <commit> [checkout dir]" >&2; exit 1; }
+out=$(cd "$(dirname "$0")" && pwd)/upstream
+dir=${2:-$(mktemp -d)/riscv-tests}
+[ -d "$dir" ] || git clone https://github.com/riscv-software-src/riscv-tests "$dir"
+cd "$dir"
+git checkout -q "$1"
+git submodule update --init --recursive
+autoconf
+./configure --with-xlen=32
+make -C isa XLEN=32 rv32ui rv32um rv32ua rv32uc rv32uf rv32mi
+rm -rf "$out"
+mkdir -p "$out"
+for suite in rv32ui rv32um rv32ua rv32uc rv32uf rv32mi; do
+    find isa -maxdepth 1 -name "$suite-p-*" ! -name '*.dump' -exec cp {} "$out" \;
+done
+cp LICENSE "$out"
+git rev-parse HEAD > "$out/COMMIT"
@@ -0,0 +1,146 @@
+use std::{env, fs, path::{Path, PathBuf}, process::{Command, Stdio}, thread, time::{Duration, Instant}};
+use riscv_vm::{Builder, Halt, Machine};
+
+// NOTE: Runs the riscv-tests binaries on the virt machine, the way their makefile runs them on
+// spike. They report through tohost: exit code 0 is a pass, anything else is the failing case
+// number, or 1337 mixed in for an unexpected trap.
+// The official binaries vendored in tests/riscv-tests/upstream are run when they're there, see
+// upstream.sh. RISCV_TESTS points the harness at another build instead.
+const TIMEOUT: Duration = Duration::from_secs(10);
+// The longest tests take a few thousand, the rest is for ones that went astray
+const MAX_STEPS: u64 = 10_000_000;
+
+fn isa_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/riscv-tests/isa")
+}
+fn upstream_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/riscv-tests/upstream")
+}
+// RISCV_TESTS, then the vendored upstream build, then the regression builds in isa/
+fn suite_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("RISCV_TESTS") {
+        return PathBuf::from(dir);
+    }
+    match fs::read_to_string(upstream_dir().join("COMMIT")) {
+        Ok(commit) => {
+            println!("riscv-tests {}", commit.trim());
+            upstream_dir()
+        }
+        Err(_) => {
+            println!("WARN: No upstream riscv-tests in {}, running the regression builds in {}", upstream_dir().display(), isa_dir().display());
+            isa_dir()
+        }
+    }
+}
+
+fn run(path: &Path) -> Result<(), String> {
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+    let mut vm = Builder::new(Machine::Virt).image(&data)?.build()?;
+    match vm.run_for(MAX_STEPS) {
+        Some(Halt::Exit(0)) => Ok(()),
+        Some(Halt::Exit(code)) => Err(format!("exit code {}", code)),
+        Some(Halt::Idle) => Err(format!("hung at ip=0x{:08X}", vm.ip)),
+        None => Err(format!("still running after {} steps", MAX_STEPS)),
+    }
+}
+
+// Runs the binary itself, for what only the command line does
+fn cli(path: &Path, extra: &[&str]) -> Result<(), String> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_riscv_vm"))
+        .arg("-mvirt")
+        .args(extra)
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("failed to start: {}", e))?;
+    let start = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return match status.code() {
+                Some(0) => Ok(()),
+                Some(code) => Err(format!("exit code {}", code)),
+                None => Err("killed by a signal".to_string()),
+            },
+            Ok(None) if start.elapsed() > TIMEOUT => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err("timed out".to_string());
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(5)),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+}
+
+// Runs every binary of the suite, physical and virtual memory environments alike
+fn suite(name: &str) {
+    let dir = suite_dir();
+    let mut tests: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("{}: {}", dir.display(), e))
+        .map(|x| x.unwrap().path())
+        .filter(|x| {
+            let file = x.file_name().unwrap().to_string_lossy();
+            // Skips the .dump files upstream builds come with
+            x.extension().is_none() && [format!("{}-p-", name), format!("{}-v-", name)].iter().any(|p| file.starts_with(p))
+        })
+        .collect();
+    tests.sort();
+    assert!(!tests.is_empty(), "No {} tests in {}", name, dir.display());
+    let mut failed = Vec::new();
+    for test in tests.iter() {
+        let file = test.file_name().unwrap().to_string_lossy();
+        match run(test) {
+            Ok(()) => println!("PASS {}", file),
+            Err(e) => {
+                println!("FAIL {}: {}", file, e);
+                failed.push(file.into_owned());
+            }
+        }
+    }
+    println!("{}: {} passed, {} failed", name, tests.len() - failed.len(), failed.len());
+    assert!(failed.is_empty(), "Failed: {}", failed.join(", "));
+}
+
+#[test]
+fn rv32ui() { suite("rv32ui"); }
+#[test]
+fn rv32um() { suite("rv32um"); }
+#[test]
+fn rv32ua() { suite("rv32ua"); }
+#[test]
+fn rv32uc() { suite("rv32uc"); }
+#[test]
+fn rv32mi() { suite("rv32mi"); }
+// Every rv32uf binary would stop at its first FP instruction with an illegal instruction trap
+#[test]
+#[ignore = "F extension not implemented"]
+fn rv32uf() { suite("rv32uf"); }
+
+// -signature dumps memory the way RISCOF expects it from a model.
+// The expected words were worked out by hand from the stores in isa/rv32ui/sw.S, not taken from
+// this or any other model: tdat1..tdat10 after every case ran, then padding up to end_signature.
+const SW_SIGNATURE: [u32; 12] = [
+    0x12345678, // tdat1, case 2 then the little endian check in case 12
+    0xaa00aa00, // tdat2, case 3
+    0x0aa00aa0, // tdat3, case 4
+    0x00aa00aa, // tdat4, case 5 then case 6 at tdat8-16
+    0xaa00aa00, // tdat5, case 7
+    0x0aa00aa0, // tdat6, case 8
+    0xa00aa00a, // tdat7, case 9
+    0xdeadbeef, // tdat8, never stored to
+    0x12345678, // tdat9, case 10 through a negative base
+    0x58213098, // tdat10, case 11 through an unaligned base
+    0, 0,       // .align 4 before end_signature
+];
+#[test]
+fn signature() {
+    let out = env::temp_dir().join(format!("riscv_vm-{}.signature", std::process::id()));
+    let res = cli(&isa_dir().join("rv32ui-p-sw"), &["-signature", out.to_str().unwrap()]);
+    let signature = fs::read_to_string(&out);
+    let _ = fs::remove_file(&out);
+    res.unwrap();
+    let expected: String = SW_SIGNATURE.iter().map(|x| format!("{:08x}\n", x)).collect();
+    assert_eq!(signature.unwrap(), expected);
+}
@@ -0,0 +1,52 @@
+use std::{env, fs};
+use riscv_vm::{Builder, Halt, Machine};
+
+// NOTE: Semihosting file calls as a guest on the simple machine makes them.
+// The test puts "RV32" at 0x7F0, the file name's length at 0x7FC and the name at 0x800,
+// the guest builds its parameter blocks at 0x900 and reads into 0xA00.
+const DATA: usize = 0x7F0;
+
+// Each call is a0 = operation, a1 = s0 = parameter block, then slli x0, x0, 0x1f; ebreak; srai x0, x0, 7
+//  s1 = SYS_OPEN(name, 4 "w", len)
+//  s2 = SYS_WRITE(s1, 0x7f0, 4), the bytes left unwritten
+//  SYS_CLOSE(s1)
+//  s3 = SYS_OPEN(name, 0 "r", len)
+//  s4 = SYS_READ(s3, 0xa00, 8), the bytes left unread, s5 = what it read
+//  SYS_EXIT(ADP_Stopped_ApplicationExit)
+const CODE: [u32; 65] = [
+    0x00001437, 0x90040413, 0x000012b7, 0x80028293, 0x00542023, 0x00400293, 0x00542223, 0x7fc02283,
+    0x00542423, 0x00100513, 0x00040593, 0x01f01013, 0x00100073, 0x40705013, 0x00050493, 0x00942023,
+    0x7f000293, 0x00542223, 0x00400293, 0x00542423, 0x00500513, 0x00040593, 0x01f01013, 0x00100073,
+    0x40705013, 0x00050913, 0x00942023, 0x00200513, 0x00040593, 0x01f01013, 0x00100073, 0x40705013,
+    0x000012b7, 0x80028293, 0x00542023, 0x00042223, 0x7fc02283, 0x00542423, 0x00100513, 0x00040593,
+    0x01f01013, 0x00100073, 0x40705013, 0x00050993, 0x01342023, 0x000012b7, 0xa0028293, 0x00542223,
+    0x00800293, 0x00542423, 0x00600513, 0x00040593, 0x01f01013, 0x00100073, 0x40705013, 0x00050a13,
+    0x000012b7, 0xa0028293, 0x0002aa83, 0x01800513, 0x000205b7, 0x02658593, 0x01f01013, 0x00100073,
+    0x40705013,
+];
+
+#[test]
+fn open_read_write() {
+    let path = env::temp_dir().join(format!("riscv_vm_semihost_{}", std::process::id()));
+    let name = path.to_str().unwrap().as_bytes();
+    let data = [b"RV32", &[0; 8][..], &(name.len() as u32).to_le_bytes(), name, &[0]].concat();
+    let code: Vec<u8> = CODE.iter().flat_map(|x| x.to_le_bytes()).collect();
+    let mut vm = Builder::new(Machine::Simple)
+        .semihost()
+        .load(0, &code)
+        .load(DATA, &data)
+        .build()
+        .unwrap();
+    let halt = vm.run_for(1000);
+    let written = fs::read(&path);
+    let _ = fs::remove_file(&path);
+    assert_eq!(halt, Some(Halt::Exit(0)));
+
+    assert_ne!(vm.get_reg(9), -1);
+    assert_eq!(vm.get_reg(18), 0);
+    assert_eq!(written.unwrap(), b"RV32");
+    assert_ne!(vm.get_reg(19), -1);
+    // Only 4 of the 8 bytes asked for were there
+    assert_eq!(vm.get_reg(20), 4);
+    assert_eq!(vm.get_reg(21).to_le_bytes(), *b"RV32");
+}
@@ -0,0 +1,44 @@
+use std::{env, fs, io::Write, process::{Command, Stdio}};
+
+// NOTE: Host calls that read stdin, fed through a pipe. Each program glances at the UART's
+// LSR first, which is what used to start the UART's reader thread and starve the call.
+
+// Writes the program out for the command line to load, then runs it with `input` on stdin
+fn run(name: &str, code: &[u32], flag: &str, input: &[u8]) -> Option<i32> {
+    let path = env::temp_dir().join(format!("riscv_vm_stdin_{}_{}.bin", name, std::process::id()));
+    fs::write(&path, code.iter().flat_map(|x| x.to_le_bytes()).collect::<Vec<u8>>()).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_riscv_vm"))
+        .args(["-mvirt", flag])
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let status = child.wait().unwrap();
+    let _ = fs::remove_file(&path);
+    status.code()
+}
+
+#[test]
+fn newlib_read() {
+    // lui t0, 0x10000; lbu t1, 5(t0); then read(0, 0x80001000, 1) and exit with the byte read
+    let code = [0x100002b7, 0x0052c303, 0x800015b7, 0x00000513, 0x00100613, 0x03f00893, 0x00000073, 0x0005c503, 0x05d00893, 0x00000073];
+    assert_eq!(run("newlib", &code, "-newlib", b"A"), Some(b'A' as i32));
+}
+
+#[test]
+fn semihost_read() {
+    // lui t0, 0x10000; lbu t1, 5(t0); then with parameter blocks at 0x80001000:
+    // SYS_OPEN(":tt", 0, 3), SYS_READ(h, 0x80001200, 1), SYS_READC and
+    // SYS_EXIT_EXTENDED(ApplicationExit, the sum of the two bytes)
+    let code = [
+        0x100002b7, 0x0052c303, 0x80001437, 0x10040293, 0x00747337, 0x43a30313, 0x0062a023, 0x00542023,
+        0x00042223, 0x00300313, 0x00642423, 0x00100513, 0x00040593, 0x01f01013, 0x00100073, 0x40705013,
+        0x00050493, 0x20040293, 0x00942023, 0x00542223, 0x00100313, 0x00642423, 0x00600513, 0x00040593,
+        0x01f01013, 0x00100073, 0x40705013, 0x20044903, 0x00700513, 0x00000593, 0x01f01013, 0x00100073,
+        0x40705013, 0x00a90933, 0x00020337, 0x02630313, 0x00642023, 0x01242223, 0x02000513, 0x00040593,
+        0x01f01013, 0x00100073, 0x40705013,
+    ];
+    assert_eq!(run("semihost", &code, "-semihost", b"AB"), Some((b'A' + b'B') as i32));
+}